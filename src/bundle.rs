@@ -0,0 +1,140 @@
+//! Parsing of bundle output into key/value maps.
+
+use std::collections::BTreeMap;
+
+use crate::config::BundleFormat;
+
+/// Split a bundle's raw output into its keys and values.
+pub fn parse(format: BundleFormat, output: &str) -> Result<BTreeMap<String, String>, String> {
+    match format {
+        BundleFormat::Json => parse_json(output),
+        BundleFormat::Dotenv => parse_dotenv(output),
+    }
+}
+
+fn parse_json(output: &str) -> Result<BTreeMap<String, String>, String> {
+    let value: serde_json::Value =
+        serde_json::from_str(output).map_err(|e| format!("invalid JSON: {e}"))?;
+    let serde_json::Value::Object(object) = value else {
+        return Err("expected a JSON object at the top level".to_owned());
+    };
+    Ok(object
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                serde_json::Value::String(s) => s,
+                serde_json::Value::Null => String::new(),
+                other => other.to_string(),
+            };
+            (key, value)
+        })
+        .collect())
+}
+
+/// Parse `KEY=VALUE` lines. Blank lines and `#` comments are ignored, an
+/// `export ` prefix is allowed, and values may be single-quoted (verbatim) or
+/// double-quoted (with `\n`, `\"`, `\\`, and `\$` escapes).
+fn parse_dotenv(output: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut values = BTreeMap::new();
+    for (index, line) in output.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("line {}: expected KEY=VALUE", index + 1));
+        };
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(format!("line {}: empty key", index + 1));
+        }
+        let value = unquote(value.trim()).map_err(|e| format!("line {}: {e}", index + 1))?;
+        values.insert(key.to_owned(), value);
+    }
+    Ok(values)
+}
+
+fn unquote(value: &str) -> Result<String, String> {
+    if let Some(inner) = value.strip_prefix('\'') {
+        return inner
+            .strip_suffix('\'')
+            .map(ToOwned::to_owned)
+            .ok_or_else(|| "unterminated single quote".to_owned());
+    }
+    let Some(inner) = value.strip_prefix('"') else {
+        return Ok(value.to_owned());
+    };
+    let inner = inner
+        .strip_suffix('"')
+        .ok_or_else(|| "unterminated double quote".to_owned())?;
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some(c @ ('"' | '\\' | '$')) => out.push(c),
+            Some(c) => {
+                out.push('\\');
+                out.push(c);
+            }
+            None => out.push('\\'),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_object() {
+        let parsed = parse(
+            BundleFormat::Json,
+            r#"{"user": "app", "port": 5432, "tls": true, "extra": null, "opts": {"a": 1}}"#,
+        )
+        .unwrap();
+        assert_eq!(parsed["user"], "app");
+        assert_eq!(parsed["port"], "5432");
+        assert_eq!(parsed["tls"], "true");
+        assert_eq!(parsed["extra"], "");
+        assert_eq!(parsed["opts"], r#"{"a":1}"#);
+    }
+
+    #[test]
+    fn json_rejects_non_objects() {
+        assert!(parse(BundleFormat::Json, "[1, 2]").is_err());
+        assert!(parse(BundleFormat::Json, "not json").is_err());
+    }
+
+    #[test]
+    fn dotenv_lines() {
+        let output = "\
+# comment
+USER=app
+
+export HOST = db.internal
+SINGLE='a \"b\" $c'
+DOUBLE=\"line1\\nline2 \\\"q\\\" \\$HOME\"
+EMPTY=
+";
+        let parsed = parse(BundleFormat::Dotenv, output).unwrap();
+        assert_eq!(parsed["USER"], "app");
+        assert_eq!(parsed["HOST"], "db.internal");
+        assert_eq!(parsed["SINGLE"], "a \"b\" $c");
+        assert_eq!(parsed["DOUBLE"], "line1\nline2 \"q\" $HOME");
+        assert_eq!(parsed["EMPTY"], "");
+    }
+
+    #[test]
+    fn dotenv_errors_name_the_line() {
+        let err = parse(BundleFormat::Dotenv, "A=1\nnot a pair\n").unwrap_err();
+        assert!(err.starts_with("line 2:"), "{err}");
+        assert!(parse(BundleFormat::Dotenv, "A='open").is_err());
+    }
+}
@@ -0,0 +1,412 @@
+//! On-disk cache for `cmd:` / `sh:` results that opt in via `cache: { ttl }`.
+//!
+//! Each entry is a small JSON file named after the SHA-256 of everything that
+//! can influence the command's output: the config file, environment, variable,
+//! the exact source definition, the command line an `sh:` source runs (which
+//! `settings.shell` and `settings.strict` shape), and the values of any
+//! `needs`. Editing the source or those settings therefore invalidates its
+//! entry automatically. Files are created
+//! owner-only (`0600` in a `0700` directory) because cached values are often
+//! secrets.
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use sha2::Digest;
+use sha2::Sha256;
+use tracing::debug;
+use tracing::warn;
+
+use crate::config::Settings;
+use crate::config::Source;
+
+/// Handle to the cache directory, scoped to one config file.
+pub struct Cache {
+    dir: PathBuf,
+    config_file: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Entry {
+    /// When the value was stored.
+    created: SystemTime,
+    value: String,
+}
+
+/// Everything hashed into an entry's key.
+#[derive(serde::Serialize)]
+struct Key<'a> {
+    config_file: &'a str,
+    environment: &'a str,
+    variable: &'a str,
+    source: &'a Source,
+    /// Full command line of an `sh:` source, interpreter included.
+    argv: Option<Vec<String>>,
+    needs: Vec<(&'a str, &'a str)>,
+}
+
+impl Cache {
+    /// Default per-user cache directory (e.g. `~/.cache/envoke` on Linux).
+    #[must_use]
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::cache_dir().map(|d| d.join("envoke"))
+    }
+
+    /// Open the cache in `dir` for entries belonging to `config_path`.
+    ///
+    /// The directory is created lazily on first write.
+    #[must_use]
+    pub fn new(dir: PathBuf, config_path: &Path) -> Self {
+        let config_file = fs::canonicalize(config_path)
+            .unwrap_or_else(|_| config_path.to_path_buf())
+            .display()
+            .to_string();
+        Self { dir, config_file }
+    }
+
+    /// Look up a fresh entry. Missing, expired, or unreadable entries are
+    /// treated as misses.
+    pub fn get(
+        &self,
+        environment: &str,
+        variable: &str,
+        source: &Source,
+        settings: &Settings,
+        needs: &[(&str, &str)],
+        ttl: Duration,
+    ) -> Option<String> {
+        let path = self.entry_path(environment, variable, source, settings, needs);
+        let entry: Entry = serde_json::from_slice(&fs::read(&path).ok()?).ok()?;
+        // An entry from the future (the clock went back) is not trusted.
+        match entry.created.elapsed() {
+            Ok(age) if age < ttl => {
+                debug!(variable, ?age, "cache hit");
+                Some(entry.value)
+            }
+            age => {
+                debug!(variable, ?age, "cache entry expired");
+                None
+            }
+        }
+    }
+
+    /// Store a value. Failures are logged and otherwise ignored — the cache
+    /// is an optimization, never a reason to fail resolution.
+    pub fn put(
+        &self,
+        environment: &str,
+        variable: &str,
+        source: &Source,
+        settings: &Settings,
+        needs: &[(&str, &str)],
+        value: &str,
+    ) {
+        let path = self.entry_path(environment, variable, source, settings, needs);
+        let entry = Entry {
+            created: SystemTime::now(),
+            value: value.to_owned(),
+        };
+        if let Err(e) = self.write_entry(&path, &entry) {
+            warn!(variable, error = %e, "failed to write cache entry");
+        }
+    }
+
+    /// Remove every file in the cache directory: entries, and temp files left
+    /// by an interrupted write. Returns the number of files deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns the I/O error if the directory cannot be listed or an entry
+    /// cannot be deleted. A missing directory is not an error.
+    pub fn clear(dir: &Path) -> io::Result<usize> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let mut removed = 0;
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn entry_path(
+        &self,
+        environment: &str,
+        variable: &str,
+        source: &Source,
+        settings: &Settings,
+        needs: &[(&str, &str)],
+    ) -> PathBuf {
+        let key = Key {
+            config_file: &self.config_file,
+            environment,
+            variable,
+            source,
+            argv: match source {
+                Source::Sh(sh) => Some(sh.argv(settings)),
+                _ => None,
+            },
+            needs: needs.to_vec(),
+        };
+        let json = serde_json::to_vec(&key).expect("cache key serializes");
+        let name = hex::encode(Sha256::digest(&json));
+        self.dir.join(format!("{name}.json"))
+    }
+
+    fn write_entry(&self, path: &Path, entry: &Entry) -> io::Result<()> {
+        create_private_dir(&self.dir)?;
+        // Write to a private temp file and rename, so readers never observe a
+        // partially written entry and the value is never world-readable.
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        let mut file = open_private(&tmp)?;
+        file.write_all(&serde_json::to_vec(entry).map_err(io::Error::other)?)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&tmp, path)
+    }
+}
+
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    use std::os::unix::fs::PermissionsExt;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    // `mode` only applies to directories created just now; tighten an
+    // existing one too.
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+#[cfg(unix)]
+fn open_private(path: &Path) -> io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn open_private(path: &Path) -> io::Result<fs::File> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ShSource;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("envoke-cache-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn source(script: &str) -> Source {
+        Source::Sh(ShSource::Script(script.to_owned()))
+    }
+
+    const HOUR: Duration = Duration::from_hours(1);
+
+    #[test]
+    fn round_trip_and_key_sensitivity() {
+        let dir = temp_dir("round-trip");
+        let cache = Cache::new(dir.clone(), Path::new("envoke.yaml"));
+        let src = source("echo a");
+        assert_eq!(
+            cache.get("local", "A", &src, &Settings::default(), &[], HOUR),
+            None
+        );
+
+        cache.put("local", "A", &src, &Settings::default(), &[], "value");
+        assert_eq!(
+            cache
+                .get("local", "A", &src, &Settings::default(), &[], HOUR)
+                .as_deref(),
+            Some("value")
+        );
+
+        // Any change to the key inputs is a miss.
+        assert_eq!(
+            cache.get("prod", "A", &src, &Settings::default(), &[], HOUR),
+            None
+        );
+        assert_eq!(
+            cache.get("local", "B", &src, &Settings::default(), &[], HOUR),
+            None
+        );
+        assert_eq!(
+            cache.get(
+                "local",
+                "A",
+                &source("echo b"),
+                &Settings::default(),
+                &[],
+                HOUR
+            ),
+            None
+        );
+        assert_eq!(
+            cache.get(
+                "local",
+                "A",
+                &src,
+                &Settings::default(),
+                &[("X", "1")],
+                HOUR
+            ),
+            None
+        );
+        let other = Cache::new(dir.clone(), Path::new("other.yaml"));
+        assert_eq!(
+            other.get("local", "A", &src, &Settings::default(), &[], HOUR),
+            None
+        );
+
+        assert_eq!(Cache::clear(&dir).unwrap(), 1);
+        assert_eq!(
+            cache.get("local", "A", &src, &Settings::default(), &[], HOUR),
+            None
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn shell_settings_are_part_of_the_key() {
+        let dir = temp_dir("settings");
+        let cache = Cache::new(dir.clone(), Path::new("envoke.yaml"));
+        let src = source("echo a");
+        let settings = Settings::default();
+        cache.put("local", "A", &src, &settings, &[], "value");
+
+        let bash = Settings {
+            shell: crate::config::Shell::Name("bash".to_owned()),
+            ..Settings::default()
+        };
+        assert_eq!(cache.get("local", "A", &src, &bash, &[], HOUR), None);
+        let strict = Settings {
+            strict: !settings.strict,
+            ..Settings::default()
+        };
+        assert_eq!(cache.get("local", "A", &src, &strict, &[], HOUR), None);
+        assert_eq!(
+            cache
+                .get("local", "A", &src, &settings, &[], HOUR)
+                .as_deref(),
+            Some("value")
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expired_entries_miss() {
+        let dir = temp_dir("expired");
+        let cache = Cache::new(dir.clone(), Path::new("envoke.yaml"));
+        let src = source("echo a");
+        cache.put("local", "A", &src, &Settings::default(), &[], "value");
+        assert_eq!(
+            cache.get(
+                "local",
+                "A",
+                &src,
+                &Settings::default(),
+                &[],
+                Duration::ZERO
+            ),
+            None
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ttl_is_not_truncated_to_seconds() {
+        let dir = temp_dir("sub-second");
+        let cache = Cache::new(dir.clone(), Path::new("envoke.yaml"));
+        let src = source("echo a");
+        cache.put("local", "A", &src, &Settings::default(), &[], "value");
+        let ttl = Duration::from_millis(300);
+        assert_eq!(
+            cache
+                .get("local", "A", &src, &Settings::default(), &[], ttl)
+                .as_deref(),
+            Some("value")
+        );
+        std::thread::sleep(ttl);
+        assert_eq!(
+            cache.get("local", "A", &src, &Settings::default(), &[], ttl),
+            None
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn clear_removes_leftover_temp_files() {
+        let dir = temp_dir("leftover");
+        let cache = Cache::new(dir.clone(), Path::new("envoke.yaml"));
+        cache.put(
+            "local",
+            "A",
+            &source("echo a"),
+            &Settings::default(),
+            &[],
+            "secret",
+        );
+        fs::write(dir.join("0123.tmp42"), "secret").unwrap();
+        assert_eq!(Cache::clear(&dir).unwrap(), 2);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn clear_missing_dir_is_noop() {
+        assert_eq!(Cache::clear(&temp_dir("missing")).unwrap(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn entries_are_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = temp_dir("perms");
+        let cache = Cache::new(dir.clone(), Path::new("envoke.yaml"));
+        cache.put(
+            "local",
+            "A",
+            &source("echo a"),
+            &Settings::default(),
+            &[],
+            "secret",
+        );
+        let dir_mode = fs::metadata(&dir).unwrap().permissions().mode() & 0o777;
+        assert_eq!(dir_mode, 0o700);
+        for entry in fs::read_dir(&dir).unwrap() {
+            let mode = entry.unwrap().metadata().unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode, 0o600);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+//! Static validation of a whole config, as run by `envoke check`.
+
+use std::collections::HashSet;
+
+use crate::config::Config;
+use crate::error::CheckFinding;
+use crate::error::ResolveError;
+use crate::error::ResolveErrorKind;
+use crate::resolve::Resolver;
+
+/// Check every environment from [`Config::environments`] without running
+/// any `cmd:` / `sh:` source (see [`Resolver::check`]).
+///
+/// Each environment is checked with no override active and with each
+/// override in turn, crossed with no tag, each tag alone, and every tag.
+/// Together these surface every missing source, unknown reference, and
+/// cycle a single override and any tag set can run into. `overrides` are
+/// active in every combination, so passing several checks that they do not
+/// conflict. `tags`, when given, is the only tag set checked instead.
+///
+/// A problem is reported once, under the first combination that hits it.
+pub fn check_config(
+    config: &Config,
+    overrides: &[String],
+    tags: Option<&[String]>,
+) -> Vec<CheckFinding> {
+    let mut override_sets = vec![None];
+    override_sets.extend(
+        config
+            .override_names()
+            .into_iter()
+            .filter(|name| !overrides.contains(name))
+            .map(Some),
+    );
+    let tag_sets: Vec<Vec<String>> = if let Some(tags) = tags {
+        vec![tags.to_vec()]
+    } else {
+        let tags = config.tag_names();
+        let mut tag_sets = vec![Vec::new()];
+        tag_sets.extend(tags.iter().map(|tag| vec![tag.clone()]));
+        if tags.len() > 1 {
+            tag_sets.push(tags);
+        }
+        tag_sets
+    };
+
+    let mut seen = HashSet::new();
+    let mut findings = Vec::new();
+    for environment in config.environments() {
+        for extra in &override_sets {
+            for tag_set in &tag_sets {
+                let errors = Resolver::new(config, environment.as_str())
+                    .overrides(overrides)
+                    .overrides(extra)
+                    .tags(tag_set)
+                    .check();
+                for error in errors {
+                    if !seen.insert(key(&error)) {
+                        continue;
+                    }
+                    let mut flags: Vec<String> = extra
+                        .iter()
+                        .map(|name| format!("--override {name}"))
+                        .collect();
+                    // Tags the caller chose are not news to them.
+                    match tag_set.as_slice() {
+                        _ if tags.is_some() => {}
+                        [] => {}
+                        [tag] => flags.push(format!("--tag {tag}")),
+                        _ => flags.push("--all-tags".to_owned()),
+                    }
+                    findings.push(CheckFinding { error, flags });
+                }
+            }
+        }
+    }
+    findings
+}
+
+/// Identity of a problem across combinations. A cycle is the same cycle
+/// whichever of its variables the report starts from.
+fn key(error: &ResolveError) -> (String, String) {
+    let what = match &error.kind {
+        ResolveErrorKind::CircularDependency { chain } => {
+            let mut members = chain.clone();
+            members.sort();
+            members.dedup();
+            members.join(" ")
+        }
+        kind => format!("{} {kind}", error.variable),
+    };
+    (error.environment.clone(), what)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> Config {
+        serde_yml::from_str(yaml).unwrap()
+    }
+
+    fn messages(findings: &[CheckFinding]) -> Vec<String> {
+        findings.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_check_reports_each_problem_once() {
+        let config = config(
+            r"
+variables:
+  OAUTH_CLIENT_ID:
+    envs:
+      local: { literal: dev-client }
+      prod: { sh: 'vault read -field=id secret/oauth' }
+  URL:
+    default:
+      template: '{{ HOST }}/api'
+  HOST:
+    tags: [remote]
+    default: { literal: example.com }
+  LOOP_A:
+    default: { template: '{{ LOOP_B }}' }
+  LOOP_B:
+    default: { template: '{{ LOOP_A }}' }
+",
+        );
+        // The cycle only shows once HOST is tagged in and the unknown
+        // reference is gone; each problem is reported once per environment,
+        // whichever variable of the cycle it is reported on.
+        let messages = messages(&check_config(&config, &[], None));
+        assert_eq!(messages.len(), 4, "{messages:#?}");
+        assert_eq!(messages[0], "URL [local]: unknown variable reference: HOST");
+        assert_eq!(messages[2], "URL [prod]: unknown variable reference: HOST");
+        for (message, environment) in [(&messages[1], "local"), (&messages[3], "prod")] {
+            assert!(
+                message.contains(&format!("[{environment}]: circular dependency: LOOP_"))
+                    && message.ends_with(" (with --tag remote)"),
+                "{message}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_covers_overrides_tags_and_environments() {
+        let config = config(
+            r"
+variables:
+  DB_HOST:
+    envs:
+      local: { literal: localhost }
+      staging: { literal: db.staging }
+    overrides:
+      replica:
+        envs:
+          staging: { cmd: { args: [] } }
+      ci:
+        default: { literal: ci-db }
+  TOKEN:
+    tags: [vault]
+    envs:
+      staging: { sh: 'vault read token' }
+",
+        );
+        assert_eq!(
+            messages(&check_config(&config, &[], None)),
+            [
+                "TOKEN [local]: no configuration for this environment (with --tag vault)",
+                "DB_HOST [staging]: invalid source: `cmd` must have at least one element (with --override replica)",
+            ],
+        );
+
+        let conflicting = messages(&check_config(
+            &config,
+            &["ci".to_owned(), "replica".to_owned()],
+            None,
+        ));
+        assert!(
+            conflicting[0].starts_with("DB_HOST [local]: conflicting overrides"),
+            "{conflicting:#?}"
+        );
+    }
+
+    #[test]
+    fn test_check_with_tags_checks_only_that_set() {
+        let config = config(
+            r"
+variables:
+  TOKEN:
+    tags: [vault]
+    envs:
+      staging: { sh: 'vault read token' }
+  REGION:
+    tags: [aws]
+    envs:
+      local: { literal: eu-west-1 }
+",
+        );
+        assert_eq!(
+            messages(&check_config(&config, &[], Some(&["vault".to_owned()]))),
+            ["TOKEN [local]: no configuration for this environment"],
+        );
+        assert!(check_config(&config, &[], Some(&[])).is_empty());
+    }
+
+    #[test]
+    fn test_check_never_runs_sources() {
+        let marker = std::env::temp_dir().join(format!("envoke-check-{}", std::process::id()));
+        let config = config(&format!(
+            r"
+variables:
+  RAN:
+    default: {{ sh: 'touch {0}' }}
+  ITEM_{{{{ item }}}}:
+    for_each: {{ sh: 'touch {0}; echo a' }}
+    default: {{ literal: x }}
+",
+            marker.display()
+        ));
+        assert!(check_config(&config, &[], None).is_empty());
+        assert!(!marker.exists());
+    }
+}
@@ -1,22 +1,79 @@
+use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::path::PathBuf;
 
 use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::duration::HumanDuration;
+use crate::error::ConfigError;
+
 /// Top-level envoke configuration, typically loaded from `envoke.yaml`.
-#[derive(Debug, Deserialize, JsonSchema)]
+#[derive(Debug, Default, Deserialize, JsonSchema)]
 pub struct Config {
+    /// Per-environment settings keyed by environment name. Environments
+    /// without an entry use the default settings.
+    #[serde(default)]
+    pub environments: BTreeMap<String, Environment>,
+    /// Settings that apply to every variable.
+    #[serde(default)]
+    pub settings: Settings,
+    /// Named multi-output sources. A bundle's command runs at most once per
+    /// invocation and variables pick individual keys from its output with
+    /// `from_bundle`.
+    #[serde(default)]
+    pub bundles: BTreeMap<String, Bundle>,
     /// Map of variable names to their definitions.
     pub variables: BTreeMap<String, Variable>,
 }
 
+/// Settings for a single environment.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct Environment {
+    /// Require confirmation before `exec` or `render --output` runs against
+    /// this environment. Interactive sessions are prompted to type the
+    /// environment name; non-interactive sessions must pass `--yes`.
+    #[serde(default)]
+    pub protected: bool,
+}
+
+/// Config-wide settings.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct Settings {
+    /// Interpreter for `sh:` sources that don't set their own `shell`.
+    /// Defaults to `sh`.
+    #[serde(default)]
+    pub shell: Shell,
+    /// Run `sh:` scripts in strict mode (`-eu`, plus `-o pipefail` for bash,
+    /// zsh, and ksh) unless a source says otherwise. Only applies to shells
+    /// given by name.
+    #[serde(default)]
+    pub strict: bool,
+    /// Fail `template:` sources (and `render` output templates) that read an
+    /// undefined value, such as a misspelled `meta` field, instead of
+    /// rendering it as an empty string. `--strict` enables it too.
+    #[serde(default)]
+    pub strict_templates: bool,
+    /// Disable the template functions that read the outside world (`env`,
+    /// `file`, `now`, `uuid`, and `hostname`), so the same config always
+    /// renders the same output. Calling one is then an error.
+    #[serde(default)]
+    pub pure_templates: bool,
+    /// Maximum number of `cmd:` / `sh:` sources run at once. Defaults to 8;
+    /// `--jobs` / `ENVOKE_JOBS` take precedence.
+    #[serde(default)]
+    pub jobs: Option<NonZeroUsize>,
+}
+
 /// A single environment variable with per-environment sources.
 ///
 /// Resolution requires either an `envs` entry matching the target environment
 /// or a `default`. If neither exists, resolution fails with a `NoConfig` error.
-#[derive(Debug, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct Variable {
     /// Human-readable description, rendered as a comment in output.
     pub description: Option<String>,
@@ -39,11 +96,100 @@ pub struct Variable {
     /// take precedence over the base sources when active.
     #[serde(default)]
     pub overrides: BTreeMap<String, Override>,
+    /// Whether the variable appears in output. Set to `false` for helper
+    /// variables that only exist to be referenced from other variables'
+    /// templates; they are still resolved but never rendered or exported.
+    #[serde(default = "default_true")]
+    pub export: bool,
+    /// Whether the value is sensitive. Commands that print values for
+    /// review, such as `envoke diff`, redact it unless asked not to.
+    #[serde(default)]
+    pub secret: bool,
+    /// Expand this definition into one variable per item. The variable's
+    /// name and the strings in its sources, `needs` and `cwd` included, are
+    /// templates over `item` (e.g. `TENANT_{{ item | upper }}_URL`).
+    #[serde(default)]
+    pub for_each: Option<ForEach>,
+}
+
+impl Default for Variable {
+    fn default() -> Self {
+        Self {
+            description: None,
+            tags: Vec::new(),
+            default: None,
+            envs: BTreeMap::new(),
+            overrides: BTreeMap::new(),
+            export: true,
+            secret: false,
+            for_each: None,
+        }
+    }
+}
+
+/// Items a `for_each` variable expands over.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ForEach {
+    /// A fixed list of items.
+    Items(Vec<String>),
+    /// A source whose output lists one item per non-empty line. It runs
+    /// before any variable is resolved, so it cannot reference variables.
+    Source(
+        #[serde(with = "serde_yml::with::singleton_map")]
+        #[schemars(with = "Source")]
+        Source,
+    ),
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A source whose output holds the values of several variables.
+///
+/// Like a variable, a bundle picks its source from `envs` or `default`. The
+/// source's output is parsed according to `format` into a map of keys to
+/// values.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct Bundle {
+    /// How to split the source's output into keys and values.
+    pub format: BundleFormat,
+    /// Fallback source used when the requested environment has no entry in
+    /// `envs`.
+    #[serde(default, with = "serde_yml::with::singleton_map_optional")]
+    #[schemars(with = "Option<Source>")]
+    pub default: Option<Source>,
+    /// Map of environment names to bundle sources.
+    #[serde(default, with = "serde_yml::with::singleton_map_recursive")]
+    #[schemars(with = "BTreeMap<String, Source>")]
+    pub envs: BTreeMap<String, Source>,
+}
+
+/// Output format of a bundle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BundleFormat {
+    /// A JSON object. String values are used as-is; other values are
+    /// serialized as JSON.
+    Json,
+    /// `KEY=VALUE` lines, as in a `.env` file.
+    Dotenv,
+}
+
+/// A reference from a variable to one key of a bundle.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BundleRef {
+    /// Name of the bundle under the top-level `bundles`.
+    pub bundle: String,
+    /// Key to read from the bundle. Defaults to the variable's name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
 }
 
 /// An override provides alternative sources for a variable, activated via
 /// the `--override` CLI flag.
-#[derive(Debug, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct Override {
     /// Fallback source for this override when the environment has no entry.
     #[serde(default, with = "serde_yml::with::singleton_map_optional")]
@@ -62,44 +208,493 @@ pub enum Source {
     /// A fixed string value.
     Literal(String),
     /// A command to execute; stdout is captured with trailing whitespace
-    /// stripped.
-    Cmd(Vec<String>),
-    /// A shell script to execute via `sh -c`; stdout is captured with trailing
-    /// whitespace stripped.
-    Sh(String),
+    /// stripped. Either a list `[program, args...]` or a map with `args` and
+    /// execution options.
+    Cmd(CmdSource),
+    /// A shell script to execute via `sh -c` (or the configured shell);
+    /// stdout is captured with trailing whitespace stripped. Either the
+    /// script itself or a map with `script`, `shell`, and `strict`.
+    Sh(ShSource),
     /// A minijinja (Jinja2) template string. Reference other variables with
     /// `{{ VAR_NAME }}` or metadata like `{{ meta.environment }}`.
     Template(String),
+    /// One key of a bundle's output: `{ bundle, key }`.
+    #[serde(rename = "from_bundle")]
+    FromBundle(BundleRef),
     /// Silently omit this variable from output. YAML surface: the bare string
     /// `skip` (not `skip: true`).
     Skip,
 }
 
 impl Source {
+    /// The source's YAML key (`literal`, `cmd`, `from_bundle`, ...).
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Literal(_) => "literal",
+            Self::Cmd(_) => "cmd",
+            Self::Sh(_) => "sh",
+            Self::Template(_) => "template",
+            Self::FromBundle(_) => "from_bundle",
+            Self::Skip => "skip",
+        }
+    }
+
+    /// Variables a `cmd:` / `sh:` source declares in `needs`. Empty for every
+    /// other source.
+    #[must_use]
+    pub fn needs(&self) -> &[String] {
+        match self {
+            Self::Cmd(CmdSource::Options(options)) => &options.needs,
+            Self::Sh(ShSource::Options(options)) => &options.needs,
+            _ => &[],
+        }
+    }
+
+    /// Cache lifetime requested by a `cmd:` / `sh:` source, if any.
+    #[must_use]
+    pub fn cache_ttl(&self) -> Option<std::time::Duration> {
+        let cache = match self {
+            Self::Cmd(CmdSource::Options(options)) => options.cache.as_ref(),
+            Self::Sh(ShSource::Options(options)) => options.cache.as_ref(),
+            _ => None,
+        };
+        cache.map(|c| c.ttl.0)
+    }
+
+    /// Retry policy requested by a `cmd:` / `sh:` source, if any.
+    #[must_use]
+    pub fn retry(&self) -> Option<&RetryOptions> {
+        match self {
+            Self::Cmd(CmdSource::Options(options)) => options.retry.as_ref(),
+            Self::Sh(ShSource::Options(options)) => options.retry.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Concurrency group of a `cmd:` / `sh:` source, if any.
+    #[must_use]
+    pub fn concurrency_group(&self) -> Option<&str> {
+        match self {
+            Self::Cmd(CmdSource::Options(options)) => options.concurrency_group.as_deref(),
+            Self::Sh(ShSource::Options(options)) => options.concurrency_group.as_deref(),
+            _ => None,
+        }
+    }
+
     /// Validate that the source is well-formed.
-    pub fn validate(&self) -> Result<(), &'static str> {
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the first problem found.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Cmd(cmd) if cmd.args().is_empty() => {
+                Err("`cmd` must have at least one element".to_owned())
+            }
+            _ => self.retry().map_or(Ok(()), RetryOptions::validate),
+        }
+    }
+}
+
+/// The two YAML surfaces of a `cmd:` source.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum CmdSource {
+    /// Short form: `[program, args...]`, run with default options.
+    Args(Vec<String>),
+    /// Long form: `{ args: [...], cwd, env, stdin, timeout, trim,
+    /// allow_failure, needs, cache, retry }`.
+    Options(Box<CmdOptions>),
+}
+
+impl CmdSource {
+    /// The program and its arguments.
+    #[must_use]
+    pub fn args(&self) -> &[String] {
+        match self {
+            Self::Args(args) => args,
+            Self::Options(options) => &options.args,
+        }
+    }
+
+    /// The command with every option spelled out, defaults filled in.
+    #[must_use]
+    pub fn options(&self) -> Cow<'_, CmdOptions> {
+        match self {
+            Self::Args(args) => Cow::Owned(CmdOptions {
+                args: args.clone(),
+                ..CmdOptions::default()
+            }),
+            Self::Options(options) => Cow::Borrowed(options),
+        }
+    }
+}
+
+/// Long form of a `cmd:` source.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct CmdOptions {
+    /// Program followed by its arguments.
+    pub args: Vec<String>,
+    /// Working directory for the command. Relative paths are resolved
+    /// against the directory holding the config file, like `file()` paths.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+    /// Extra environment variables for the command, overlaid on envoke's own
+    /// environment.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Text written to the command's standard input. Without it, stdin is
+    /// closed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<String>,
+    /// Kill the command and fail if it runs longer than this (e.g. `30s`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<HumanDuration>,
+    /// Whitespace trimming applied to captured stdout.
+    #[serde(default)]
+    pub trim: Trim,
+    /// Use stdout even when the command exits non-zero, instead of failing.
+    #[serde(default)]
+    pub allow_failure: bool,
+    /// Variables that must be resolved before this command runs. Their
+    /// values are passed to the command as environment variables of the
+    /// same name (entries in `env` take precedence).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub needs: Vec<String>,
+    /// Reuse the command's output across invocations for a while.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheOptions>,
+    /// Run the command again when it fails.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryOptions>,
+    /// Sources sharing a group name never run at the same time; sources in
+    /// different groups (or none) still run in parallel.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency_group: Option<String>,
+}
+
+/// The two YAML surfaces of an `sh:` source.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ShSource {
+    /// Short form: the script, run with the config-level shell.
+    Script(String),
+    /// Long form: `{ script, shell, strict, needs, cache, retry,
+    /// concurrency_group }`.
+    Options(ShOptions),
+}
+
+impl ShSource {
+    /// The script text.
+    #[must_use]
+    pub fn script(&self) -> &str {
+        match self {
+            Self::Script(script) => script,
+            Self::Options(options) => &options.script,
+        }
+    }
+
+    /// Full argument vector that runs this script: the interpreter chosen
+    /// by the source (falling back to `settings`), its flags, and the script
+    /// as the final argument.
+    #[must_use]
+    pub fn argv(&self, settings: &Settings) -> Vec<String> {
+        let (shell, strict) = match self {
+            Self::Script(_) => (&settings.shell, settings.strict),
+            Self::Options(options) => (
+                options.shell.as_ref().unwrap_or(&settings.shell),
+                options.strict.unwrap_or(settings.strict),
+            ),
+        };
+        let mut argv = shell.argv(strict);
+        argv.push(self.script().to_owned());
+        argv
+    }
+}
+
+/// Long form of an `sh:` source.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ShOptions {
+    /// The script to run.
+    pub script: String,
+    /// Interpreter for this script, overriding `settings.shell`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<Shell>,
+    /// Strict mode for this script, overriding `settings.strict`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+    /// Variables that must be resolved before this script runs. Their values
+    /// are passed to the script as environment variables of the same name.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub needs: Vec<String>,
+    /// Reuse the script's output across invocations for a while.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheOptions>,
+    /// Run the script again when it fails.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryOptions>,
+    /// Scripts sharing a group name never run at the same time; scripts in
+    /// different groups (or none) still run in parallel.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency_group: Option<String>,
+}
+
+/// On-disk caching for a `cmd:` / `sh:` source.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CacheOptions {
+    /// How long a cached value stays valid (e.g. `15m`).
+    pub ttl: HumanDuration,
+}
+
+/// Retry policy for a flaky `cmd:` / `sh:` source.
+///
+/// Failed runs (non-zero exit or timeout) are retried up to `attempts` times
+/// in total, sleeping `backoff` before the first retry and doubling the delay
+/// before each subsequent one. `on_exit_codes` and `on_stderr` narrow which
+/// failures are retried; when both are given, either may match.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RetryOptions {
+    /// Total number of runs, including the first (at least 1).
+    pub attempts: u32,
+    /// Delay before the first retry; doubles for each further retry.
+    #[serde(default = "default_backoff")]
+    pub backoff: HumanDuration,
+    /// Only retry when the command exits with one of these codes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_exit_codes: Vec<i32>,
+    /// Only retry when stderr matches one of these regular expressions.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_stderr: Vec<StderrPattern>,
+}
+
+/// A regular expression from `retry.on_stderr`, compiled once when the config
+/// is read. An invalid pattern is kept so validation can report it against
+/// the variable that uses it.
+#[derive(Debug, Clone)]
+pub struct StderrPattern {
+    text: String,
+    regex: Result<regex::Regex, regex::Error>,
+}
+
+impl StderrPattern {
+    /// Compile `text`; errors surface through [`StderrPattern::error`].
+    pub fn new(text: impl Into<String>) -> Self {
+        let text = text.into();
+        let regex = regex::Regex::new(&text);
+        Self { text, regex }
+    }
+
+    /// The pattern as written in the config.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Why the pattern failed to compile, if it did.
+    #[must_use]
+    pub fn error(&self) -> Option<&regex::Error> {
+        self.regex.as_ref().err()
+    }
+
+    /// Whether `haystack` matches the pattern.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pattern is invalid; configs are validated before any
+    /// source runs.
+    #[must_use]
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.regex
+            .as_ref()
+            .expect("`retry.on_stderr` patterns are validated before sources run")
+            .is_match(haystack)
+    }
+}
+
+impl<'de> Deserialize<'de> for StderrPattern {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::new)
+    }
+}
+
+impl Serialize for StderrPattern {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.text)
+    }
+}
+
+impl JsonSchema for StderrPattern {
+    fn inline_schema() -> bool {
+        true
+    }
+
+    fn schema_name() -> Cow<'static, str> {
+        "StderrPattern".into()
+    }
+
+    fn json_schema(generator: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        String::json_schema(generator)
+    }
+}
+
+fn default_backoff() -> HumanDuration {
+    HumanDuration(std::time::Duration::from_secs(1))
+}
+
+impl RetryOptions {
+    /// Whether a failed run is eligible for another attempt. Timeouts carry
+    /// no exit code or stderr, so they are only retried without filters.
+    #[must_use]
+    pub fn should_retry(&self, exit_code: Option<i32>, stderr: Option<&str>) -> bool {
+        if self.on_exit_codes.is_empty() && self.on_stderr.is_empty() {
+            return true;
+        }
+        let code_matches = exit_code.is_some_and(|code| self.on_exit_codes.contains(&code));
+        let stderr_matches = stderr.is_some_and(|stderr| {
+            self.on_stderr
+                .iter()
+                .any(|pattern| pattern.is_match(stderr))
+        });
+        code_matches || stderr_matches
+    }
+
+    /// Delay before retry number `retry` (1-based).
+    #[must_use]
+    pub fn delay(&self, retry: u32) -> std::time::Duration {
+        self.backoff
+            .0
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.attempts == 0 {
+            return Err("`retry.attempts` must be at least 1".to_owned());
+        }
+        for pattern in &self.on_stderr {
+            if let Some(e) = pattern.error() {
+                return Err(format!(
+                    "invalid `retry.on_stderr` pattern `{}`: {e}",
+                    pattern.as_str()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Interpreter used for `sh:` sources.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Shell {
+    /// A shell by name (e.g. `bash`), invoked as `<name> -c <script>`.
+    /// Strict mode adds `-eu`, and `-o pipefail` for bash, zsh, and ksh.
+    Name(String),
+    /// An exact argument list; the script is appended as the last argument
+    /// (e.g. `[bash, -euo, pipefail, -c]`). Strict mode does not alter it.
+    Argv(Vec<String>),
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::Name("sh".to_owned())
+    }
+}
+
+impl Shell {
+    /// Interpreter and flags that precede the script.
+    #[must_use]
+    pub fn argv(&self, strict: bool) -> Vec<String> {
         match self {
-            Self::Cmd(args) if args.is_empty() => Err("`cmd` must have at least one element"),
-            _ => Ok(()),
+            Self::Argv(argv) => argv.clone(),
+            Self::Name(name) => {
+                let mut argv = vec![name.clone()];
+                if strict {
+                    argv.extend(["-e".to_owned(), "-u".to_owned()]);
+                    let basename = name.rsplit('/').next().unwrap_or(name);
+                    if matches!(basename, "bash" | "zsh" | "ksh") {
+                        argv.extend(["-o".to_owned(), "pipefail".to_owned()]);
+                    }
+                }
+                argv.push("-c".to_owned());
+                argv
+            }
+        }
+    }
+}
+
+/// Whitespace trimming applied to captured command output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Trim {
+    /// Keep the output byte-for-byte.
+    None,
+    /// Strip trailing whitespace (the default).
+    #[default]
+    End,
+    /// Strip leading and trailing whitespace.
+    Both,
+}
+
+impl Trim {
+    /// Apply this trimming mode to `s`.
+    #[must_use]
+    pub fn apply(self, s: &str) -> &str {
+        match self {
+            Self::None => s,
+            Self::End => s.trim_end(),
+            Self::Both => s.trim(),
         }
     }
 }
 
 impl Config {
-    /// Returns sorted, deduplicated environment names found across all
-    /// variables' `envs` maps and override `envs` maps.
+    /// Read and parse a config file.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError`] if the file cannot be read or is not a valid
+    /// envoke config.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let yaml = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        serde_yml::from_str(&yaml).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Returns sorted, deduplicated environment names found across the
+    /// top-level `environments` map, all variables' `envs` maps, and override
+    /// `envs` maps.
+    #[must_use]
     pub fn environments(&self) -> Vec<String> {
-        let mut set = BTreeSet::new();
+        let mut set: BTreeSet<String> = self.environments.keys().cloned().collect();
         for var in self.variables.values() {
             set.extend(var.envs.keys().cloned());
             for ovr in var.overrides.values() {
                 set.extend(ovr.envs.keys().cloned());
             }
         }
+        for bundle in self.bundles.values() {
+            set.extend(bundle.envs.keys().cloned());
+        }
         set.into_iter().collect()
     }
 
+    /// Whether the given environment is marked `protected`.
+    #[must_use]
+    pub fn is_protected(&self, environment: &str) -> bool {
+        self.environments
+            .get(environment)
+            .is_some_and(|e| e.protected)
+    }
+
     /// Returns sorted, deduplicated override names found across all variables.
+    #[must_use]
     pub fn override_names(&self) -> Vec<String> {
         let mut set = BTreeSet::new();
         for var in self.variables.values() {
@@ -109,6 +704,7 @@ impl Config {
     }
 
     /// Returns sorted, deduplicated tag names found across all variables.
+    #[must_use]
     pub fn tag_names(&self) -> Vec<String> {
         let mut set = BTreeSet::new();
         for var in self.variables.values() {
@@ -116,6 +712,31 @@ impl Config {
         }
         set.into_iter().collect()
     }
+
+    /// Every source in the config, for any environment: variable and
+    /// override sources, `for_each` item sources, and bundle sources.
+    pub fn sources(&self) -> impl Iterator<Item = &Source> {
+        let variables = self.variables.values().flat_map(|var| {
+            let overrides = var
+                .overrides
+                .values()
+                .flat_map(|ovr| ovr.default.iter().chain(ovr.envs.values()));
+            let items = match &var.for_each {
+                Some(ForEach::Source(source)) => Some(source),
+                _ => None,
+            };
+            var.default
+                .iter()
+                .chain(var.envs.values())
+                .chain(overrides)
+                .chain(items)
+        });
+        let bundles = self
+            .bundles
+            .values()
+            .flat_map(|bundle| bundle.default.iter().chain(bundle.envs.values()));
+        variables.chain(bundles)
+    }
 }
 
 #[cfg(test)]
@@ -132,6 +753,7 @@ mod tests {
                 .into_iter()
                 .map(|(k, v)| (k.to_string(), v))
                 .collect(),
+            ..Default::default()
         }
     }
 
@@ -157,6 +779,7 @@ mod tests {
                         ]),
                     },
                 )]),
+                ..Default::default()
             },
         )]);
         assert_eq!(config.environments(), vec!["dev", "prod", "staging"]);
@@ -172,6 +795,7 @@ mod tests {
                 default: Some(source_literal("x")),
                 envs: BTreeMap::new(),
                 overrides: BTreeMap::new(),
+                ..Default::default()
             },
         )]);
         assert!(config.environments().is_empty());
@@ -203,6 +827,7 @@ mod tests {
                             },
                         ),
                     ]),
+                    ..Default::default()
                 },
             ),
             (
@@ -219,6 +844,7 @@ mod tests {
                             envs: BTreeMap::new(),
                         },
                     )]),
+                    ..Default::default()
                 },
             ),
         ]);
@@ -235,6 +861,7 @@ mod tests {
                 default: Some(source_literal("x")),
                 envs: BTreeMap::new(),
                 overrides: BTreeMap::new(),
+                ..Default::default()
             },
         )]);
         assert!(config.override_names().is_empty());
@@ -251,6 +878,7 @@ mod tests {
                     default: None,
                     envs: BTreeMap::new(),
                     overrides: BTreeMap::new(),
+                    ..Default::default()
                 },
             ),
             (
@@ -261,6 +889,7 @@ mod tests {
                     default: None,
                     envs: BTreeMap::new(),
                     overrides: BTreeMap::new(),
+                    ..Default::default()
                 },
             ),
         ]);
@@ -277,8 +906,216 @@ mod tests {
                 default: Some(source_literal("x")),
                 envs: BTreeMap::new(),
                 overrides: BTreeMap::new(),
+                ..Default::default()
             },
         )]);
         assert!(config.tag_names().is_empty());
     }
+
+    #[test]
+    fn sources_cover_every_definition() {
+        let config: Config = serde_yml::from_str(
+            "
+bundles:
+  secrets:
+    format: json
+    envs:
+      prod:
+        literal: '{}'
+variables:
+  A:
+    default:
+      literal: a
+    envs:
+      prod:
+        literal: b
+    overrides:
+      fast:
+        default:
+          literal: c
+  TENANT_{{ item }}:
+    for_each:
+      literal: x
+    default:
+      literal: d
+",
+        )
+        .unwrap();
+        let mut literals: Vec<&str> = config
+            .sources()
+            .map(|source| match source {
+                Source::Literal(value) => value.as_str(),
+                other => panic!("unexpected source {other:?}"),
+            })
+            .collect();
+        literals.sort_unstable();
+        assert_eq!(literals, ["a", "b", "c", "d", "x", "{}"]);
+    }
+
+    #[test]
+    fn cmd_short_and_long_forms_parse() {
+        let short: Source = serde_yml::from_str("cmd: [echo, hi]").unwrap();
+        let Source::Cmd(short) = short else {
+            panic!("expected cmd source");
+        };
+        assert_eq!(short.args(), ["echo", "hi"]);
+        assert_eq!(short.options().trim, Trim::End);
+
+        let long: Source = serde_yml::from_str(
+            "cmd:\n  args: [cat]\n  cwd: /tmp\n  env: {A: b}\n  stdin: hello\n  timeout: 5s\n  trim: both\n  allow_failure: true\n",
+        )
+        .unwrap();
+        let Source::Cmd(long) = long else {
+            panic!("expected cmd source");
+        };
+        let options = long.options();
+        assert_eq!(options.args, ["cat"]);
+        assert_eq!(options.cwd.as_deref(), Some(std::path::Path::new("/tmp")));
+        assert_eq!(options.env["A"], "b");
+        assert_eq!(options.stdin.as_deref(), Some("hello"));
+        assert_eq!(
+            options.timeout.map(|t| t.0),
+            Some(std::time::Duration::from_secs(5))
+        );
+        assert_eq!(options.trim, Trim::Both);
+        assert!(options.allow_failure);
+    }
+
+    #[test]
+    fn sh_argv_uses_settings_and_source_shell() {
+        let settings = Settings::default();
+        let short: Source = serde_yml::from_str("sh: echo hi").unwrap();
+        let Source::Sh(short) = short else {
+            panic!("expected sh source");
+        };
+        assert_eq!(short.argv(&settings), ["sh", "-c", "echo hi"]);
+
+        let strict_bash = Settings {
+            shell: Shell::Name("bash".to_owned()),
+            strict: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            short.argv(&strict_bash),
+            ["bash", "-e", "-u", "-o", "pipefail", "-c", "echo hi"]
+        );
+
+        let long: Source =
+            serde_yml::from_str("sh:\n  script: echo hi\n  shell: [zsh, -f, -c]\n  strict: true\n")
+                .unwrap();
+        let Source::Sh(long) = long else {
+            panic!("expected sh source");
+        };
+        assert_eq!(long.argv(&settings), ["zsh", "-f", "-c", "echo hi"]);
+    }
+
+    #[test]
+    fn strict_sh_omits_pipefail() {
+        assert_eq!(
+            Shell::Name("/bin/sh".to_owned()).argv(true),
+            ["/bin/sh", "-e", "-u", "-c"]
+        );
+        assert_eq!(
+            Shell::Name("/usr/bin/bash".to_owned()).argv(true),
+            ["/usr/bin/bash", "-e", "-u", "-o", "pipefail", "-c"]
+        );
+    }
+
+    #[test]
+    fn trim_modes() {
+        assert_eq!(Trim::None.apply(" a \n"), " a \n");
+        assert_eq!(Trim::End.apply(" a \n"), " a");
+        assert_eq!(Trim::Both.apply(" a \n"), "a");
+    }
+
+    #[test]
+    fn environments_include_settings_keys() {
+        let mut config = make_config(vec![(
+            "VAR",
+            Variable {
+                description: None,
+                tags: vec![],
+                default: None,
+                envs: BTreeMap::from([("staging".to_string(), source_literal("a"))]),
+                overrides: BTreeMap::new(),
+                ..Default::default()
+            },
+        )]);
+        config
+            .environments
+            .insert("prod".to_string(), Environment { protected: true });
+        assert_eq!(config.environments(), vec!["prod", "staging"]);
+    }
+
+    #[test]
+    fn protected_environments_parse() {
+        let config: Config = serde_yml::from_str(
+            "environments:\n  prod:\n    protected: true\n  staging: {}\nvariables: {}\n",
+        )
+        .unwrap();
+        assert!(config.is_protected("prod"));
+        assert!(!config.is_protected("staging"));
+        assert!(!config.is_protected("local"));
+    }
+
+    #[test]
+    fn retry_options_parse_and_match() {
+        let yaml = "sh:\n  script: fetch\n  retry:\n    attempts: 4\n    backoff: 200ms\n    on_exit_codes: [75]\n    on_stderr: ['\\b503\\b']\n";
+        let source: Source = serde_yml::from_str(yaml).unwrap();
+        let retry = source.retry().unwrap();
+        assert_eq!(retry.attempts, 4);
+        assert_eq!(retry.delay(1), std::time::Duration::from_millis(200));
+        assert_eq!(retry.delay(3), std::time::Duration::from_millis(800));
+        assert!(retry.should_retry(Some(75), Some("")));
+        assert!(retry.should_retry(Some(1), Some("HTTP 503 from vault")));
+        assert!(!retry.should_retry(Some(1), Some("permission denied")));
+        assert!(
+            !retry.should_retry(None, None),
+            "timeouts need unfiltered retry"
+        );
+    }
+
+    #[test]
+    fn retry_validation() {
+        let source = |retry: &str| -> Source {
+            let yaml = format!("cmd:\n  args: [x]\n  retry: {retry}\n");
+            serde_yml::from_str(&yaml).unwrap()
+        };
+        assert!(source("{ attempts: 2 }").validate().is_ok());
+        assert!(source("{ attempts: 0 }").validate().is_err());
+        assert!(
+            source("{ attempts: 2, on_stderr: ['('] }")
+                .validate()
+                .unwrap_err()
+                .contains("on_stderr")
+        );
+    }
+
+    #[test]
+    fn stderr_patterns_compile_once_and_round_trip() {
+        let pattern: StderrPattern = serde_yml::from_str("'^50[0-9]'").unwrap();
+        assert!(pattern.error().is_none());
+        assert!(pattern.is_match("503 Service Unavailable"));
+        assert_eq!(serde_yml::to_string(&pattern).unwrap().trim(), "^50[0-9]");
+
+        let invalid: StderrPattern = serde_yml::from_str("'('").unwrap();
+        assert_eq!(invalid.as_str(), "(");
+        assert!(invalid.error().is_some(), "reported by validation, not serde");
+    }
+
+    #[test]
+    fn for_each_forms() {
+        let config: Config = serde_yml::from_str(
+            "variables:\n  \"T_{{ item }}\":\n    for_each: [a, b]\n    default:\n      literal: x\n  \"U_{{ item }}\":\n    for_each:\n      cmd: [ls, tenants]\n    default:\n      literal: y\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            &config.variables["T_{{ item }}"].for_each,
+            Some(ForEach::Items(items)) if items == &["a", "b"]
+        ));
+        assert!(matches!(
+            &config.variables["U_{{ item }}"].for_each,
+            Some(ForEach::Source(Source::Cmd(_)))
+        ));
+    }
 }
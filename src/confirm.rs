@@ -0,0 +1,134 @@
+//! Confirmation gate for environments marked `protected` in the config.
+
+use std::io::BufRead;
+use std::io::IsTerminal;
+use std::io::Write;
+
+use tracing::info;
+
+use envoke_cli::error::ConfirmError;
+
+/// Require confirmation before acting on a protected environment.
+///
+/// With `assume_yes` the gate passes without prompting. Otherwise stdin must be
+/// a terminal: the user is asked on stderr to type the environment name, and
+/// anything else aborts. Every successful confirmation is recorded in tracing
+/// output together with the method used.
+pub fn confirm_protected(
+    environment: &str,
+    action: &str,
+    assume_yes: bool,
+) -> Result<(), ConfirmError> {
+    let stdin = std::io::stdin();
+    let is_tty = stdin.is_terminal();
+    confirm(
+        environment,
+        action,
+        assume_yes,
+        is_tty,
+        &mut stdin.lock(),
+        &mut std::io::stderr(),
+    )
+}
+
+/// Testable core of [`confirm_protected`] with injectable I/O.
+fn confirm(
+    environment: &str,
+    action: &str,
+    assume_yes: bool,
+    is_tty: bool,
+    input: &mut impl BufRead,
+    prompt: &mut impl Write,
+) -> Result<(), ConfirmError> {
+    if assume_yes {
+        info!(
+            environment,
+            action,
+            method = "--yes",
+            "protected environment confirmed"
+        );
+        return Ok(());
+    }
+    if !is_tty {
+        return Err(ConfirmError::NonInteractive {
+            environment: environment.to_owned(),
+        });
+    }
+
+    let io_err = |e: std::io::Error| ConfirmError::Io {
+        reason: e.to_string(),
+    };
+    write!(
+        prompt,
+        "Environment '{environment}' is protected. Type its name to {action}: "
+    )
+    .map_err(io_err)?;
+    prompt.flush().map_err(io_err)?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer).map_err(io_err)?;
+    if answer.trim() != environment {
+        return Err(ConfirmError::Mismatch {
+            environment: environment.to_owned(),
+        });
+    }
+
+    info!(
+        environment,
+        action,
+        method = "interactive",
+        "protected environment confirmed"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(answer: &str, assume_yes: bool, is_tty: bool) -> (Result<(), ConfirmError>, String) {
+        let mut input = answer.as_bytes();
+        let mut prompt = Vec::new();
+        let result = confirm("prod", "exec", assume_yes, is_tty, &mut input, &mut prompt);
+        (result, String::from_utf8(prompt).unwrap())
+    }
+
+    #[test]
+    fn matching_name_confirms() {
+        let (result, prompt) = run("prod\n", false, true);
+        assert!(result.is_ok());
+        assert!(prompt.contains("'prod' is protected"));
+    }
+
+    #[test]
+    fn surrounding_whitespace_is_ignored() {
+        let (result, _) = run("  prod  \n", false, true);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn wrong_name_is_rejected() {
+        let (result, _) = run("prd\n", false, true);
+        assert!(matches!(result, Err(ConfirmError::Mismatch { .. })));
+    }
+
+    #[test]
+    fn empty_input_is_rejected() {
+        let (result, _) = run("", false, true);
+        assert!(matches!(result, Err(ConfirmError::Mismatch { .. })));
+    }
+
+    #[test]
+    fn non_tty_without_yes_is_refused() {
+        let (result, prompt) = run("prod\n", false, false);
+        assert!(matches!(result, Err(ConfirmError::NonInteractive { .. })));
+        assert!(prompt.is_empty(), "must not prompt without a terminal");
+    }
+
+    #[test]
+    fn yes_skips_prompt() {
+        let (result, prompt) = run("", true, false);
+        assert!(result.is_ok());
+        assert!(prompt.is_empty());
+    }
+}
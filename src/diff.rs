@@ -0,0 +1,235 @@
+//! Variable-by-variable comparison of two resolutions, as printed by
+//! `envoke diff`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::resolve::Origin;
+use crate::resolve::Resolved;
+
+/// A variable as resolved on one side of a [`Diff`].
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Side {
+    /// The value, or `None` when it is redacted.
+    pub value: Option<String>,
+    /// Kind of the source that produced the value.
+    pub source: &'static str,
+    /// Where in the selection chain the source was found.
+    pub origin: Origin,
+}
+
+/// How a variable differs between the two sides.
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum Change {
+    /// Only resolved on the second side.
+    Added { b: Side },
+    /// Only resolved on the first side.
+    Removed { a: Side },
+    /// Resolved on both sides to different values.
+    Changed { a: Side, b: Side },
+}
+
+/// One variable that differs.
+#[derive(Debug, serde::Serialize)]
+pub struct Entry {
+    pub name: String,
+    /// Whether the variable is marked `secret`.
+    pub secret: bool,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+/// Differences between two resolutions, labelled `a` and `b` (typically the
+/// environment names).
+#[derive(Debug, serde::Serialize)]
+pub struct Diff {
+    pub a: String,
+    pub b: String,
+    /// Variables that differ, in name order.
+    pub entries: Vec<Entry>,
+    /// Number of variables resolved to the same value on both sides.
+    pub unchanged: usize,
+}
+
+impl Diff {
+    /// Compare two resolutions by variable name. A variable counts as changed
+    /// when its value differs; a different source alone is not a change.
+    /// Values of `secret` variables are redacted unless `show_secrets`.
+    pub fn new(
+        a: impl Into<String>,
+        a_resolved: &[Resolved],
+        b: impl Into<String>,
+        b_resolved: &[Resolved],
+        show_secrets: bool,
+    ) -> Self {
+        let side = |r: &Resolved, secret: bool| Side {
+            value: (show_secrets || !secret).then(|| r.value.clone()),
+            source: r.source,
+            origin: r.origin.clone(),
+        };
+        let mut names: BTreeMap<&str, (Option<&Resolved>, Option<&Resolved>)> = BTreeMap::new();
+        for r in a_resolved {
+            names.entry(&r.name).or_default().0 = Some(r);
+        }
+        for r in b_resolved {
+            names.entry(&r.name).or_default().1 = Some(r);
+        }
+
+        let mut entries = Vec::new();
+        let mut unchanged = 0;
+        for (name, sides) in names {
+            let secret = sides.0.is_some_and(|r| r.secret) || sides.1.is_some_and(|r| r.secret);
+            let change = match sides {
+                (Some(a), Some(b)) if a.value == b.value => {
+                    unchanged += 1;
+                    continue;
+                }
+                (Some(a), Some(b)) => Change::Changed {
+                    a: side(a, secret),
+                    b: side(b, secret),
+                },
+                (Some(a), None) => Change::Removed { a: side(a, secret) },
+                (None, Some(b)) => Change::Added { b: side(b, secret) },
+                (None, None) => unreachable!("every name comes from one side"),
+            };
+            entries.push(Entry {
+                name: name.to_owned(),
+                secret,
+                change,
+            });
+        }
+        Self {
+            a: a.into(),
+            b: b.into(),
+            entries,
+            unchanged,
+        }
+    }
+
+    /// Whether both sides resolved the same variables to the same values.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn count(&self, kind: fn(&Change) -> bool) -> usize {
+        self.entries.iter().filter(|e| kind(&e.change)).count()
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{value:?}")?,
+            None => f.write_str("<redacted>")?,
+        }
+        write!(f, "  ({}, {})", self.source, self.origin)
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "--- {}", self.a)?;
+        writeln!(f, "+++ {}", self.b)?;
+        let width = self.a.len().max(self.b.len()) + 1;
+        for entry in &self.entries {
+            let name = &entry.name;
+            match &entry.change {
+                Change::Added { b } => writeln!(f, "+ {name} = {b}")?,
+                Change::Removed { a } => writeln!(f, "- {name} = {a}")?,
+                Change::Changed { a, b } => {
+                    writeln!(f, "~ {name}")?;
+                    writeln!(f, "    {:<width$} {a}", format!("{}:", self.a))?;
+                    writeln!(f, "    {:<width$} {b}", format!("{}:", self.b))?;
+                }
+            }
+        }
+        writeln!(
+            f,
+            "{} added, {} removed, {} changed, {} unchanged",
+            self.count(|c| matches!(c, Change::Added { .. })),
+            self.count(|c| matches!(c, Change::Removed { .. })),
+            self.count(|c| matches!(c, Change::Changed { .. })),
+            self.unchanged,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolved(name: &str, value: &str, secret: bool) -> Resolved {
+        Resolved {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            source: "literal",
+            origin: Origin::Env {
+                environment: "x".to_owned(),
+            },
+            secret,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_classifies_and_redacts() {
+        let a = [
+            resolved("SAME", "1", false),
+            resolved("GONE", "old", false),
+            resolved("URL", "http://staging", false),
+            resolved("TOKEN", "s3cr3t", true),
+        ];
+        let b = [
+            resolved("SAME", "1", false),
+            resolved("NEW", "fresh", false),
+            resolved("URL", "http://prod", false),
+            resolved("TOKEN", "pr0d", true),
+        ];
+        let diff = Diff::new("staging", &a, "prod", &b, false);
+        assert!(!diff.is_empty());
+        assert_eq!(diff.unchanged, 1);
+        let text = diff.to_string();
+        assert_eq!(
+            text,
+            "\
+--- staging
++++ prod
+- GONE = \"old\"  (literal, envs.x)
++ NEW = \"fresh\"  (literal, envs.x)
+~ TOKEN
+    staging: <redacted>  (literal, envs.x)
+    prod:    <redacted>  (literal, envs.x)
+~ URL
+    staging: \"http://staging\"  (literal, envs.x)
+    prod:    \"http://prod\"  (literal, envs.x)
+1 added, 1 removed, 2 changed, 1 unchanged
+"
+        );
+        assert!(!text.contains("s3cr3t"));
+
+        let json = serde_json::to_value(&diff).unwrap();
+        assert_eq!(json["entries"][2]["name"], "TOKEN");
+        assert_eq!(json["entries"][2]["change"], "changed");
+        assert_eq!(json["entries"][2]["secret"], true);
+        assert!(json["entries"][2]["a"]["value"].is_null());
+        assert_eq!(json["entries"][3]["b"]["origin"]["path"], "envs.x");
+
+        let shown = Diff::new("staging", &a, "prod", &b, true);
+        assert!(shown.to_string().contains("\"s3cr3t\""));
+    }
+
+    #[test]
+    fn test_diff_ignores_source_only_changes() {
+        let a = [resolved("A", "1", false)];
+        let mut b = [resolved("A", "1", false)];
+        b[0].source = "cmd";
+        let diff = Diff::new("a", &a, "b", &b, false);
+        assert!(diff.is_empty());
+        assert!(
+            diff.to_string()
+                .ends_with("0 added, 0 removed, 0 changed, 1 unchanged\n")
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//! The resolution plan for one environment, as printed by `--dry-run`.
+
+use std::fmt;
+
+use crate::config::Source;
+use crate::resolve::Origin;
+
+/// Every node of one resolution in the order it would be resolved, with the
+/// source selected for it. Built by
+/// [`Resolver::dry_run`](crate::Resolver::dry_run) without running anything.
+#[derive(Debug)]
+pub struct DryRun {
+    pub environment: String,
+    /// Variables and the bundles they read, each after its dependencies.
+    pub steps: Vec<Step>,
+    /// `for_each` definitions whose items a `cmd:` / `sh:` source would
+    /// list, with that source. The variables they expand to are not planned.
+    pub unlisted: Vec<(String, Source)>,
+}
+
+/// One node of the plan: a variable, or a bundle (named `bundle:<name>`).
+#[derive(Debug)]
+pub struct Step {
+    pub name: String,
+    /// Where in the selection chain the source was found; `None` for a
+    /// bundle.
+    pub origin: Option<Origin>,
+    pub source: Source,
+    /// Variables and bundles this node reads, in name order.
+    pub dependencies: Vec<String>,
+    pub export: bool,
+    pub secret: bool,
+}
+
+impl DryRun {
+    /// Number of `cmd:` / `sh:` sources the resolution would run, counting
+    /// `for_each` lists. Cached results may spare some of them.
+    #[must_use]
+    pub fn commands(&self) -> usize {
+        let sources = self.steps.iter().map(|step| &step.source);
+        sources
+            .chain(self.unlisted.iter().map(|(_, source)| source))
+            .filter(|source| matches!(source, Source::Cmd(_) | Source::Sh(_)))
+            .count()
+    }
+}
+
+/// `args` as a user would type them into a POSIX shell: space-separated,
+/// with each argument that needs it single-quoted.
+#[must_use]
+pub fn command_line(args: &[String]) -> String {
+    let quote = |arg: &String| {
+        let plain = !arg.is_empty()
+            && arg
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c));
+        if plain {
+            arg.clone()
+        } else {
+            format!("'{}'", crate::filters::shell_escape(arg))
+        }
+    };
+    args.iter().map(quote).collect::<Vec<_>>().join(" ")
+}
+
+/// Write the body of `name`'s source, indented: the value of a literal
+/// (redacted when `secret`), the arguments of a command, the text of a script
+/// or template, or the bundle key read.
+fn write_source(
+    f: &mut fmt::Formatter<'_>,
+    name: &str,
+    source: &Source,
+    secret: bool,
+) -> fmt::Result {
+    const INDENT: &str = "       ";
+    match source {
+        Source::Literal(_) if secret => writeln!(f, "{INDENT}<redacted>"),
+        Source::Literal(value) => writeln!(f, "{INDENT}{value:?}"),
+        Source::Cmd(cmd) => writeln!(f, "{INDENT}{}", command_line(cmd.args())),
+        Source::Sh(sh) => sh
+            .script()
+            .lines()
+            .try_for_each(|line| writeln!(f, "{INDENT}{line}")),
+        Source::Template(template) => template
+            .lines()
+            .try_for_each(|line| writeln!(f, "{INDENT}{line}")),
+        Source::FromBundle(r) => {
+            let key = r.key.as_deref().unwrap_or(name);
+            writeln!(f, "{INDENT}key '{key}' of bundle '{}'", r.bundle)
+        }
+        Source::Skip => Ok(()),
+    }
+}
+
+impl fmt::Display for DryRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Plan for {} (nothing was run):", self.environment)?;
+        for (name, source) in &self.unlisted {
+            writeln!(
+                f,
+                "  for_each {name}  {}  (items not listed)",
+                source.kind()
+            )?;
+            write_source(f, name, source, false)?;
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            let mut notes = vec![
+                step.origin
+                    .as_ref()
+                    .map_or_else(|| "bundle".to_owned(), ToString::to_string),
+            ];
+            if !step.dependencies.is_empty() {
+                notes.push(format!("depends on {}", step.dependencies.join(", ")));
+            }
+            if step.origin.is_some() && !step.export {
+                notes.push("not exported".to_owned());
+            }
+            if step.secret {
+                notes.push("secret".to_owned());
+            }
+            writeln!(
+                f,
+                "{:>4}. {}  {}  ({})",
+                i + 1,
+                step.name,
+                step.source.kind(),
+                notes.join("; ")
+            )?;
+            write_source(f, &step.name, &step.source, step.secret)?;
+        }
+        writeln!(
+            f,
+            "{} step(s); {} command(s) and script(s) would run",
+            self.steps.len(),
+            self.commands()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CmdSource;
+
+    fn step(name: &str, source: Source, secret: bool) -> Step {
+        Step {
+            name: name.to_owned(),
+            origin: Some(Origin::Default),
+            source,
+            dependencies: Vec::new(),
+            export: true,
+            secret,
+        }
+    }
+
+    #[test]
+    fn test_dry_run_shows_commands_and_redacts_secret_literals() {
+        let mut url = step(
+            "URL",
+            Source::Template("{{ TOKEN }}@host".to_owned()),
+            false,
+        );
+        url.dependencies = vec!["TOKEN".to_owned()];
+        let plan = DryRun {
+            environment: "prod".to_owned(),
+            steps: vec![
+                step("TOKEN", Source::Literal("s3cr3t".to_owned()), true),
+                step(
+                    "REV",
+                    Source::Cmd(CmdSource::Args(vec![
+                        "git".to_owned(),
+                        "rev-parse".to_owned(),
+                    ])),
+                    false,
+                ),
+                url,
+            ],
+            unlisted: Vec::new(),
+        };
+        assert_eq!(
+            plan.to_string(),
+            "\
+Plan for prod (nothing was run):
+   1. TOKEN  literal  (default; secret)
+       <redacted>
+   2. REV  cmd  (default)
+       git rev-parse
+   3. URL  template  (default; depends on TOKEN)
+       {{ TOKEN }}@host
+3 step(s); 1 command(s) and script(s) would run
+"
+        );
+    }
+
+    #[test]
+    fn test_command_line_quotes_only_what_needs_it() {
+        let args = ["cargo", "run", "--", "it's", "", "a b", "$HOME", "--x=1"].map(String::from);
+        assert_eq!(
+            command_line(&args),
+            r"cargo run -- 'it'\''s' '' 'a b' '$HOME' --x=1"
+        );
+    }
+}
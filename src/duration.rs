@@ -0,0 +1,159 @@
+//! Human-friendly durations for config fields such as `timeout: 30s`.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use schemars::Schema;
+use schemars::SchemaGenerator;
+use schemars::json_schema;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+
+/// A duration written as a number with a unit suffix (`500ms`, `30s`, `15m`,
+/// `2h`, `1d`), optionally chained (`1m30s`). A bare integer is read as
+/// seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HumanDuration(pub Duration);
+
+impl HumanDuration {
+    /// Parse the textual form accepted in config files.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the problem if `s` is not a valid duration.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("empty duration".to_owned());
+        }
+        if let Ok(secs) = s.parse::<u64>() {
+            return Ok(Self(Duration::from_secs(secs)));
+        }
+
+        let mut total = Duration::ZERO;
+        let mut rest = s;
+        while !rest.is_empty() {
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            if digits == 0 {
+                return Err(format!("invalid duration `{s}`: expected a number"));
+            }
+            let value: u64 = rest[..digits]
+                .parse()
+                .map_err(|e| format!("invalid duration `{s}`: {e}"))?;
+            rest = &rest[digits..];
+            let unit_len = rest
+                .find(|c: char| c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let part = match &rest[..unit_len] {
+                "ms" => Duration::from_millis(value),
+                "s" => Duration::from_secs(value),
+                "m" => Duration::from_secs(value.saturating_mul(60)),
+                "h" => Duration::from_secs(value.saturating_mul(60 * 60)),
+                "d" => Duration::from_secs(value.saturating_mul(60 * 60 * 24)),
+                "" => return Err(format!("invalid duration `{s}`: missing unit")),
+                unit => {
+                    return Err(format!(
+                        "invalid duration `{s}`: unknown unit `{unit}` (use ms, s, m, h, or d)"
+                    ));
+                }
+            };
+            total = total.saturating_add(part);
+            rest = &rest[unit_len..];
+        }
+        Ok(Self(total))
+    }
+}
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for HumanDuration {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Seconds(u64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Seconds(secs) => Ok(Self(Duration::from_secs(secs))),
+            Raw::Text(s) => Self::parse(&s).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl Serialize for HumanDuration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let millis = self.0.as_millis();
+        if millis.is_multiple_of(1000) {
+            serializer.serialize_str(&format!("{}s", millis / 1000))
+        } else {
+            serializer.serialize_str(&format!("{millis}ms"))
+        }
+    }
+}
+
+impl JsonSchema for HumanDuration {
+    fn schema_name() -> Cow<'static, str> {
+        "Duration".into()
+    }
+
+    fn json_schema(_generator: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "description": "A duration such as `500ms`, `30s`, `15m`, `2h`, `1d`, or `1m30s`. A bare integer is read as seconds.",
+            "anyOf": [
+                { "type": "string", "pattern": "^([0-9]+(ms|s|m|h|d))+$" },
+                { "type": "integer", "minimum": 0 }
+            ]
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_units_and_chains() {
+        let cases = [
+            ("500ms", Duration::from_millis(500)),
+            ("30s", Duration::from_secs(30)),
+            ("15m", Duration::from_mins(15)),
+            ("2h", Duration::from_hours(2)),
+            ("1d", Duration::from_hours(24)),
+            ("1m30s", Duration::from_secs(90)),
+            ("45", Duration::from_secs(45)),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(HumanDuration::parse(input).unwrap().0, expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        for input in ["", "s", "10x", "1.5s", "m10", "5m3"] {
+            assert!(
+                HumanDuration::parse(input).is_err(),
+                "{input:?} should fail"
+            );
+        }
+    }
+
+    #[test]
+    fn deserializes_from_string_or_integer() {
+        let d: HumanDuration = serde_yml::from_str("15m").unwrap();
+        assert_eq!(d.0, Duration::from_mins(15));
+        let d: HumanDuration = serde_yml::from_str("12").unwrap();
+        assert_eq!(d.0, Duration::from_secs(12));
+        assert!(serde_yml::from_str::<HumanDuration>("soon").is_err());
+    }
+}
@@ -17,6 +17,38 @@ fn format_override_names(names: &[String]) -> String {
     }
 }
 
+fn format_interrupted(command: Option<&[String]>) -> String {
+    match command {
+        Some(command) => format!("command `{command:?}` was killed because envoke was interrupted"),
+        None => "not resolved because envoke was interrupted".to_owned(),
+    }
+}
+
+fn format_killed(killed: &[String]) -> String {
+    if killed.is_empty() {
+        "no sources were running".to_owned()
+    } else {
+        format!("terminated in-flight sources: {}", killed.join(", "))
+    }
+}
+
+fn format_flags(flags: &[String]) -> String {
+    if flags.is_empty() {
+        String::new()
+    } else {
+        format!(" (with {})", flags.join(" "))
+    }
+}
+
+fn format_attempts(history: &[String]) -> String {
+    let lines: Vec<String> = history
+        .iter()
+        .enumerate()
+        .map(|(i, failure)| format!("attempt {}: {failure}", i + 1))
+        .collect();
+    lines.join("\n")
+}
+
 /// Errors that occur during variable resolution.
 #[derive(Debug, thiserror::Error, miette::Diagnostic)]
 #[error("{variable} [{environment}]: {kind}")]
@@ -28,7 +60,10 @@ pub struct ResolveError {
 }
 
 /// The specific kind of resolution failure.
+///
+/// New kinds may be added in minor releases.
 #[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[non_exhaustive]
 pub enum ResolveErrorKind {
     #[error("no configuration for this environment")]
     #[diagnostic(
@@ -43,6 +78,8 @@ pub enum ResolveErrorKind {
         help("check that the command exists and is executable")
     )]
     CmdFailed {
+        /// Kind of source that ran the command: `cmd` or `sh`.
+        source_kind: &'static str,
         command: Vec<String>,
         reason: String,
     },
@@ -53,11 +90,46 @@ pub enum ResolveErrorKind {
         help("check the command's stderr output above for details")
     )]
     CmdNonZero {
+        /// Kind of source that ran the command: `cmd` or `sh`.
+        source_kind: &'static str,
         command: Vec<String>,
         exit_code: Option<i32>,
         stderr: String,
     },
 
+    #[error("command `{command:?}` timed out after {timeout:?}")]
+    #[diagnostic(
+        code(envoke::timeout),
+        help("the command was killed; raise `timeout` or check why it hangs")
+    )]
+    Timeout {
+        command: Vec<String>,
+        timeout: std::time::Duration,
+    },
+
+    #[error("command `{command:?}` was killed because another source failed")]
+    #[diagnostic(
+        code(envoke::cancelled),
+        help("fix the other error; without `--fail-fast`, running sources finish first")
+    )]
+    Cancelled { command: Vec<String> },
+
+    #[error("{}", format_interrupted(command.as_deref()))]
+    #[diagnostic(code(envoke::interrupted))]
+    Interrupted {
+        /// The command that was killed, or `None` if the source never
+        /// started.
+        command: Option<Vec<String>>,
+    },
+
+    #[error("{last} (gave up after {} attempts)", history.len())]
+    #[diagnostic(code(envoke::retries_exhausted), help("{}", format_attempts(history)))]
+    RetriesExhausted {
+        /// Failure of every attempt, in order; the last entry is `last`.
+        history: Vec<String>,
+        last: Box<ResolveErrorKind>,
+    },
+
     #[error("circular dependency: {}", format_cycle(chain))]
     #[diagnostic(
         code(envoke::circular_dependency),
@@ -72,6 +144,15 @@ pub enum ResolveErrorKind {
     )]
     UnknownReference { name: String },
 
+    #[error("not defined in envoke.yaml")]
+    #[diagnostic(
+        code(envoke::unknown_variable),
+        help(
+            "variables generated by for_each only exist when their definition passes the tag filter"
+        )
+    )]
+    UnknownVariable,
+
     #[error("template error: {reason}")]
     #[diagnostic(
         code(envoke::template_render),
@@ -86,6 +167,48 @@ pub enum ResolveErrorKind {
     )]
     InvalidSource { reason: String },
 
+    #[error("for_each item '{item}': {reason}")]
+    #[diagnostic(
+        code(envoke::for_each),
+        help("check the `{{{{ item }}}}` templates in this variable's name and sources")
+    )]
+    ForEach { item: String, reason: String },
+
+    #[error("for_each expands to '{name}', which is already defined by '{other}'")]
+    #[diagnostic(
+        code(envoke::for_each_conflict),
+        help("rename one of the variables or remove the duplicate item")
+    )]
+    ForEachConflict { name: String, other: String },
+
+    #[error("unknown bundle: {name}")]
+    #[diagnostic(
+        code(envoke::unknown_bundle),
+        help("check that the bundle is defined under `bundles` in envoke.yaml")
+    )]
+    UnknownBundle { name: String },
+
+    #[error("could not parse output of bundle '{bundle}': {reason}")]
+    #[diagnostic(
+        code(envoke::bundle_parse),
+        help("check that the bundle's output matches its `format`")
+    )]
+    BundleParse { bundle: String, reason: String },
+
+    #[error("bundle '{bundle}' has no key '{key}'")]
+    #[diagnostic(
+        code(envoke::bundle_key_missing),
+        help("set `key` on the `from_bundle` source, or check the bundle's output")
+    )]
+    MissingBundleKey { bundle: String, key: String },
+
+    #[error("depends on '{dependency}', which failed to resolve")]
+    #[diagnostic(
+        code(envoke::dependency_failed),
+        help("fix the error reported for '{dependency}'")
+    )]
+    DependencyFailed { dependency: String },
+
     #[error("conflicting overrides: {}", format_override_names(names))]
     #[diagnostic(
         code(envoke::conflicting_overrides),
@@ -102,3 +225,196 @@ pub struct ResolveErrors {
     #[related]
     pub errors: Vec<ResolveError>,
 }
+
+/// envoke caught SIGINT or SIGTERM while resolving.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("interrupted while resolving '{environment}'")]
+#[diagnostic(code(envoke::interrupted), help("{}", format_killed(killed)))]
+pub struct Interrupted {
+    pub environment: String,
+    /// Variables whose source processes were killed, in name order.
+    pub killed: Vec<String>,
+}
+
+impl Interrupted {
+    /// Collect the variables reported as killed by
+    /// [`ResolveErrorKind::Interrupted`].
+    pub fn new(environment: impl Into<String>, errors: &[ResolveError]) -> Self {
+        let killed = errors
+            .iter()
+            .filter(|e| matches!(e.kind, ResolveErrorKind::Interrupted { command: Some(_) }))
+            .map(|e| e.variable.clone())
+            .collect();
+        Self {
+            environment: environment.into(),
+            killed,
+        }
+    }
+}
+
+/// Some environments failed when rendering several in one invocation
+/// (`render --all-envs` / `--envs`). Each failure is a related diagnostic.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("{} of {total} environment(s) failed: {}", failed.len(), failed.join(", "))]
+#[diagnostic(code(envoke::environments_failed))]
+pub struct EnvironmentsFailed {
+    /// Number of environments rendered.
+    pub total: usize,
+    /// Names of the environments that failed, in render order.
+    pub failed: Vec<String>,
+    #[related]
+    pub errors: Vec<Box<dyn miette::Diagnostic + Send + Sync>>,
+}
+
+/// `render --check` found a file that differs from freshly rendered output.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("{} is out of date", path.display())]
+#[diagnostic(
+    code(envoke::out_of_date),
+    help("re-render it with the same flags, using --output instead of --check")
+)]
+pub struct OutOfDate {
+    pub path: std::path::PathBuf,
+}
+
+/// `diff --exit-code` found differences between the two sides.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("{a} and {b} differ in {count} variable(s)")]
+#[diagnostic(code(envoke::environments_differ))]
+pub struct EnvironmentsDiffer {
+    /// Labels of the two sides, as in the diff header.
+    pub a: String,
+    pub b: String,
+    /// Number of variables that differ.
+    pub count: usize,
+}
+
+/// A problem found by [`lint`](crate::lint) in a template.
+#[derive(Debug, Clone, thiserror::Error, miette::Diagnostic)]
+#[non_exhaustive]
+pub enum LintKind {
+    #[error("unknown `meta` field '{field}'")]
+    #[diagnostic(
+        code(envoke::lint::unknown_meta_field),
+        help("`meta` has: {}", fields.join(", "))
+    )]
+    UnknownMetaField {
+        field: String,
+        /// Fields `meta` does have in this kind of template.
+        fields: Vec<String>,
+    },
+
+    #[error("unknown filter '{name}'")]
+    #[diagnostic(
+        code(envoke::lint::unknown_filter),
+        help("check the spelling; see the README for the filters available in templates")
+    )]
+    UnknownFilter { name: String },
+
+    #[error("filter '{name}' has no effect: its result is never used")]
+    #[diagnostic(
+        code(envoke::lint::unused_filter),
+        help("use the variable it is assigned to, or remove the filter")
+    )]
+    UnusedFilter { name: String },
+
+    #[error("invalid template: {reason}")]
+    #[diagnostic(
+        code(envoke::lint::syntax),
+        help("check the template syntax — Jinja2/minijinja is used")
+    )]
+    Syntax { reason: String },
+}
+
+/// A lint finding, located in the template it was found in.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("{location}: {kind}")]
+#[diagnostic(forward(kind))]
+pub struct TemplateLint {
+    /// Where the template is defined: `VAR (envs.prod)` for a variable's
+    /// source, or the path of an output template.
+    pub location: String,
+    pub kind: LintKind,
+    #[source_code]
+    pub template: miette::NamedSource<String>,
+    #[label]
+    pub span: miette::SourceSpan,
+}
+
+/// Wrapper for every finding of one lint run, displayed as related
+/// diagnostics.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("{} template problem(s) found", self.findings.len())]
+#[diagnostic(code(envoke::lint_failed))]
+pub struct LintErrors {
+    #[related]
+    pub findings: Vec<TemplateLint>,
+}
+
+/// A problem found by [`check`](crate::check), with the flags it shows up
+/// under.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("{error}{}", format_flags(flags))]
+#[diagnostic(forward(error))]
+pub struct CheckFinding {
+    pub error: ResolveError,
+    /// `--override` / `--tag` flags needed to hit the problem, e.g.
+    /// `["--tag vault"]`. Empty when it shows up without any.
+    pub flags: Vec<String>,
+}
+
+/// Wrapper for every finding of one check run, displayed as related
+/// diagnostics.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("{} problem(s) found", self.findings.len())]
+#[diagnostic(code(envoke::check_failed))]
+pub struct CheckErrors {
+    #[related]
+    pub findings: Vec<CheckFinding>,
+}
+
+/// Errors from loading a config file.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[non_exhaustive]
+pub enum ConfigError {
+    #[error("failed to read {}", path.display())]
+    #[diagnostic(code(envoke::config_read))]
+    Read {
+        path: std::path::PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("failed to parse {}", path.display())]
+    #[diagnostic(
+        code(envoke::config_parse),
+        help("run `envoke schema` for the JSON Schema of envoke.yaml")
+    )]
+    Parse {
+        path: std::path::PathBuf,
+        #[source]
+        source: serde_yml::Error,
+    },
+}
+
+/// Errors from the confirmation gate on protected environments.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub enum ConfirmError {
+    #[error("environment '{environment}' is protected and stdin is not a terminal")]
+    #[diagnostic(
+        code(envoke::protected_non_interactive),
+        help("pass `--yes` to confirm non-interactively")
+    )]
+    NonInteractive { environment: String },
+
+    #[error("confirmation did not match protected environment '{environment}'")]
+    #[diagnostic(
+        code(envoke::confirmation_mismatch),
+        help("type the environment name exactly as shown, or pass `--yes`")
+    )]
+    Mismatch { environment: String },
+
+    #[error("failed to read confirmation: {reason}")]
+    #[diagnostic(code(envoke::confirmation_io))]
+    Io { reason: String },
+}
@@ -6,7 +6,7 @@ use std::process::Command;
 use miette::Context;
 use miette::IntoDiagnostic;
 
-use crate::resolve::Resolved;
+use envoke_cli::Resolved;
 
 /// Build a `Command` whose environment is the current process env plus the
 /// resolved variables overlaid on top (resolved values win on name
@@ -68,6 +68,7 @@ mod tests {
             name: name.to_owned(),
             value: value.to_owned(),
             description: None,
+            ..Default::default()
         }
     }
 
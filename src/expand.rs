@@ -0,0 +1,306 @@
+//! Expansion of `for_each` variable definitions into concrete variables.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use minijinja::Value;
+use minijinja::machinery::Token;
+use minijinja::machinery::WhitespaceConfig;
+use minijinja::syntax::SyntaxConfig;
+
+use crate::config::CmdSource;
+use crate::config::ForEach;
+use crate::config::ShSource;
+use crate::config::Source;
+use crate::config::Variable;
+use crate::error::ResolveError;
+use crate::error::ResolveErrorKind;
+
+/// Replace every `for_each` definition with one variable per item.
+///
+/// Definitions rejected by `is_selected` are left unexpanded, so a `for_each`
+/// source never runs for variables that are filtered out anyway. Items listed
+/// by a source come from `list_items`, one per non-empty line of its output.
+/// An expanded name that collides with another variable is an error.
+pub fn expand(
+    variables: &BTreeMap<String, Variable>,
+    environment: &str,
+    functions: Option<&Path>,
+    is_selected: impl Fn(&Variable) -> bool,
+    mut list_items: impl FnMut(&str, &Source) -> Result<String, ResolveError>,
+) -> Result<BTreeMap<String, Variable>, Vec<ResolveError>> {
+    let mut expanded = BTreeMap::new();
+    // Which definition each name came from, for conflict messages.
+    let mut origin: BTreeMap<String, &str> = BTreeMap::new();
+    let mut errors = Vec::new();
+
+    for (name, variable) in variables {
+        if variable.for_each.is_none() || !is_selected(variable) {
+            origin.insert(name.clone(), name);
+            expanded.insert(name.clone(), variable.clone());
+        }
+    }
+
+    let mut env = crate::filters::environment(true, functions);
+    env.set_keep_trailing_newline(true);
+    // In `sh:` scripts every `{{ ... }}` is quoted for where it stands (see
+    // `quote_substitutions`), so an item cannot inject commands.
+    let mut script_env = crate::filters::environment(true, functions);
+    script_env.set_keep_trailing_newline(true);
+    for quoting in [Quoting::None, Quoting::Single, Quoting::Double] {
+        script_env.add_filter(quoting.filter(), move |value: Value| quoting.quote(&value));
+    }
+
+    for (name, variable) in variables {
+        let Some(for_each) = &variable.for_each else {
+            continue;
+        };
+        if !is_selected(variable) {
+            continue;
+        }
+        let error = |kind| ResolveError {
+            variable: name.clone(),
+            environment: environment.to_owned(),
+            kind,
+        };
+        let items = match for_each {
+            ForEach::Items(items) => items.clone(),
+            ForEach::Source(source) => match list_items(name, source) {
+                Ok(output) => output
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(ToOwned::to_owned)
+                    .collect(),
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            },
+        };
+
+        for item in &items {
+            let instance = Instance {
+                env: &env,
+                script_env: &script_env,
+                item,
+            };
+            let result = instance
+                .render(name)
+                .and_then(|expanded_name| Ok((expanded_name, instance.variable(variable)?)));
+            let (expanded_name, instance) = match result {
+                Ok(pair) => pair,
+                Err(e) => {
+                    errors.push(error(ResolveErrorKind::ForEach {
+                        item: item.clone(),
+                        reason: e.to_string(),
+                    }));
+                    continue;
+                }
+            };
+            if let Some(other) = origin.get(&expanded_name) {
+                errors.push(error(ResolveErrorKind::ForEachConflict {
+                    name: expanded_name,
+                    other: (*other).to_owned(),
+                }));
+                continue;
+            }
+            origin.insert(expanded_name.clone(), name);
+            expanded.insert(expanded_name, instance);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(expanded)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Shell quoting in effect at some point of an `sh:` script.
+#[derive(Clone, Copy)]
+enum Quoting {
+    None,
+    Single,
+    Double,
+}
+
+impl Quoting {
+    /// The quoting in effect after `text`.
+    fn after(mut self, text: &str) -> Self {
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            self = match (self, c) {
+                (Self::None | Self::Double, '\\') => {
+                    chars.next();
+                    self
+                }
+                (Self::None, '\'') => Self::Single,
+                (Self::None, '"') => Self::Double,
+                (Self::Single, '\'') | (Self::Double, '"') => Self::None,
+                _ => self,
+            };
+        }
+        self
+    }
+
+    /// Name of the filter that quotes a value substituted here.
+    fn filter(self) -> &'static str {
+        match self {
+            Self::None => "envoke_sh_word",
+            Self::Single => "envoke_sh_in_single_quotes",
+            Self::Double => "envoke_sh_in_double_quotes",
+        }
+    }
+
+    /// `value` as literal text at this point: a single-quoted word, closing
+    /// and reopening the surrounding quotes if there are any. A double-quoted
+    /// string is closed too, since `$(...)` inside it starts afresh.
+    fn quote(self, value: &Value) -> Result<String, minijinja::Error> {
+        if value.is_undefined() {
+            return Err(minijinja::Error::from(minijinja::ErrorKind::UndefinedError));
+        }
+        let word = crate::filters::shell_escape(&value.to_string());
+        Ok(match self {
+            Self::None => format!("'{word}'"),
+            Self::Single => word,
+            Self::Double => format!("\"'{word}'\""),
+        })
+    }
+}
+
+/// Pass every `{{ ... }}` of an `sh:` script through the [`Quoting`] filter
+/// for where it stands, so a substitution is one literal shell word whether
+/// it is written bare (`echo {{ item }}`) or inside a quoted string
+/// (`echo "tenant-{{ item }}"`).
+fn quote_substitutions(script: &str) -> Result<String, minijinja::Error> {
+    let mut out = String::with_capacity(script.len());
+    let mut quoting = Quoting::None;
+    let mut copied = 0;
+    let mut expression = None;
+    let tokens =
+        minijinja::machinery::tokenize(script, false, SyntaxConfig, WhitespaceConfig::default());
+    for token in tokens {
+        let (token, span) = token?;
+        match token {
+            Token::TemplateData(text) => quoting = quoting.after(text),
+            Token::VariableStart => expression = Some(span.end_offset as usize),
+            Token::VariableEnd => {
+                if let Some(start) = expression.take() {
+                    let end = span.start_offset as usize;
+                    out.push_str(&script[copied..start]);
+                    let _ = write!(out, " ({}) | {} ", &script[start..end], quoting.filter());
+                    copied = end;
+                }
+            }
+            _ => {}
+        }
+    }
+    out.push_str(&script[copied..]);
+    Ok(out)
+}
+
+/// One item of a `for_each` expansion.
+struct Instance<'a> {
+    env: &'a minijinja::Environment<'a>,
+    /// Renders `sh:` scripts; has the filters `quote_substitutions` uses.
+    script_env: &'a minijinja::Environment<'a>,
+    item: &'a str,
+}
+
+impl Instance<'_> {
+    fn render(&self, template: &str) -> Result<String, minijinja::Error> {
+        self.env
+            .render_str(template, minijinja::context! { item => self.item })
+    }
+
+    fn render_script(&self, script: &str) -> Result<String, minijinja::Error> {
+        self.script_env.render_str(
+            &quote_substitutions(script)?,
+            minijinja::context! { item => self.item },
+        )
+    }
+
+    fn variable(&self, variable: &Variable) -> Result<Variable, minijinja::Error> {
+        let mut out = variable.clone();
+        out.for_each = None;
+        out.description = variable
+            .description
+            .as_deref()
+            .map(|d| self.render(d))
+            .transpose()?;
+        out.default = variable
+            .default
+            .as_ref()
+            .map(|s| self.source(s))
+            .transpose()?;
+        for source in out.envs.values_mut() {
+            *source = self.source(source)?;
+        }
+        for ovr in out.overrides.values_mut() {
+            ovr.default = ovr.default.as_ref().map(|s| self.source(s)).transpose()?;
+            for source in ovr.envs.values_mut() {
+                *source = self.source(source)?;
+            }
+        }
+        Ok(out)
+    }
+
+    /// Substitute the item into a source. Template sources are not rendered
+    /// here, since they may reference other variables; instead `item` is
+    /// bound around them for resolution time.
+    fn source(&self, source: &Source) -> Result<Source, minijinja::Error> {
+        Ok(match source {
+            Source::Literal(value) => Source::Literal(self.render(value)?),
+            Source::Template(template) => {
+                // A string literal with the template delimiters escaped, so
+                // no item can end the `with` tag or open a block of its own.
+                let item = serde_json::to_string(self.item)
+                    .expect("strings serialize")
+                    .replace('{', "\\u007b")
+                    .replace('}', "\\u007d")
+                    .replace('%', "\\u0025")
+                    .replace('#', "\\u0023");
+                Source::Template(format!(
+                    "{{% with item = {item} %}}{template}{{% endwith %}}"
+                ))
+            }
+            Source::Cmd(CmdSource::Args(args)) => Source::Cmd(CmdSource::Args(self.all(args)?)),
+            Source::Cmd(CmdSource::Options(options)) => {
+                let mut options = options.clone();
+                options.args = self.all(&options.args)?;
+                for value in options.env.values_mut() {
+                    *value = self.render(value)?;
+                }
+                options.stdin = options.stdin.map(|s| self.render(&s)).transpose()?;
+                options.cwd = options
+                    .cwd
+                    .map(|cwd| self.render(&cwd.to_string_lossy()).map(Into::into))
+                    .transpose()?;
+                options.needs = self.all(&options.needs)?;
+                Source::Cmd(CmdSource::Options(options))
+            }
+            Source::Sh(ShSource::Script(script)) => {
+                Source::Sh(ShSource::Script(self.render_script(script)?))
+            }
+            Source::Sh(ShSource::Options(options)) => {
+                let mut options = options.clone();
+                options.script = self.render_script(&options.script)?;
+                options.needs = self.all(&options.needs)?;
+                Source::Sh(ShSource::Options(options))
+            }
+            Source::FromBundle(r) => {
+                let mut r = r.clone();
+                r.bundle = self.render(&r.bundle)?;
+                r.key = r.key.map(|k| self.render(&k)).transpose()?;
+                Source::FromBundle(r)
+            }
+            Source::Skip => Source::Skip,
+        })
+    }
+
+    fn all(&self, values: &[String]) -> Result<Vec<String>, minijinja::Error> {
+        values.iter().map(|v| self.render(v)).collect()
+    }
+}
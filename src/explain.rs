@@ -0,0 +1,167 @@
+//! A step-by-step account of how one variable gets its value, as printed by
+//! `envoke explain`.
+
+use std::fmt;
+
+use crate::config::Source;
+use crate::resolve::Origin;
+
+/// How a variable is selected and resolved for one environment. Built by
+/// [`Resolver::explain`](crate::Resolver::explain).
+#[derive(Debug, serde::Serialize)]
+pub struct Explanation {
+    pub name: String,
+    pub environment: String,
+    pub description: Option<String>,
+    pub export: bool,
+    pub tags: TagDecision,
+    /// Every override defined on the variable, in name order.
+    pub overrides: Vec<OverrideCheck>,
+    pub selection: Selection,
+    /// Variables (and `bundle:` nodes) the selected source depends on.
+    pub dependencies: Vec<String>,
+    /// The resolved value, when requested and the variable is resolved.
+    pub value: Option<String>,
+}
+
+/// Outcome of the tag filter for one variable.
+#[derive(Debug, serde::Serialize)]
+pub struct TagDecision {
+    /// Tags declared on the variable.
+    pub tags: Vec<String>,
+    /// Declared tags that are active.
+    pub matched: Vec<String>,
+    pub included: bool,
+}
+
+/// One override defined on the variable.
+#[derive(Debug, serde::Serialize)]
+pub struct OverrideCheck {
+    pub name: String,
+    pub active: bool,
+    /// Whether the override has an `envs` entry for the environment.
+    pub has_env: bool,
+    pub has_default: bool,
+}
+
+/// Which source the selection chain settled on.
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Selection {
+    Selected {
+        origin: Origin,
+        source: Box<Source>,
+    },
+    /// More than one active override is defined on the variable.
+    Conflict {
+        names: Vec<String>,
+    },
+    /// Nothing in the chain has an entry for the environment.
+    NoConfig,
+}
+
+impl Explanation {
+    fn reason(&self) -> String {
+        let env = &self.environment;
+        let active = self.overrides.iter().find(|o| o.active);
+        let fell_through = active.map_or(String::new(), |o| {
+            format!(
+                "override '{}' has neither an entry for '{env}' nor a default; ",
+                o.name
+            )
+        });
+        match &self.selection {
+            Selection::Selected { origin, source } => {
+                let why = match origin {
+                    Origin::OverrideEnv { name, .. } => {
+                        format!("override '{name}' is active and has an entry for '{env}'")
+                    }
+                    Origin::OverrideDefault { name } => format!(
+                        "override '{name}' is active but has no entry for '{env}', so its default applies"
+                    ),
+                    Origin::Env { .. } => {
+                        format!("{fell_through}the variable has an entry for '{env}'")
+                    }
+                    Origin::Default => format!(
+                        "{fell_through}the variable has no entry for '{env}', so its default applies"
+                    ),
+                };
+                if matches!(**source, Source::Skip) {
+                    format!("{why}; the source is `skip`, so the variable is left out")
+                } else {
+                    why
+                }
+            }
+            Selection::Conflict { names } => format!(
+                "active overrides {} are all defined on this variable",
+                names.join(", ")
+            ),
+            Selection::NoConfig => {
+                format!("{fell_through}the variable has no entry for '{env}' and no default")
+            }
+        }
+    }
+}
+
+fn list_or_none(items: &[String]) -> String {
+    if items.is_empty() {
+        "(none)".to_owned()
+    } else {
+        items.join(", ")
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} [{}]", self.name, self.environment)?;
+        if let Some(description) = &self.description {
+            writeln!(f, "  description: {description}")?;
+        }
+
+        let decision = if !self.tags.included {
+            "excluded"
+        } else if self.tags.tags.is_empty() {
+            "untagged, always included"
+        } else {
+            "included"
+        };
+        writeln!(
+            f,
+            "  tags:        {} (active: {}) -> {decision}",
+            list_or_none(&self.tags.tags),
+            list_or_none(&self.tags.matched),
+        )?;
+
+        for o in &self.overrides {
+            let state = if o.active { "active" } else { "inactive" };
+            let env = if o.has_env { "yes" } else { "no" };
+            let default = if o.has_default { "yes" } else { "no" };
+            writeln!(
+                f,
+                "  override:    {} ({state}; entry for {}: {env}, default: {default})",
+                o.name, self.environment
+            )?;
+        }
+
+        match &self.selection {
+            Selection::Selected { origin, source } => {
+                let source = serde_json::to_string(source).map_err(|_| fmt::Error)?;
+                writeln!(f, "  source:      {origin} = {source}")?;
+            }
+            Selection::Conflict { .. } | Selection::NoConfig => {
+                writeln!(f, "  source:      (none)")?;
+            }
+        }
+        writeln!(f, "  because:     {}", self.reason())?;
+        writeln!(f, "  depends on:  {}", list_or_none(&self.dependencies))?;
+        writeln!(
+            f,
+            "  exported:    {}",
+            if self.export { "yes" } else { "no" }
+        )?;
+        if let Some(value) = &self.value {
+            writeln!(f, "  value:       {value}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,436 @@
+//! Filters and global functions shared by every template envoke renders:
+//! `template:` sources and output templates (presets and `--template`) see
+//! exactly the same set.
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use base64::Engine as _;
+
+use minijinja::Error;
+use minijinja::ErrorKind;
+use minijinja::Value;
+use minijinja::value::Rest;
+use sha2::Digest;
+use sha2::Sha256;
+
+/// Global functions whose result depends on the machine, the filesystem, or
+/// the clock rather than on the config alone.
+const IMPURE_FUNCTIONS: [&str; 5] = ["env", "file", "now", "uuid", "hostname"];
+
+/// Template environment with every envoke filter and function registered, on
+/// top of minijinja's builtins (which include `split`). With `strict`,
+/// reading an undefined value (a misspelled `meta` field, say) is an error
+/// instead of an empty string.
+///
+/// `file()` paths are relative to `base_dir`. Without one (under
+/// `settings.pure_templates`), calling any of the [`IMPURE_FUNCTIONS`] is an
+/// error, so rendering depends on nothing but the config.
+pub(crate) fn environment(
+    strict: bool,
+    base_dir: Option<&Path>,
+) -> minijinja::Environment<'static> {
+    let mut env = minijinja::Environment::new();
+    env.add_filter("shell_escape", shell_escape);
+    env.add_filter("dotenv_escape", dotenv_escape);
+    env.add_filter("wrap", wrap);
+    env.add_filter("b64encode", b64encode);
+    env.add_filter("b64decode", b64decode);
+    env.add_filter("sha256", sha256);
+    env.add_filter("hex", hex);
+    env.add_filter("json_get", json_get);
+    env.add_filter("regex_replace", regex_replace);
+    env.add_filter("date", date);
+    env.add_filter("quote_yaml", quote_yaml);
+    if let Some(base_dir) = base_dir {
+        let base_dir = base_dir.to_path_buf();
+        env.add_function("env", env_var);
+        env.add_function("file", move |path: &str| read_file(&base_dir, path));
+        env.add_function("now", now);
+        env.add_function("uuid", uuid);
+        env.add_function("hostname", hostname);
+    } else {
+        for name in IMPURE_FUNCTIONS {
+            env.add_function(name, move |_: Rest<Value>| -> Result<Value, Error> {
+                Err(invalid(format!(
+                    "{name}() is disabled by settings.pure_templates"
+                )))
+            });
+        }
+    }
+    if strict {
+        env.set_undefined_behavior(minijinja::UndefinedBehavior::Strict);
+    }
+    env
+}
+
+/// Whether `name` is a global function in templates, either a minijinja
+/// builtin (`range`, `dict`, ...) or one of envoke's. A variable of the same
+/// name shadows it.
+pub(crate) fn is_function(name: &str) -> bool {
+    static FUNCTIONS: LazyLock<HashSet<String>> = LazyLock::new(|| {
+        environment(false, None)
+            .globals()
+            .map(|(name, _)| name.to_owned())
+            .collect()
+    });
+    FUNCTIONS.contains(name)
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidOperation, message.into())
+}
+
+/// Escape a value for safe inclusion in a single-quoted shell string.
+///
+/// Embedded single quotes are replaced with `'\''` (end quote, escaped quote,
+/// start quote).
+pub(crate) fn shell_escape(value: &str) -> String {
+    value.replace('\'', "'\\''")
+}
+
+/// Encode a value as a portable `.env` token, delimiters included.
+///
+/// Returns the fully-quoted form — do not wrap the output in additional
+/// quotes when using this filter in templates.
+///
+/// Encoding rule:
+/// - If the value contains no `'` and no newline, emit `'value'` (single-
+///   quoted literal). Every byte passes through unchanged; `$` is never
+///   expanded inside single quotes by any dotenv parser.
+/// - Otherwise, emit `"value"` with the conservative escape set shared by
+///   `dotenvy`, `godotenv`, `python-dotenv`, and similar parsers: `\\`, `\"`,
+///   `\$`, and `\n` for newline. All other bytes (including literal tab and CR)
+///   pass through as-is. `\t` and `\r` escape *sequences* are deliberately not
+///   emitted because `dotenvy` rejects unknown escapes as parse errors.
+pub(crate) fn dotenv_escape(value: &str) -> String {
+    let needs_double_quote = value.contains('\'') || value.contains('\n');
+    let mut out = String::with_capacity(value.len() + 2);
+    if !needs_double_quote {
+        out.push('\'');
+        out.push_str(value);
+        out.push('\'');
+        return out;
+    }
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str(r"\\"),
+            '"' => out.push_str(r#"\""#),
+            '$' => out.push_str(r"\$"),
+            '\n' => out.push_str(r"\n"),
+            other => out.push(other),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Wraps a string into lines of maximum `width` columns.
+pub(crate) fn wrap(s: &str, width: usize) -> Vec<String> {
+    textwrap::wrap(s, width)
+        .into_iter()
+        .map(|c| c.to_string())
+        .collect()
+}
+
+/// Standard base64 (RFC 4648) with padding.
+fn b64encode(value: &str) -> String {
+    base64::engine::general_purpose::STANDARD.encode(value)
+}
+
+/// Decode standard base64, padded or not. Whitespace is ignored; the decoded
+/// bytes must be UTF-8.
+fn b64decode(value: &str) -> Result<String, Error> {
+    let digits: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+    let bytes = base64::engine::general_purpose::STANDARD_PAD_INDIFFERENT
+        .decode(digits)
+        .map_err(|e| invalid(format!("b64decode: {e}")))?;
+    String::from_utf8(bytes).map_err(|_| invalid("b64decode: decoded bytes are not UTF-8"))
+}
+
+/// Lowercase hex of the value's UTF-8 bytes.
+fn hex(value: &str) -> String {
+    hex::encode(value)
+}
+
+/// Lowercase hex SHA-256 digest of the value.
+fn sha256(value: &str) -> String {
+    hex::encode(Sha256::digest(value.as_bytes()))
+}
+
+/// Parse the value as JSON and return the element at `path`: dot-separated
+/// keys and array indices (`items.0.name`), or a JSON pointer when it starts
+/// with `/`. A missing element is undefined, so `default` applies.
+fn json_get(value: &str, path: &str) -> Result<Value, Error> {
+    let json: serde_json::Value = serde_json::from_str(value)
+        .map_err(|e| invalid(format!("json_get: value is not JSON: {e}")))?;
+    let found = if path.starts_with('/') || path.is_empty() {
+        json.pointer(path)
+    } else {
+        path.split('.').try_fold(&json, |node, key| match node {
+            serde_json::Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+            _ => node.get(key),
+        })
+    };
+    Ok(found.map_or(Value::UNDEFINED, Value::from_serialize))
+}
+
+/// Replace every match of the regular expression `pattern` with
+/// `replacement`, which may refer to groups as `$1` or `${name}`.
+fn regex_replace(value: &str, pattern: &str, replacement: &str) -> Result<String, Error> {
+    let regex = regex::Regex::new(pattern)
+        .map_err(|e| invalid(format!("regex_replace: invalid pattern: {e}")))?;
+    Ok(regex.replace_all(value, replacement).into_owned())
+}
+
+/// Reformat an RFC 3339 timestamp (such as `meta.timestamp`) with a
+/// strftime-style `format`, keeping its UTC offset.
+fn date(value: &str, format: &str) -> Result<String, Error> {
+    let timestamp = chrono::DateTime::parse_from_rfc3339(value)
+        .map_err(|e| invalid(format!("date: {value:?} is not an RFC 3339 timestamp: {e}")))?;
+    strftime("date", &timestamp, format)
+}
+
+fn strftime(
+    caller: &str,
+    timestamp: &chrono::DateTime<chrono::FixedOffset>,
+    format: &str,
+) -> Result<String, Error> {
+    let items = chrono::format::StrftimeItems::new(format)
+        .parse()
+        .map_err(|_| invalid(format!("{caller}: invalid format {format:?}")))?;
+    Ok(timestamp.format_with_items(items.iter()).to_string())
+}
+
+/// Quote a value as a YAML double-quoted scalar, delimiters included. JSON
+/// string syntax is valid YAML, so this escapes exactly like `tojson` on a
+/// string.
+fn quote_yaml(value: &str) -> String {
+    serde_json::to_string(value).expect("strings serialize")
+}
+
+/// `env(name, default)`: the environment variable `name`, or `default` when
+/// it is unset. Without a default, an unset variable is undefined.
+fn env_var(name: &str, default: Option<Value>) -> Result<Value, Error> {
+    match std::env::var(name) {
+        Ok(value) => Ok(Value::from(value)),
+        Err(std::env::VarError::NotPresent) => Ok(default.unwrap_or(Value::UNDEFINED)),
+        Err(std::env::VarError::NotUnicode(_)) => {
+            Err(invalid(format!("env: ${name} is not valid UTF-8")))
+        }
+    }
+}
+
+/// `file(path)`: contents of the file at `path`, relative to `base_dir`,
+/// trailing newline included.
+fn read_file(base_dir: &Path, path: &str) -> Result<String, Error> {
+    let full = base_dir.join(path);
+    std::fs::read_to_string(&full)
+        .map_err(|e| invalid(format!("file: cannot read {}: {e}", full.display())))
+}
+
+/// `now(format)`: the current local time, as RFC 3339 or with a
+/// strftime-style `format`.
+fn now(format: Option<&str>) -> Result<String, Error> {
+    let now = chrono::Local::now().fixed_offset();
+    match format {
+        Some(format) => strftime("now", &now, format),
+        None => Ok(now.to_rfc3339()),
+    }
+}
+
+/// `uuid()`: a random (version 4) UUID in lowercase hyphenated form.
+fn uuid() -> Result<String, Error> {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).map_err(|e| invalid(format!("uuid: {e}")))?;
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+/// `hostname()`: the machine's host name.
+#[cfg(unix)]
+fn hostname() -> Result<String, Error> {
+    let mut buf = [0u8; 256];
+    // SAFETY: `buf` is valid for writes of `buf.len()` bytes.
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return Err(invalid(format!(
+            "hostname: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8(buf[..len].to_vec()).map_err(|_| invalid("hostname: not valid UTF-8"))
+}
+
+/// `hostname()`: the machine's host name.
+#[cfg(not(unix))]
+fn hostname() -> Result<String, Error> {
+    std::env::var("COMPUTERNAME").map_err(|_| invalid("hostname: %COMPUTERNAME% is not set"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str) -> Result<String, Error> {
+        environment(false, Some(Path::new("."))).render_str(template, ())
+    }
+
+    #[test]
+    fn test_base64_round_trip() {
+        for (plain, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foobar", "Zm9vYmFy"),
+            ("søl", "c8O4bA=="),
+        ] {
+            assert_eq!(b64encode(plain), encoded);
+            assert_eq!(b64decode(encoded).unwrap(), plain);
+        }
+        assert_eq!(b64decode("Zm9v\nYmFy").unwrap(), "foobar");
+        // Every remainder length, unpadded.
+        assert_eq!(b64decode("Zg").unwrap(), "f");
+        assert_eq!(b64decode("Zm8").unwrap(), "fo");
+        assert_eq!(b64decode("Zm9vYg").unwrap(), "foob");
+        assert!(b64decode("Z").is_err());
+        assert!(b64decode("Zm9v!").is_err());
+        assert!(b64decode("/w==").is_err());
+    }
+
+    #[test]
+    fn test_hashing_and_hex() {
+        assert_eq!(
+            render("{{ 'abc' | sha256 }}").unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(render("{{ 'hi!' | hex }}").unwrap(), "686921");
+        assert_eq!(hex(""), "");
+        assert_eq!(hex("\n"), "0a");
+    }
+
+    #[test]
+    fn test_json_get() {
+        let json = r#"{"db": {"hosts": [{"name": "a"}, {"name": "b"}], "port": 5432}}"#;
+        assert_eq!(json_get(json, "db.hosts.1.name").unwrap().to_string(), "b");
+        assert_eq!(json_get(json, "/db/port").unwrap().to_string(), "5432");
+        assert!(json_get(json, "db.missing").unwrap().is_undefined());
+        assert!(json_get(json, "db.hosts.x").unwrap().is_undefined());
+        assert!(json_get("not json", "a").is_err());
+        assert_eq!(
+            render(r#"{{ '{"a": 1}' | json_get("b") | default("none") }}"#).unwrap(),
+            "none"
+        );
+    }
+
+    #[test]
+    fn test_regex_replace_and_split() {
+        assert_eq!(
+            render(r#"{{ 'db-01.internal' | regex_replace("-(\\d+)", "_$1") }}"#).unwrap(),
+            "db_01.internal"
+        );
+        assert!(render(r#"{{ 'x' | regex_replace("(", "") }}"#).is_err());
+        assert_eq!(
+            render("{{ ('a,b,c' | split(',') | list)[1] }}").unwrap(),
+            "b"
+        );
+    }
+
+    #[test]
+    fn test_date() {
+        let ts = "2025-06-15T10:30:00+02:00";
+        assert_eq!(date(ts, "%Y%m%d").unwrap(), "20250615");
+        assert_eq!(date(ts, "%H:%M %z").unwrap(), "10:30 +0200");
+        assert!(date("yesterday", "%Y").is_err());
+        assert!(date(ts, "%Q").is_err());
+    }
+
+    #[test]
+    fn test_quote_yaml() {
+        assert_eq!(quote_yaml("plain"), r#""plain""#);
+        assert_eq!(quote_yaml("a \"b\"\nc: d"), r#""a \"b\"\nc: d""#);
+        let parsed: String = serde_yml::from_str(&quote_yaml("yes: no # not a comment")).unwrap();
+        assert_eq!(parsed, "yes: no # not a comment");
+    }
+
+    #[test]
+    fn test_env_function() {
+        // `PATH` is set wherever cargo can run the tests; only its presence
+        // is checked since the value differs between machines.
+        assert!(!render(r#"{{ env("PATH") }}"#).unwrap().is_empty());
+        assert_eq!(
+            render(r#"{{ env("ENVOKE_TEST_SURELY_UNSET", "fallback") }}"#).unwrap(),
+            "fallback"
+        );
+        assert_eq!(
+            render(r#"{{ env("ENVOKE_TEST_SURELY_UNSET") | default("d") }}"#).unwrap(),
+            "d"
+        );
+        let strict = environment(true, Some(Path::new(".")));
+        assert!(
+            strict
+                .render_str(r#"{{ env("ENVOKE_TEST_SURELY_UNSET") }}"#, ())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_file_is_relative_to_base_dir() {
+        let dir = std::env::temp_dir().join(format!("envoke-file-fn-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("VERSION"), "1.2.3\n").unwrap();
+        let env = environment(false, Some(&dir));
+        assert_eq!(
+            env.render_str(r#"{{ file("VERSION") | trim }}"#, ())
+                .unwrap(),
+            "1.2.3"
+        );
+        let err = env.render_str(r#"{{ file("missing") }}"#, ()).unwrap_err();
+        assert!(err.to_string().contains("cannot read"), "{err}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_now_uuid_hostname() {
+        let now = render("{{ now() }}").unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(&now).is_ok(), "{now}");
+        assert_eq!(render(r#"{{ now("%Y") }}"#).unwrap().len(), 4);
+        assert!(render(r#"{{ now("%Q") }}"#).is_err());
+
+        let id = uuid().unwrap();
+        assert_eq!(id.len(), 36);
+        assert_eq!(
+            id.split('-').map(str::len).collect::<Vec<_>>(),
+            [8, 4, 4, 4, 12]
+        );
+        assert_eq!(&id[14..15], "4");
+        assert!(matches!(&id[19..20], "8" | "9" | "a" | "b"), "{id}");
+        assert_ne!(uuid().unwrap(), id);
+
+        assert!(!render("{{ hostname() }}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_pure_environment_rejects_impure_functions() {
+        let env = environment(false, None);
+        for name in IMPURE_FUNCTIONS {
+            let err = env
+                .render_str(&format!("{{{{ {name}() }}}}"), ())
+                .unwrap_err();
+            assert!(err.to_string().contains("pure_templates"), "{err}");
+        }
+        assert_eq!(env.render_str("{{ 'a' | upper }}", ()).unwrap(), "A");
+    }
+}
@@ -0,0 +1,42 @@
+//! SIGINT / SIGTERM handling, so an interrupted envoke kills the sources it
+//! is running instead of leaving them behind.
+
+use std::sync::Once;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static INSTALL: Once = Once::new();
+
+/// Exit status used when envoke stops because of a signal (128 + SIGINT).
+pub const EXIT_CODE: i32 = 130;
+
+/// Catch SIGINT and SIGTERM (Ctrl-C and Ctrl-Break on Windows) for the rest
+/// of the process.
+///
+/// The first signal only marks the process as interrupted: running `cmd:` /
+/// `sh:` sources are killed, no new ones start, and resolution fails with
+/// [`ResolveErrorKind::Interrupted`](crate::ResolveErrorKind::Interrupted). A
+/// second signal exits immediately with [`EXIT_CODE`]. Installing more than
+/// once is a no-op.
+///
+/// # Errors
+///
+/// Fails if the handler cannot be registered, e.g. because another handler
+/// was already installed through the `ctrlc` crate.
+pub fn install() -> Result<(), ctrlc::Error> {
+    let mut result = Ok(());
+    INSTALL.call_once(|| {
+        result = ctrlc::set_handler(|| {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                std::process::exit(EXIT_CODE);
+            }
+        });
+    });
+    result
+}
+
+/// Whether a signal has been caught since [`install`].
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,70 @@
+//! Resolve variables from an `envoke.yaml` config without spawning the
+//! `envoke` binary.
+//!
+//! The CLI is a thin consumer of this library: load a [`Config`], resolve it
+//! for one environment with a [`Resolver`], and optionally render the result
+//! with one of the built-in [`Format`]s.
+//!
+//! ```no_run
+//! use envoke_cli::Config;
+//! use envoke_cli::Format;
+//! use envoke_cli::Resolver;
+//! use envoke_cli::render::Meta;
+//! use envoke_cli::render::RenderContext;
+//! use envoke_cli::render::render_format;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let config = Config::from_path("envoke.yaml")?;
+//! let resolved = Resolver::new(&config, "local")
+//!     .tags(["vault"])
+//!     .parallel(false)
+//!     .resolve()?;
+//! for var in &resolved {
+//!     println!("{}={}", var.name, var.value);
+//! }
+//!
+//! let ctx = RenderContext {
+//!     resolved,
+//!     meta: Meta {
+//!         environment: "local".to_owned(),
+//!         ..Meta::default()
+//!     },
+//!     errors: Vec::new(),
+//!     strict: false,
+//!     pure: false,
+//! };
+//! print!("{}", render_format(&ctx, Format::Dotenv)?);
+//! # Ok(())
+//! # }
+//! ```
+
+mod bundle;
+pub mod cache;
+pub mod check;
+pub mod config;
+pub mod diff;
+pub mod dry_run;
+pub mod duration;
+pub mod error;
+mod expand;
+pub mod explain;
+mod filters;
+pub mod interrupt;
+pub mod lint;
+mod process;
+pub mod render;
+pub mod resolve;
+pub mod timings;
+
+pub use config::Config;
+pub use dry_run::DryRun;
+pub use error::ConfigError;
+pub use error::ResolveError;
+pub use error::ResolveErrorKind;
+pub use error::ResolveErrors;
+pub use explain::Explanation;
+pub use render::Format;
+pub use resolve::Origin;
+pub use resolve::Partial;
+pub use resolve::Resolved;
+pub use resolve::Resolver;
@@ -0,0 +1,558 @@
+//! Static checks over templates, as run by `envoke lint`.
+//!
+//! Templates are parsed, never rendered, so every branch is checked whatever
+//! the environment, and nothing is resolved. Findings point at the offending
+//! span of the template string.
+
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::Path;
+
+use minijinja::machinery::WhitespaceConfig;
+use minijinja::machinery::ast::CallArg;
+use minijinja::machinery::ast::Expr;
+use minijinja::machinery::ast::Stmt;
+use minijinja::syntax::SyntaxConfig;
+
+use crate::config::Config;
+use crate::config::ForEach;
+use crate::config::Source;
+use crate::error::LintKind;
+use crate::error::TemplateLint;
+use crate::resolve::Origin;
+
+/// What a kind of template may use.
+struct Vocabulary {
+    /// Provides the filter registry.
+    env: minijinja::Environment<'static>,
+    meta_fields: Vec<String>,
+}
+
+impl Vocabulary {
+    /// Variable templates (`template:` sources).
+    fn variables() -> Self {
+        Self {
+            env: crate::filters::environment(false, Some(Path::new("."))),
+            meta_fields: field_names(&crate::resolve::TemplateMeta::default()),
+        }
+    }
+
+    /// Output templates (`render --template`).
+    fn output() -> Self {
+        Self {
+            env: crate::filters::environment(false, Some(Path::new("."))),
+            meta_fields: field_names(&crate::render::Meta::default()),
+        }
+    }
+
+    fn has_filter(&self, name: &str) -> bool {
+        // The registry cannot be listed; calling a filter without arguments
+        // fails with `UnknownFilter` only when it does not exist.
+        !matches!(
+            self.env.empty_state().apply_filter(name, &[]),
+            Err(e) if e.kind() == minijinja::ErrorKind::UnknownFilter
+        )
+    }
+}
+
+fn field_names(value: &impl serde::Serialize) -> Vec<String> {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::Object(map)) => map.keys().cloned().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Lint every `template:` source in `config` (the same sources as
+/// [`Config::sources`]): those of variables, in variable order, including
+/// their `for_each` lists, then those of bundles.
+#[must_use]
+pub fn lint_config(config: &Config) -> Vec<TemplateLint> {
+    let vocabulary = Vocabulary::variables();
+    let mut findings = Vec::new();
+    let mut lint_source = |location: String, source: &Source| {
+        if let Source::Template(template) = source {
+            findings.extend(lint(&location, template, &vocabulary));
+        }
+    };
+    for (name, variable) in &config.variables {
+        if let Some(ForEach::Source(source)) = &variable.for_each {
+            lint_source(format!("{name} (for_each)"), source);
+        }
+        let mut sources: Vec<(Origin, &Source)> = Vec::new();
+        sources.extend(variable.default.iter().map(|s| (Origin::Default, s)));
+        sources.extend(variable.envs.iter().map(|(environment, s)| {
+            let origin = Origin::Env {
+                environment: environment.clone(),
+            };
+            (origin, s)
+        }));
+        for (override_name, o) in &variable.overrides {
+            sources.extend(o.default.iter().map(|s| {
+                let origin = Origin::OverrideDefault {
+                    name: override_name.clone(),
+                };
+                (origin, s)
+            }));
+            sources.extend(o.envs.iter().map(|(environment, s)| {
+                let origin = Origin::OverrideEnv {
+                    name: override_name.clone(),
+                    environment: environment.clone(),
+                };
+                (origin, s)
+            }));
+        }
+        for (origin, source) in sources {
+            lint_source(format!("{name} ({origin})"), source);
+        }
+    }
+    for (name, bundle) in &config.bundles {
+        if let Some(source) = &bundle.default {
+            lint_source(format!("bundle:{name} ({})", Origin::Default), source);
+        }
+        for (environment, source) in &bundle.envs {
+            lint_source(format!("bundle:{name} (envs.{environment})"), source);
+        }
+    }
+    findings
+}
+
+/// Lint an output template, as passed to `render --template`. `location`
+/// names it in findings, typically its path.
+#[must_use]
+pub fn lint_output_template(location: &str, template: &str) -> Vec<TemplateLint> {
+    lint(location, template, &Vocabulary::output())
+}
+
+fn lint(location: &str, template: &str, vocabulary: &Vocabulary) -> Vec<TemplateLint> {
+    let problems = match minijinja::machinery::parse(
+        template,
+        location,
+        SyntaxConfig,
+        WhitespaceConfig::default(),
+    ) {
+        Ok(ast) => {
+            let mut linter = Linter {
+                source: template,
+                vocabulary,
+                problems: Vec::new(),
+                filters: Vec::new(),
+                assignments: Vec::new(),
+                reads: HashSet::new(),
+            };
+            linter.stmt(&ast);
+            linter.unused_filters();
+            linter.problems.sort_by_key(|(_, span)| span.start);
+            linter.problems
+        }
+        Err(e) => {
+            let reason = e
+                .detail()
+                .map_or_else(|| e.kind().to_string(), str::to_owned);
+            let span = e.range().unwrap_or(0..template.len());
+            vec![(LintKind::Syntax { reason }, span)]
+        }
+    };
+    problems
+        .into_iter()
+        .map(|(kind, span)| TemplateLint {
+            location: location.to_owned(),
+            kind,
+            template: miette::NamedSource::new(location, template.to_owned()),
+            span: span.into(),
+        })
+        .collect()
+}
+
+/// Walks a template's AST, collecting problems with their byte ranges.
+struct Linter<'a> {
+    source: &'a str,
+    vocabulary: &'a Vocabulary,
+    problems: Vec<(LintKind, Range<usize>)>,
+    /// Every filter applied, with the span of its name, in walk order.
+    filters: Vec<(String, Range<usize>)>,
+    /// Variables assigned by `set` / `with`, with the filters (indices into
+    /// `filters`) applied to their value.
+    assignments: Vec<(String, Range<usize>)>,
+    /// Every variable read anywhere in the template.
+    reads: HashSet<String>,
+}
+
+fn range(span: minijinja::machinery::Span) -> Range<usize> {
+    span.start_offset as usize..span.end_offset as usize
+}
+
+fn is_meta(expr: &Expr<'_>) -> bool {
+    matches!(expr, Expr::Var(var) if var.id == "meta")
+}
+
+impl Linter<'_> {
+    fn stmts(&mut self, stmts: &[Stmt<'_>]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt<'_>) {
+        match stmt {
+            Stmt::Template(t) => self.stmts(&t.children),
+            Stmt::EmitExpr(e) => self.expr(&e.expr),
+            Stmt::ForLoop(f) => {
+                self.expr(&f.iter);
+                if let Some(filter) = &f.filter_expr {
+                    self.expr(filter);
+                }
+                self.stmts(&f.body);
+                self.stmts(&f.else_body);
+            }
+            Stmt::IfCond(i) => {
+                self.expr(&i.expr);
+                self.stmts(&i.true_body);
+                self.stmts(&i.false_body);
+            }
+            Stmt::WithBlock(w) => {
+                for (target, value) in &w.assignments {
+                    self.assign(target, value);
+                }
+                self.stmts(&w.body);
+            }
+            Stmt::Set(s) => self.assign(&s.target, &s.expr),
+            Stmt::SetBlock(s) => {
+                if let Some(filter) = &s.filter {
+                    self.assign(&s.target, filter);
+                }
+                self.stmts(&s.body);
+            }
+            Stmt::AutoEscape(a) => self.stmts(&a.body),
+            Stmt::FilterBlock(f) => {
+                let start = self.filters.len();
+                self.expr(&f.filter);
+                let empty = f
+                    .body
+                    .iter()
+                    .all(|stmt| matches!(stmt, Stmt::EmitRaw(r) if r.raw.trim().is_empty()));
+                if empty {
+                    self.unused(start..self.filters.len());
+                }
+                self.stmts(&f.body);
+            }
+            Stmt::Block(b) => self.stmts(&b.body),
+            Stmt::Macro(m) => {
+                for default in &m.defaults {
+                    self.expr(default);
+                }
+                self.stmts(&m.body);
+            }
+            Stmt::CallBlock(c) => {
+                self.expr(&c.call.expr);
+                self.args(&c.call.args);
+                self.stmts(&c.macro_decl.body);
+            }
+            Stmt::Do(d) => {
+                self.expr(&d.call.expr);
+                self.args(&d.call.args);
+            }
+            _ => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr<'_>) {
+        match expr {
+            Expr::Var(var) => {
+                self.reads.insert(var.id.to_owned());
+            }
+            Expr::Const(_) => {}
+            Expr::GetAttr(g) => {
+                if is_meta(&g.expr) {
+                    let end = g.span().end_offset as usize;
+                    self.meta_field(g.name, end.saturating_sub(g.name.len())..end);
+                }
+                self.expr(&g.expr);
+            }
+            Expr::GetItem(g) => {
+                if is_meta(&g.expr)
+                    && let Expr::Const(c) = &g.subscript_expr
+                    && let Some(field) = c.value.as_str()
+                {
+                    self.meta_field(field, range(c.span()));
+                }
+                self.expr(&g.expr);
+                self.expr(&g.subscript_expr);
+            }
+            Expr::Filter(f) => {
+                let span = self.name_in(f.name, range(f.span()));
+                if !self.vocabulary.has_filter(f.name) {
+                    let name = f.name.to_owned();
+                    self.problems
+                        .push((LintKind::UnknownFilter { name }, span.clone()));
+                }
+                self.filters.push((f.name.to_owned(), span));
+                if let Some(inner) = &f.expr {
+                    self.expr(inner);
+                }
+                self.args(&f.args);
+            }
+            Expr::Test(t) => {
+                self.expr(&t.expr);
+                self.args(&t.args);
+            }
+            Expr::Slice(s) => {
+                self.expr(&s.expr);
+                for bound in [&s.start, &s.stop, &s.step].into_iter().flatten() {
+                    self.expr(bound);
+                }
+            }
+            Expr::UnaryOp(u) => self.expr(&u.expr),
+            Expr::BinOp(b) => {
+                self.expr(&b.left);
+                self.expr(&b.right);
+            }
+            Expr::Compare(c) => {
+                self.expr(&c.expr);
+                for op in &c.ops {
+                    self.expr(&op.expr);
+                }
+            }
+            Expr::IfExpr(i) => {
+                self.expr(&i.test_expr);
+                self.expr(&i.true_expr);
+                if let Some(false_expr) = &i.false_expr {
+                    self.expr(false_expr);
+                }
+            }
+            Expr::Call(c) => {
+                self.expr(&c.expr);
+                self.args(&c.args);
+            }
+            Expr::List(l) => {
+                for item in &l.items {
+                    self.expr(item);
+                }
+            }
+            Expr::Map(m) => {
+                for item in m.keys.iter().chain(&m.values) {
+                    self.expr(item);
+                }
+            }
+        }
+    }
+
+    fn args(&mut self, args: &[CallArg<'_>]) {
+        for arg in args {
+            match arg {
+                CallArg::Pos(e)
+                | CallArg::Kwarg(_, e)
+                | CallArg::PosSplat(e)
+                | CallArg::KwargSplat(e) => {
+                    self.expr(e);
+                }
+            }
+        }
+    }
+
+    /// Walk the value assigned to `target`, remembering the filters applied
+    /// to it.
+    fn assign(&mut self, target: &Expr<'_>, value: &Expr<'_>) {
+        let start = self.filters.len();
+        self.expr(value);
+        if let Expr::Var(var) = target {
+            let filters = start..self.filters.len();
+            self.assignments.push((var.id.to_owned(), filters));
+        }
+    }
+
+    /// Report the filters at `indices` as having no effect.
+    fn unused(&mut self, indices: Range<usize>) {
+        for (name, span) in &self.filters[indices] {
+            let kind = LintKind::UnusedFilter { name: name.clone() };
+            self.problems.push((kind, span.clone()));
+        }
+    }
+
+    /// Report the filters applied to assigned variables that are never read.
+    fn unused_filters(&mut self) {
+        let assignments = std::mem::take(&mut self.assignments);
+        for (name, filters) in assignments {
+            if !self.reads.contains(&name) {
+                self.unused(filters);
+            }
+        }
+    }
+
+    fn meta_field(&mut self, field: &str, span: Range<usize>) {
+        let fields = &self.vocabulary.meta_fields;
+        if !fields.iter().any(|f| f == field) {
+            let kind = LintKind::UnknownMetaField {
+                field: field.to_owned(),
+                fields: fields.clone(),
+            };
+            self.problems.push((kind, span));
+        }
+    }
+
+    /// Narrow a filter expression's span to the filter's name: its last
+    /// occurrence after a `|`, or the last occurrence at all (as in
+    /// `{% filter name %}`).
+    fn name_in(&self, name: &str, span: Range<usize>) -> Range<usize> {
+        let Some(text) = self.source.get(span.clone()) else {
+            return span;
+        };
+        let piped = text
+            .rmatch_indices(name)
+            .find(|(i, _)| text[..*i].trim_end().ends_with('|'));
+        match piped.or_else(|| text.rmatch_indices(name).next()) {
+            Some((i, _)) => span.start + i..span.start + i + name.len(),
+            None => span,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::config::Variable;
+
+    fn kinds(findings: &[TemplateLint]) -> Vec<String> {
+        findings.iter().map(|f| f.kind.to_string()).collect()
+    }
+
+    fn spanned<'t>(template: &'t str, finding: &TemplateLint) -> &'t str {
+        let start = finding.span.offset();
+        &template[start..start + finding.span.len()]
+    }
+
+    #[test]
+    fn flags_unknown_meta_fields_with_spans() {
+        let template = "{{ meta.environment }}-{{ meta.enviroment }}-{{ meta['tagz'] }}";
+        let findings = lint("T (default)", template, &Vocabulary::variables());
+        assert_eq!(
+            kinds(&findings),
+            [
+                "unknown `meta` field 'enviroment'",
+                "unknown `meta` field 'tagz'"
+            ]
+        );
+        assert_eq!(spanned(template, &findings[0]), "enviroment");
+        assert_eq!(spanned(template, &findings[1]), "'tagz'");
+    }
+
+    #[test]
+    fn flags_unknown_filters_in_every_branch() {
+        let template =
+            "{% if false %}{{ X | shell_escape | uper }}{% else %}{{ X | trim }}{% endif %}";
+        let findings = lint("T (default)", template, &Vocabulary::variables());
+        assert_eq!(kinds(&findings), ["unknown filter 'uper'"]);
+        assert_eq!(spanned(template, &findings[0]), "uper");
+    }
+
+    #[test]
+    fn flags_filters_whose_result_is_unused() {
+        let template = "{% set host = H | trim %}{% set port = P | int %}\
+                        {% filter upper %}{% endfilter %}{{ port }}";
+        let findings = lint("T (default)", template, &Vocabulary::variables());
+        assert_eq!(
+            kinds(&findings),
+            [
+                "filter 'trim' has no effect: its result is never used",
+                "filter 'upper' has no effect: its result is never used",
+            ]
+        );
+        assert_eq!(spanned(template, &findings[0]), "trim");
+        assert_eq!(spanned(template, &findings[1]), "upper");
+
+        let used =
+            "{% with x = X | lower %}{{ x }}{% endwith %}{% filter trim %} a {% endfilter %}";
+        assert!(lint("T (default)", used, &Vocabulary::variables()).is_empty());
+    }
+
+    #[test]
+    fn output_templates_have_their_own_meta() {
+        let template = "{{ meta.invocation }}{{ v.X | wrap(10) | b64encode }}";
+        assert!(lint_output_template("out.j2", template).is_empty());
+
+        let findings = lint("T (default)", template, &Vocabulary::variables());
+        assert_eq!(kinds(&findings), ["unknown `meta` field 'invocation'"]);
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        let findings = lint_output_template("out.j2", "{% for x in %}");
+        assert!(matches!(
+            findings[..],
+            [TemplateLint {
+                kind: LintKind::Syntax { .. },
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn lint_config_names_the_source() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "URL".to_owned(),
+                Variable {
+                    envs: BTreeMap::from([(
+                        "prod".to_owned(),
+                        Source::Template("{{ meta.env }}".to_owned()),
+                    )]),
+                    default: Some(Source::Template("{{ meta.environment }}".to_owned())),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let findings = lint_config(&config);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(
+            findings[0].to_string(),
+            "URL (envs.prod): unknown `meta` field 'env'"
+        );
+    }
+
+    #[test]
+    fn lint_config_covers_every_template_source() {
+        let config: Config = serde_yml::from_str(
+            r"
+bundles:
+  db:
+    format: json
+    default: { template: '{{ meta.a }}' }
+    envs:
+      prod: { template: '{{ meta.b }}' }
+variables:
+  X_{{ item }}:
+    for_each: { template: '{{ meta.c }}' }
+    default: { template: '{{ meta.d }}' }
+    envs:
+      prod: { template: '{{ meta.e }}' }
+    overrides:
+      ci:
+        default: { template: '{{ meta.f }}' }
+        envs:
+          prod: { template: '{{ meta.g }}' }
+",
+        )
+        .unwrap();
+        let templates = config
+            .sources()
+            .filter(|s| matches!(s, Source::Template(_)))
+            .count();
+        let findings = lint_config(&config);
+        assert_eq!(findings.len(), templates);
+        let locations: Vec<&str> = findings.iter().map(|f| f.location.as_str()).collect();
+        assert_eq!(
+            locations,
+            [
+                "X_{{ item }} (for_each)",
+                "X_{{ item }} (default)",
+                "X_{{ item }} (envs.prod)",
+                "X_{{ item }} (overrides.ci.default)",
+                "X_{{ item }} (overrides.ci.envs.prod)",
+                "bundle:db (default)",
+                "bundle:db (envs.prod)",
+            ]
+        );
+    }
+}
@@ -1,21 +1,30 @@
+use std::collections::HashSet;
 use std::fs;
+use std::num::NonZeroUsize;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
 
+use clap::ArgGroup;
+use clap::ArgMatches;
 use clap::Args;
 use clap::CommandFactory;
+use clap::FromArgMatches;
 use clap::Parser;
 use clap::Subcommand;
 use clap::ValueEnum;
+use clap::parser::ValueSource;
 use miette::Context;
 use miette::IntoDiagnostic;
 use tracing_subscriber::EnvFilter;
 
-mod config;
-mod error;
+use envoke_cli::cache;
+use envoke_cli::config;
+use envoke_cli::render;
+
+mod confirm;
 mod exec;
-mod render;
-mod resolve;
 
 #[derive(Parser)]
 /// Resolve environment variables from envoke.yaml and either print them, write
@@ -29,10 +38,18 @@ Examples:
   envoke r prod --format json                 Print resolved vars as a JSON object (r = render)
   envoke render prod --format shell-export    Print `export NAME='value'` lines
   envoke render prod --output .env            Write resolved vars to .env
+  envoke render --all-envs -o '.env.{env}'    Write one file per environment
+  envoke render ci --check .env.ci            Fail if .env.ci is out of date
   envoke exec prod -- psql                    Exec psql with resolved vars overlaid
   envoke x prod -- sh -c 'echo $DB_URL'       Exec an inline script (x = exec)
+  envoke render prod --dry-run                Show which sources would run, without running them
+  envoke explain DB_URL prod --show-value     Show how DB_URL gets its value in prod
+  envoke diff staging prod                    Show variables that differ between two environments
+  envoke lint --template out.j2               Check templates for unknown meta fields and filters
+  envoke check                                Validate every environment without running sources
   envoke meta environments                    Enumerate environment names from the config
   envoke meta all                             Enumerate environments, tags, and overrides
+  envoke cache clear                          Delete cached command and shell results
   envoke schema                               Print JSON Schema for envoke.yaml
   envoke completions zsh                      Print shell completions",
     verbatim_doc_comment
@@ -46,23 +63,8 @@ struct Cli {
     #[arg(short, long, global = true)]
     quiet: bool,
 
-    /// Disable parallel resolution of command and shell sources.
-    #[arg(long, global = true)]
-    no_parallel: bool,
-
-    /// Only include tagged variables with a matching tag. Repeatable.
-    /// Untagged variables are always included.
-    #[arg(short = 't', long = "tag", global = true, verbatim_doc_comment)]
-    tags: Vec<String>,
-
-    /// Include all tagged variables regardless of their tags.
-    #[arg(long, global = true, conflicts_with = "tags")]
-    all_tags: bool,
-
-    /// Select named overrides for source selection. Repeatable.
-    /// Per variable, at most one active override may be defined.
-    #[arg(short = 'O', long = "override", global = true, verbatim_doc_comment)]
-    overrides: Vec<String>,
+    #[command(flatten)]
+    resolve: ResolveArgs,
 
     #[command(subcommand)]
     cmd: Cmd,
@@ -79,9 +81,34 @@ enum Cmd {
     #[command(visible_alias = "x")]
     Exec(ExecArgs),
 
+    /// Show how one variable's value is chosen: the tag filter decision, each
+    /// override considered, the winning source, and its dependencies.
+    Explain(ExplainArgs),
+
+    /// Compare two environments (or one environment under different
+    /// overrides or tags): variables added, removed, or changed, with the
+    /// source of each value.
+    Diff(DiffArgs),
+
+    /// Check templates without resolving anything: flag unknown `meta`
+    /// fields, unknown or unused filters, and syntax errors in every
+    /// `template:` source (and optionally an output template).
+    Lint(LintArgs),
+
+    /// Validate every environment without running any command or script:
+    /// report variables with no source, invalid sources, unknown references,
+    /// and cycles. Each environment is checked under each override and tag
+    /// (or only the tags from `--tag` / `--all-tags`), and `--override`
+    /// flags are checked for conflicts.
+    Check,
+
     /// Enumerate names of a config dimension (environments, tags, overrides).
     Meta(MetaArgs),
 
+    /// Manage the on-disk cache of command and shell results.
+    #[command(subcommand)]
+    Cache(CacheCmd),
+
     /// Print the JSON Schema for envoke.yaml and exit.
     Schema,
 
@@ -90,15 +117,34 @@ enum Cmd {
 }
 
 #[derive(Args)]
+#[command(group(ArgGroup::new("destination").args(["output", "check"])))]
 struct RenderArgs {
     /// Target environment (e.g. local, prod).
-    #[arg(env = "ENVOKE_ENV")]
-    env: String,
+    #[arg(env = "ENVOKE_ENV", required_unless_present_any = ["all_envs", "envs"])]
+    env: Option<String>,
+
+    /// Where ENV came from (the command line or `ENVOKE_ENV`); set after
+    /// parsing.
+    #[arg(skip)]
+    env_source: Option<ValueSource>,
 
-    /// Write output to a file instead of stdout.
+    #[command(flatten)]
+    targets: TargetArgs,
+
+    #[command(flatten)]
+    dry_run: DryRunArgs,
+
+    /// Write output to a file instead of stdout. With `--all-envs` or
+    /// `--envs`, `{env}` in the path is replaced by each environment's name.
     #[arg(short, long)]
     output: Option<PathBuf>,
 
+    /// Render in memory and compare with PATH instead of writing it: print a
+    /// unified diff and exit non-zero if they differ. The `@generated`
+    /// header line is ignored. `{env}` is replaced as for `--output`.
+    #[arg(long, value_name = "PATH", conflicts_with = "dry_run")]
+    check: Option<PathBuf>,
+
     /// Select a built-in output format preset.
     #[arg(
         short = 'f',
@@ -144,9 +190,14 @@ The template uses Jinja2 syntax (minijinja).
 
 Template context:
 
-  variables  Map of name -> {value, description}. Iterate with:
+  variables  Map of name -> {value, description, source, origin}.
+             Iterate with:
                {% for name, var in variables | items %}
              Access fields: {{ variables.DB_URL.value }}
+             var.source is the source kind (literal, cmd, sh,
+             template, from_bundle); var.origin has kind (env or
+             default), override (name or none), and path (e.g.
+             overrides.local.envs.prod).
 
   v          Flat map of name -> value string. Shorthand:
                {{ v.DB_URL }}
@@ -159,6 +210,10 @@ Template context:
                meta.config_file      Path to the config file
                meta.tags             Active --tag values as a list
                meta.overrides        Active --override values as a list
+               meta.provenance       Whether --provenance was passed
+
+  errors     Map of name -> {message, code} for variables that
+             failed under --keep-going (empty otherwise).
 
 Available filters:
 
@@ -174,9 +229,35 @@ Available filters:
     dotenv_escape Encode a value as a portable .env token
     urlencode     Percent-encodes special characters
     wrap(n)       Wraps into lines of maximum n columns
-
-All filters are available in both variable templates (the `template`
-source type) and custom output templates.
+    b64encode     Encodes as standard base64 with padding
+    b64decode     Decodes base64; the result must be UTF-8
+    sha256        Lowercase hex SHA-256 digest
+    hex           Lowercase hex of the value's UTF-8 bytes
+    json_get(p)   Element at path p of a JSON value (a.0.b or /a/0/b)
+    regex_replace(pattern, replacement)
+                  Replaces every regex match; groups as $1 or ${name}
+    date(fmt)     Reformats an RFC 3339 timestamp with a strftime format
+    quote_yaml    Quotes as a YAML double-quoted scalar
+
+All filters are available in variable templates (the `template`
+source type), for_each templates, and custom output templates.
+
+Available functions:
+
+  Built-in (minijinja builtins): range, dict, namespace, and more.
+    See https://docs.rs/minijinja/latest/minijinja/functions
+
+  Additional functions:
+    env(name, default)  Environment variable, or default when unset
+    file(path)          File contents; relative paths are resolved
+                        against the config file's directory
+    now(fmt)            Current local time, RFC 3339 or strftime fmt
+    uuid()              Random version 4 UUID
+    hostname()          The machine's host name
+
+  These read the outside world. `settings.pure_templates: true` in
+  envoke.yaml turns calling any of them into an error, so rendering
+  depends only on the config.
 
 Note: Variable template sources (the `template` source type in
 envoke.yaml) also have access to a `meta` object:
@@ -186,6 +267,55 @@ envoke.yaml) also have access to a `meta` object:
   meta.timestamp        RFC 3339 timestamp"
     )]
     template: Option<PathBuf>,
+
+    /// Add a comment above each variable in the dotenv and shell-export
+    /// presets naming the source that produced it (e.g. `# source: cmd
+    /// (envs.prod)`).
+    #[arg(long)]
+    provenance: bool,
+
+    /// Render every variable that resolved even if some failed. Failed
+    /// variables are listed as comments in formats that support them (and
+    /// omitted otherwise), exposed to custom templates as `errors`, and
+    /// still make envoke exit non-zero.
+    #[arg(long, conflicts_with_all = ["fail_fast", "dry_run"])]
+    keep_going: bool,
+
+    /// Confirm protected environments without prompting (required when
+    /// stdin is not a terminal and `--output` is used).
+    #[arg(short, long)]
+    yes: bool,
+}
+
+/// Flags for rendering several environments in one invocation.
+#[derive(Args)]
+struct TargetArgs {
+    /// Render every environment in the config (as listed by `envoke meta
+    /// environments`) to its own file. Requires `--output` (or `--check`)
+    /// with `{env}`.
+    #[arg(long, requires = "destination", conflicts_with = "envs")]
+    all_envs: bool,
+
+    /// Render these environments to their own files. Comma-separated or
+    /// repeatable. Requires `--output` (or `--check`) with `{env}` when more
+    /// than one is given.
+    #[arg(
+        long,
+        value_name = "ENV",
+        value_delimiter = ',',
+        requires = "destination"
+    )]
+    envs: Vec<String>,
+}
+
+/// Flag shared by `render` and `exec` for previewing a resolution.
+#[derive(Args)]
+struct DryRunArgs {
+    /// Print the resolution plan instead of resolving: each variable in
+    /// order with its selected source, commands and scripts verbatim and
+    /// literals of `secret` variables redacted. Nothing is run or written.
+    #[arg(long)]
+    dry_run: bool,
 }
 
 #[derive(Args)]
@@ -194,6 +324,14 @@ struct ExecArgs {
     #[arg(env = "ENVOKE_ENV")]
     env: String,
 
+    #[command(flatten)]
+    dry_run: DryRunArgs,
+
+    /// Confirm protected environments without prompting (required when
+    /// stdin is not a terminal).
+    #[arg(short, long)]
+    yes: bool,
+
     /// Command to execute with resolved variables overlaid on the current
     /// environment. Everything after `--` is passed verbatim to the child.
     ///
@@ -213,6 +351,78 @@ struct ExecArgs {
     command: Vec<String>,
 }
 
+#[derive(Args)]
+struct ExplainArgs {
+    /// Variable to explain.
+    var: String,
+
+    /// Target environment (e.g. local, prod).
+    #[arg(env = "ENVOKE_ENV")]
+    env: String,
+
+    /// Also resolve the variable (and only what it depends on) and print its
+    /// value.
+    #[arg(long)]
+    show_value: bool,
+}
+
+#[derive(Args)]
+struct DiffArgs {
+    /// Environment to compare from.
+    env_a: String,
+
+    /// Environment to compare to. May equal ENV_A when comparing override or
+    /// tag sets.
+    env_b: String,
+
+    /// Activate an override for ENV_A only, on top of `--override`.
+    /// Repeatable.
+    #[arg(long, value_name = "NAME")]
+    override_a: Vec<String>,
+
+    /// Activate an override for ENV_B only, on top of `--override`.
+    /// Repeatable.
+    #[arg(long, value_name = "NAME")]
+    override_b: Vec<String>,
+
+    /// Activate a tag for ENV_A only, on top of `--tag`. Repeatable.
+    #[arg(long, value_name = "TAG")]
+    tag_a: Vec<String>,
+
+    /// Activate a tag for ENV_B only, on top of `--tag`. Repeatable.
+    #[arg(long, value_name = "TAG")]
+    tag_b: Vec<String>,
+
+    /// Output format.
+    #[arg(short, long, value_enum, default_value_t = DiffFormat::Text)]
+    format: DiffFormat,
+
+    /// Print the values of variables marked `secret` instead of redacting
+    /// them.
+    #[arg(long)]
+    show_secrets: bool,
+
+    /// Exit with status 1, reporting how many variables differ, when the two
+    /// sides differ.
+    #[arg(long)]
+    exit_code: bool,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum DiffFormat {
+    /// One line per added or removed variable, both values for changed ones.
+    Text,
+    /// A JSON document with every difference.
+    Json,
+}
+
+#[derive(Args)]
+struct LintArgs {
+    /// Also lint a custom output template, as passed to `render --template`.
+    #[arg(long, value_name = "PATH")]
+    template: Option<PathBuf>,
+}
+
 #[derive(Args)]
 struct MetaArgs {
     /// Which config dimension to enumerate.
@@ -231,19 +441,101 @@ enum MetaTarget {
     All,
 }
 
+/// Global flags that shape how variables are resolved.
+#[derive(Args)]
+struct ResolveArgs {
+    #[command(flatten)]
+    concurrency: ConcurrencyArgs,
+
+    /// Ignore cached command and shell results: always run sources and do
+    /// not store their output.
+    #[arg(long, global = true)]
+    no_cache: bool,
+
+    /// Only include tagged variables with a matching tag. Repeatable.
+    /// Untagged variables are always included.
+    #[arg(short = 't', long = "tag", global = true, verbatim_doc_comment)]
+    tags: Vec<String>,
+
+    /// Include all tagged variables regardless of their tags.
+    #[arg(long, global = true, conflicts_with = "tags")]
+    all_tags: bool,
+
+    /// Fail templates that read an undefined value (e.g. a misspelled
+    /// `meta` field) instead of rendering it as an empty string.
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Select named overrides for source selection. Repeatable.
+    /// Per variable, at most one active override may be defined.
+    #[arg(short = 'O', long = "override", global = true, verbatim_doc_comment)]
+    overrides: Vec<String>,
+
+    #[command(flatten)]
+    timings: TimingArgs,
+}
+
+/// Global flags for how command and shell sources are run.
+#[derive(Args)]
+struct ConcurrencyArgs {
+    /// Disable parallel resolution of command and shell sources.
+    #[arg(long, global = true)]
+    no_parallel: bool,
+
+    /// Run at most N command and shell sources at once (default: 8, or
+    /// `settings.jobs` from the config).
+    #[arg(short, long, global = true, env = "ENVOKE_JOBS", value_name = "N")]
+    jobs: Option<NonZeroUsize>,
+
+    /// Kill the command and shell sources still running as soon as one
+    /// source fails, instead of letting them finish.
+    #[arg(long, global = true)]
+    fail_fast: bool,
+}
+
+/// Global flags for reporting how long each source took to resolve.
+#[derive(Args)]
+struct TimingArgs {
+    /// Print how long each source took (including time queued for a worker)
+    /// to stderr, slowest first.
+    #[arg(long, global = true)]
+    timings: bool,
+
+    /// Write per-source timings as JSON to FILE.
+    #[arg(long, global = true, value_name = "FILE")]
+    timings_json: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum CacheCmd {
+    /// Delete every cached value.
+    Clear,
+}
+
 #[derive(Args)]
 struct CompletionsArgs {
     /// Shell to generate completions for.
     shell: clap_complete::Shell,
 }
 
+/// Build the CLI from parsed matches, recording where values came from.
+fn parse_cli(matches: &ArgMatches) -> Cli {
+    let mut cli = Cli::from_arg_matches(matches).unwrap_or_else(|e| e.exit());
+    if let Cmd::Render(args) = &mut cli.cmd {
+        args.env_source = matches
+            .subcommand_matches("render")
+            .and_then(|m| m.value_source("env"));
+    }
+    cli
+}
+
 fn run() -> miette::Result<()> {
     tracing_subscriber::fmt()
         .with_writer(std::io::stderr)
         .with_env_filter(EnvFilter::from_default_env())
         .init();
 
-    let cli = Cli::parse();
+    let cli = parse_cli(&Cli::command().get_matches());
 
     match cli.cmd {
         Cmd::Schema => {
@@ -264,33 +556,18 @@ fn run() -> miette::Result<()> {
             Ok(())
         }
         Cmd::Meta(args) => cmd_meta(&cli.config, args.target),
-        Cmd::Render(args) => cmd_render(
-            args,
-            &cli.config,
-            cli.quiet,
-            cli.no_parallel,
-            cli.tags,
-            cli.all_tags,
-            cli.overrides,
-        ),
-        Cmd::Exec(args) => cmd_exec(
-            args,
-            &cli.config,
-            cli.no_parallel,
-            cli.tags,
-            cli.all_tags,
-            cli.overrides,
-        ),
+        Cmd::Cache(CacheCmd::Clear) => cmd_cache_clear(cli.quiet),
+        Cmd::Render(args) => cmd_render(&args, &cli.config, cli.quiet, &cli.resolve),
+        Cmd::Exec(args) => cmd_exec(args, &cli.config, &cli.resolve),
+        Cmd::Explain(args) => cmd_explain(&args, &cli.config, &cli.resolve),
+        Cmd::Diff(args) => cmd_diff(&args, &cli.config, &cli.resolve),
+        Cmd::Lint(args) => cmd_lint(&args, &cli.config, cli.quiet),
+        Cmd::Check => cmd_check(&cli.config, &cli.resolve, cli.quiet),
     }
 }
 
 fn load_config(config_path: &Path) -> miette::Result<config::Config> {
-    let yaml = fs::read_to_string(config_path)
-        .into_diagnostic()
-        .with_context(|| format!("failed to read {}", config_path.display()))?;
-    serde_yml::from_str(&yaml)
-        .into_diagnostic()
-        .with_context(|| format!("failed to parse {}", config_path.display()))
+    Ok(config::Config::from_path(config_path)?)
 }
 
 fn cmd_meta(config_path: &Path, target: MetaTarget) -> miette::Result<()> {
@@ -327,59 +604,235 @@ fn cmd_meta(config_path: &Path, target: MetaTarget) -> miette::Result<()> {
     Ok(())
 }
 
+fn cmd_cache_clear(quiet: bool) -> miette::Result<()> {
+    let Some(dir) = cache::Cache::default_dir() else {
+        miette::bail!("could not determine the cache directory");
+    };
+    let removed = cache::Cache::clear(&dir)
+        .into_diagnostic()
+        .with_context(|| format!("failed to clear {}", dir.display()))?;
+    if !quiet {
+        eprintln!("Removed {removed} cached value(s) from {}", dir.display());
+    }
+    Ok(())
+}
+
 struct Resolution {
-    resolved: Vec<resolve::Resolved>,
+    resolved: Vec<envoke_cli::Resolved>,
+    /// Failures tolerated by `--keep-going`; always empty otherwise.
+    errors: Vec<envoke_cli::ResolveError>,
     tags: Vec<String>,
     overrides: Vec<String>,
     timestamp: String,
 }
 
+/// Builder for resolving `environment` with the global resolve flags.
+fn resolver_for<'c>(
+    config: &'c config::Config,
+    config_path: &Path,
+    environment: &str,
+    opts: &ResolveArgs,
+) -> envoke_cli::Resolver<'c> {
+    let mut builder = envoke_cli::Resolver::new(config, environment)
+        .overrides(&opts.overrides)
+        .parallel(!opts.concurrency.no_parallel)
+        .fail_fast(opts.concurrency.fail_fast)
+        .strict(opts.strict)
+        .config_dir(config_path.parent().unwrap_or(Path::new(".")));
+    if let Some(jobs) = opts.concurrency.jobs {
+        builder = builder.jobs(jobs);
+    }
+    builder = if opts.all_tags {
+        builder.all_tags()
+    } else {
+        builder.tags(&opts.tags)
+    };
+    if !opts.no_cache
+        && let Some(dir) = cache::Cache::default_dir()
+    {
+        builder = builder.cache(cache::Cache::new(dir, config_path));
+    }
+    builder
+}
+
+/// Catch SIGINT / SIGTERM from here on, so running sources are killed
+/// rather than orphaned (without a terminal they run in their own process
+/// groups and never see a signal sent to envoke's group).
+fn catch_signals() -> miette::Result<()> {
+    envoke_cli::interrupt::install()
+        .into_diagnostic()
+        .context("failed to install signal handler")
+}
+
 fn resolve_for(
     config: &config::Config,
+    config_path: &Path,
     environment: &str,
-    tags: Vec<String>,
-    all_tags: bool,
-    overrides: Vec<String>,
-    no_parallel: bool,
+    opts: &ResolveArgs,
+    keep_going: bool,
 ) -> miette::Result<Resolution> {
-    let tags = if all_tags { config.tag_names() } else { tags };
+    catch_signals()?;
     let timestamp = chrono::Local::now().to_rfc3339();
-    let parallel = !no_parallel;
-    let resolved =
-        resolve::resolve_all(config, environment, &tags, &overrides, &timestamp, parallel)
-            .map_err(|errors| error::ResolveErrors { errors })?;
+    let started = Instant::now();
+    let builder = resolver_for(config, config_path, environment, opts).timestamp(&timestamp);
+    let (result, mut timings) = if keep_going {
+        let partial = builder.resolve_partial();
+        (Ok((partial.resolved, partial.errors)), partial.timings)
+    } else {
+        let (result, timings) = builder.resolve_timed();
+        (result.map(|resolved| (resolved, Vec::new())), timings)
+    };
+    report_timings(&opts.timings, environment, &mut timings, started.elapsed())?;
+    if envoke_cli::interrupt::interrupted() {
+        let errors = match &result {
+            Ok((_, errors)) => errors.as_slice(),
+            Err(e) => e.errors.as_slice(),
+        };
+        return Err(envoke_cli::error::Interrupted::new(environment, errors).into());
+    }
+    let (resolved, errors) = result?;
+    let tags = if opts.all_tags {
+        config.tag_names()
+    } else {
+        opts.tags.clone()
+    };
 
     Ok(Resolution {
         resolved,
+        errors,
         tags,
-        overrides,
+        overrides: opts.overrides.clone(),
         timestamp,
     })
 }
 
+/// Print and/or write per-source timings as requested by `--timings` and
+/// `--timings-json`.
+fn report_timings(
+    opts: &TimingArgs,
+    environment: &str,
+    timings: &mut [envoke_cli::timings::SourceTiming],
+    wall: Duration,
+) -> miette::Result<()> {
+    envoke_cli::timings::sort(timings);
+    if opts.timings {
+        eprint!("{}", envoke_cli::timings::table(timings, wall));
+    }
+    if let Some(path) = &opts.timings_json {
+        let path = env_path(path, environment);
+        let report = envoke_cli::timings::Report {
+            environment,
+            wall,
+            sources: timings,
+        };
+        let json = serde_json::to_string_pretty(&report)
+            .into_diagnostic()
+            .context("failed to serialize timings")?;
+        fs::write(&path, json + "\n")
+            .into_diagnostic()
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// `path` with every `{env}` replaced by `environment`.
+fn env_path(path: &Path, environment: &str) -> PathBuf {
+    PathBuf::from(path.to_string_lossy().replace("{env}", environment))
+}
+
 fn cmd_render(
-    args: RenderArgs,
+    args: &RenderArgs,
     config_path: &Path,
     quiet: bool,
-    no_parallel: bool,
-    tags: Vec<String>,
-    all_tags: bool,
-    overrides: Vec<String>,
+    opts: &ResolveArgs,
 ) -> miette::Result<()> {
-    let environment = args.env;
+    let config = load_config(config_path)?;
+    let environments = if args.targets.all_envs {
+        config.environments()
+    } else if !args.targets.envs.is_empty() {
+        let mut seen = HashSet::new();
+        let mut environments = args.targets.envs.clone();
+        environments.retain(|environment| seen.insert(environment.clone()));
+        environments
+    } else {
+        let environment = args.env.as_deref().expect("clap requires ENV");
+        if args.output.is_some() && !args.dry_run.dry_run && config.is_protected(environment) {
+            confirm::confirm_protected(environment, "render", args.yes)?;
+        }
+        return render_env(args, &config, config_path, environment, quiet, opts);
+    };
+
+    if let Some(environment) = &args.env
+        && args.env_source != Some(ValueSource::EnvVariable)
+    {
+        miette::bail!(
+            help = "drop the environment argument, or render it alone",
+            "environment '{environment}' cannot be combined with --all-envs or --envs"
+        );
+    }
+    let path = args
+        .output
+        .as_deref()
+        .or(args.check.as_deref())
+        .expect("clap requires --output or --check");
+    if environments.len() > 1 && !path.to_string_lossy().contains("{env}") {
+        miette::bail!(
+            help = "e.g. --output '.env.{env}'",
+            "the path must contain {{env}} to render {} environments to separate files",
+            environments.len()
+        );
+    }
+    if environments.is_empty() {
+        miette::bail!("no environments found in {}", config_path.display());
+    }
+    for environment in &environments {
+        if args.output.is_some() && !args.dry_run.dry_run && config.is_protected(environment) {
+            confirm::confirm_protected(environment, "render", args.yes)?;
+        }
+    }
+
+    // One environment at a time, so `--jobs` and concurrency groups bound
+    // the whole run and each environment's output stays together.
+    let results: Vec<miette::Result<()>> = environments
+        .iter()
+        .map(|environment| render_env(args, &config, config_path, environment, quiet, opts))
+        .collect();
+
+    let (failed, errors): (Vec<String>, Vec<_>) = environments
+        .iter()
+        .zip(results)
+        .filter_map(|(environment, result)| Some((environment.clone(), result.err()?.into())))
+        .unzip();
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(envoke_cli::error::EnvironmentsFailed {
+            total: environments.len(),
+            failed,
+            errors,
+        }
+        .into())
+    }
+}
+
+/// Resolve and render one environment, to `--output` (with `{env}`
+/// substituted) or stdout.
+fn render_env(
+    args: &RenderArgs,
+    config: &config::Config,
+    config_path: &Path,
+    environment: &str,
+    quiet: bool,
+    opts: &ResolveArgs,
+) -> miette::Result<()> {
+    if args.dry_run.dry_run {
+        return print_plan(config, config_path, environment, opts);
+    }
     if !quiet {
         eprintln!("Generating environment variables for {environment}...");
     }
-
-    let config = load_config(config_path)?;
-    let res = resolve_for(
-        &config,
-        &environment,
-        tags,
-        all_tags,
-        overrides,
-        no_parallel,
-    )?;
+    let strict = opts.strict || config.settings.strict_templates;
+    let res = resolve_for(config, config_path, environment, opts, args.keep_going)?;
 
     let invocation_args: Vec<String> = std::env::args().collect();
     let ctx = render::RenderContext {
@@ -388,11 +841,15 @@ fn cmd_render(
             timestamp: res.timestamp,
             invocation: invocation_args.join(" "),
             invocation_args,
-            environment,
+            environment: environment.to_owned(),
             config_file: config_path.display().to_string(),
             tags: res.tags,
             overrides: res.overrides,
+            provenance: args.provenance,
         },
+        errors: res.errors,
+        strict,
+        pure: config.settings.pure_templates,
     };
 
     let content = if let Some(path) = &args.template {
@@ -403,8 +860,11 @@ fn cmd_render(
         render::render_format(&ctx, render::Format::Dotenv)?
     };
 
-    if let Some(path) = &args.output {
-        fs::write(path, &content)
+    if let Some(path) = &args.check {
+        check_file(&env_path(path, environment), &content, quiet)?;
+    } else if let Some(path) = &args.output {
+        let path = env_path(path, environment);
+        fs::write(&path, &content)
             .into_diagnostic()
             .with_context(|| format!("failed to write {}", path.display()))?;
         if !quiet {
@@ -414,23 +874,180 @@ fn cmd_render(
         print!("{content}");
     }
 
+    if !ctx.errors.is_empty() {
+        return Err(envoke_cli::ResolveErrors { errors: ctx.errors }.into());
+    }
     Ok(())
 }
 
-fn cmd_exec(
-    args: ExecArgs,
+/// Print the resolution plan for `--dry-run`. Nothing is run.
+fn print_plan(
+    config: &config::Config,
     config_path: &Path,
-    no_parallel: bool,
-    tags: Vec<String>,
-    all_tags: bool,
-    overrides: Vec<String>,
+    environment: &str,
+    opts: &ResolveArgs,
 ) -> miette::Result<()> {
-    let ExecArgs { env, command } = args;
+    let plan = resolver_for(config, config_path, environment, opts).dry_run()?;
+    print!("{plan}");
+    Ok(())
+}
+
+/// Compare `rendered` with the file at `path` for `render --check`, printing
+/// a unified diff when they differ. A missing file counts as empty.
+fn check_file(path: &Path, rendered: &str, quiet: bool) -> miette::Result<()> {
+    let existing = match fs::read_to_string(path) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(e)
+                .into_diagnostic()
+                .with_context(|| format!("failed to read {}", path.display()));
+        }
+    };
+    match render::drift(&path.display().to_string(), &existing, rendered) {
+        None => {
+            if !quiet {
+                eprintln!("{} is up to date", path.display());
+            }
+            Ok(())
+        }
+        Some(diff) => {
+            print!("{diff}");
+            Err(envoke_cli::error::OutOfDate {
+                path: path.to_path_buf(),
+            }
+            .into())
+        }
+    }
+}
+
+fn cmd_exec(args: ExecArgs, config_path: &Path, opts: &ResolveArgs) -> miette::Result<()> {
+    let ExecArgs {
+        env,
+        dry_run,
+        yes,
+        command,
+    } = args;
     let config = load_config(config_path)?;
-    let res = resolve_for(&config, &env, tags, all_tags, overrides, no_parallel)?;
+    if dry_run.dry_run {
+        print_plan(&config, config_path, &env, opts)?;
+        println!("then exec: {}", envoke_cli::dry_run::command_line(&command));
+        return Ok(());
+    }
+    if config.is_protected(&env) {
+        confirm::confirm_protected(&env, "exec", yes)?;
+    }
+    let res = resolve_for(&config, config_path, &env, opts, false)?;
     exec::exec_command(&command, &res.resolved)
 }
 
+fn cmd_explain(args: &ExplainArgs, config_path: &Path, opts: &ResolveArgs) -> miette::Result<()> {
+    let config = load_config(config_path)?;
+    if args.show_value {
+        catch_signals()?;
+    }
+    let explanation =
+        resolver_for(&config, config_path, &args.env, opts).explain(&args.var, args.show_value)?;
+    print!("{explanation}");
+    Ok(())
+}
+
+fn cmd_diff(args: &DiffArgs, config_path: &Path, opts: &ResolveArgs) -> miette::Result<()> {
+    let config = load_config(config_path)?;
+    catch_signals()?;
+    let resolve = |environment: &str,
+                   overrides: &[String],
+                   tags: &[String]|
+     -> miette::Result<Vec<envoke_cli::Resolved>> {
+        let result = resolver_for(&config, config_path, environment, opts)
+            .overrides(overrides)
+            .tags(tags)
+            .resolve();
+        if envoke_cli::interrupt::interrupted() {
+            let errors = result.as_ref().err().map_or(&[][..], |e| &e.errors);
+            return Err(envoke_cli::error::Interrupted::new(environment, errors).into());
+        }
+        Ok(result?)
+    };
+    let a = resolve(&args.env_a, &args.override_a, &args.tag_a)?;
+    let b = resolve(&args.env_b, &args.override_b, &args.tag_b)?;
+    let diff = envoke_cli::diff::Diff::new(
+        side_label(&args.env_a, &args.override_a, &args.tag_a),
+        &a,
+        side_label(&args.env_b, &args.override_b, &args.tag_b),
+        &b,
+        args.show_secrets,
+    );
+
+    match args.format {
+        DiffFormat::Text => print!("{diff}"),
+        DiffFormat::Json => {
+            let json = serde_json::to_string_pretty(&diff)
+                .into_diagnostic()
+                .context("failed to serialize diff")?;
+            println!("{json}");
+        }
+    }
+    if args.exit_code && !diff.is_empty() {
+        return Err(envoke_cli::error::EnvironmentsDiffer {
+            a: diff.a,
+            b: diff.b,
+            count: diff.entries.len(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Name one side of a diff: the environment plus its side-specific flags.
+fn side_label(environment: &str, overrides: &[String], tags: &[String]) -> String {
+    let flags = overrides
+        .iter()
+        .map(|name| format!(" -O {name}"))
+        .chain(tags.iter().map(|tag| format!(" -t {tag}")));
+    std::iter::once(environment.to_owned())
+        .chain(flags)
+        .collect()
+}
+
+fn cmd_lint(args: &LintArgs, config_path: &Path, quiet: bool) -> miette::Result<()> {
+    let config = load_config(config_path)?;
+    let mut findings = envoke_cli::lint::lint_config(&config);
+    if let Some(path) = &args.template {
+        let template = fs::read_to_string(path)
+            .into_diagnostic()
+            .with_context(|| format!("failed to read template {}", path.display()))?;
+        findings.extend(envoke_cli::lint::lint_output_template(
+            &path.display().to_string(),
+            &template,
+        ));
+    }
+    if !findings.is_empty() {
+        return Err(envoke_cli::error::LintErrors { findings }.into());
+    }
+    if !quiet {
+        eprintln!("No template problems found.");
+    }
+    Ok(())
+}
+
+fn cmd_check(config_path: &Path, opts: &ResolveArgs, quiet: bool) -> miette::Result<()> {
+    let config = load_config(config_path)?;
+    let tags = if opts.all_tags {
+        Some(config.tag_names())
+    } else {
+        (!opts.tags.is_empty()).then(|| opts.tags.clone())
+    };
+    let findings = envoke_cli::check::check_config(&config, &opts.overrides, tags.as_deref());
+    if !findings.is_empty() {
+        return Err(envoke_cli::error::CheckErrors { findings }.into());
+    }
+    if !quiet {
+        eprintln!("No problems found.");
+    }
+    Ok(())
+}
+
 fn main() -> miette::Result<()> {
     miette::set_hook(Box::new(|_| {
         Box::new(
@@ -442,11 +1059,21 @@ fn main() -> miette::Result<()> {
         )
     }))
     .expect("miette hook should only be set once");
-    run()
+    let result = run();
+    if envoke_cli::interrupt::interrupted() {
+        if let Err(report) = result {
+            eprintln!("{report:?}");
+        }
+        std::process::exit(envoke_cli::interrupt::EXIT_CODE);
+    }
+    result
 }
 
 #[cfg(test)]
 mod cli_tests {
+    use std::num::NonZeroUsize;
+
+    use clap::CommandFactory;
     use clap::Parser;
 
     use super::Cli;
@@ -626,21 +1253,240 @@ mod cli_tests {
             "psql",
         ])
         .unwrap();
-        assert_eq!(cli.tags, vec!["vault".to_owned()]);
-        assert_eq!(cli.overrides, vec!["read-replica".to_owned()]);
+        assert_eq!(cli.resolve.tags, vec!["vault".to_owned()]);
+        assert_eq!(cli.resolve.overrides, vec!["read-replica".to_owned()]);
         let Cmd::Exec(args) = cli.cmd else {
             panic!("expected Exec subcommand");
         };
         assert_eq!(args.command, vec!["psql".to_owned()]);
     }
 
+    #[test]
+    fn yes_flag_parses_on_render_and_exec() {
+        let cli = Cli::try_parse_from(["envoke", "r", "prod", "-o", ".env", "--yes"]).unwrap();
+        let Cmd::Render(args) = cli.cmd else {
+            panic!("expected Render subcommand");
+        };
+        assert!(args.yes);
+
+        let cli = Cli::try_parse_from(["envoke", "x", "prod", "-y", "--", "psql"]).unwrap();
+        let Cmd::Exec(args) = cli.cmd else {
+            panic!("expected Exec subcommand");
+        };
+        assert!(args.yes);
+    }
+
+    #[test]
+    fn cache_clear_parses() {
+        let cli = Cli::try_parse_from(["envoke", "cache", "clear"]).unwrap();
+        assert!(matches!(cli.cmd, Cmd::Cache(super::CacheCmd::Clear)));
+        assert!(Cli::try_parse_from(["envoke", "cache"]).is_err());
+    }
+
+    #[test]
+    fn no_cache_is_global() {
+        let cli = Cli::try_parse_from(["envoke", "x", "prod", "--no-cache", "--", "psql"]).unwrap();
+        assert!(cli.resolve.no_cache);
+    }
+
+    #[test]
+    fn timings_flags_are_global() {
+        let cli = Cli::try_parse_from([
+            "envoke",
+            "--timings",
+            "x",
+            "prod",
+            "--timings-json",
+            "t.json",
+            "--",
+            "true",
+        ])
+        .unwrap();
+        assert!(cli.resolve.timings.timings);
+        assert_eq!(
+            cli.resolve.timings.timings_json.as_deref(),
+            Some(std::path::Path::new("t.json"))
+        );
+    }
+
+    #[test]
+    fn keep_going_parses() {
+        let cli = Cli::try_parse_from(["envoke", "render", "prod", "--keep-going"]).unwrap();
+        let Cmd::Render(args) = cli.cmd else {
+            panic!("expected Render subcommand");
+        };
+        assert!(args.keep_going);
+    }
+
+    #[test]
+    fn jobs_parses_and_rejects_zero() {
+        let cli = Cli::try_parse_from(["envoke", "-j", "32", "render", "prod"]).unwrap();
+        assert_eq!(
+            cli.resolve.concurrency.jobs.map(NonZeroUsize::get),
+            Some(32)
+        );
+        assert!(Cli::try_parse_from(["envoke", "render", "prod", "--jobs", "0"]).is_err());
+    }
+
+    #[test]
+    fn fail_fast_is_global_and_conflicts_with_keep_going() {
+        let cli =
+            Cli::try_parse_from(["envoke", "--fail-fast", "exec", "prod", "--", "true"]).unwrap();
+        assert!(cli.resolve.concurrency.fail_fast);
+        assert!(
+            Cli::try_parse_from(["envoke", "render", "prod", "--fail-fast", "--keep-going"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn lint_parses_and_strict_is_global() {
+        let cli = Cli::try_parse_from(["envoke", "lint", "--template", "out.j2"]).unwrap();
+        let Cmd::Lint(args) = cli.cmd else {
+            panic!("expected Lint subcommand");
+        };
+        assert_eq!(args.template.unwrap().to_str(), Some("out.j2"));
+
+        let cli = Cli::try_parse_from(["envoke", "render", "prod", "--strict"]).unwrap();
+        assert!(cli.resolve.strict);
+    }
+
+    #[test]
+    fn check_parses_with_overrides() {
+        let cli = Cli::try_parse_from(["envoke", "check", "-O", "ci", "-O", "replica"]).unwrap();
+        assert!(matches!(cli.cmd, Cmd::Check));
+        assert_eq!(cli.resolve.overrides, ["ci", "replica"]);
+
+        let cli = Cli::try_parse_from(["envoke", "check", "--tag", "vault"]).unwrap();
+        assert_eq!(cli.resolve.tags, ["vault"]);
+    }
+
+    #[test]
+    fn render_all_envs_and_envs_parse() {
+        let cli =
+            Cli::try_parse_from(["envoke", "render", "--all-envs", "-o", ".env.{env}"]).unwrap();
+        let Cmd::Render(args) = cli.cmd else {
+            panic!("expected Render subcommand");
+        };
+        assert!(args.targets.all_envs);
+
+        let cli = Cli::try_parse_from([
+            "envoke",
+            "render",
+            "--envs",
+            "a,b",
+            "--envs",
+            "c",
+            "-o",
+            "out/{env}",
+        ])
+        .unwrap();
+        let Cmd::Render(args) = cli.cmd else {
+            panic!("expected Render subcommand");
+        };
+        assert_eq!(args.targets.envs, ["a", "b", "c"]);
+
+        assert!(Cli::try_parse_from(["envoke", "render", "--all-envs"]).is_err());
+        assert!(
+            Cli::try_parse_from(["envoke", "render", "--all-envs", "--envs", "a", "-o", "x"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn render_check_parses_and_conflicts_with_output() {
+        let cli = Cli::try_parse_from(["envoke", "render", "ci", "--check", ".env.ci"]).unwrap();
+        let Cmd::Render(args) = cli.cmd else {
+            panic!("expected Render subcommand");
+        };
+        assert_eq!(args.check.unwrap().to_str(), Some(".env.ci"));
+
+        assert!(
+            Cli::try_parse_from(["envoke", "render", "--all-envs", "--check", "{env}"]).is_ok()
+        );
+        assert!(
+            Cli::try_parse_from(["envoke", "render", "ci", "--check", "a", "--output", "b"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn dry_run_parses_for_render_and_exec() {
+        let cli = Cli::try_parse_from(["envoke", "render", "prod", "--dry-run"]).unwrap();
+        let Cmd::Render(args) = cli.cmd else {
+            panic!("expected Render subcommand");
+        };
+        assert!(args.dry_run.dry_run);
+
+        let cli =
+            Cli::try_parse_from(["envoke", "exec", "prod", "--dry-run", "--", "psql"]).unwrap();
+        let Cmd::Exec(args) = cli.cmd else {
+            panic!("expected Exec subcommand");
+        };
+        assert!(args.dry_run.dry_run);
+        assert_eq!(args.command, ["psql"]);
+
+        for conflicting in ["--keep-going", "--check=.env"] {
+            assert!(
+                Cli::try_parse_from(["envoke", "render", "prod", "--dry-run", conflicting])
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn diff_parses() {
+        let cli = Cli::try_parse_from([
+            "envoke",
+            "diff",
+            "prod",
+            "prod",
+            "--override-b",
+            "canary",
+            "--format",
+            "json",
+            "--exit-code",
+        ])
+        .unwrap();
+        let Cmd::Diff(args) = cli.cmd else {
+            panic!("expected Diff subcommand");
+        };
+        assert_eq!((args.env_a.as_str(), args.env_b.as_str()), ("prod", "prod"));
+        assert_eq!(args.override_b, ["canary"]);
+        assert!(args.override_a.is_empty());
+        assert!(matches!(args.format, super::DiffFormat::Json));
+        assert!(args.exit_code && !args.show_secrets);
+        assert!(Cli::try_parse_from(["envoke", "diff", "prod"]).is_err());
+    }
+
+    #[test]
+    fn explain_parses() {
+        let cli = Cli::try_parse_from([
+            "envoke",
+            "explain",
+            "DB_URL",
+            "prod",
+            "--show-value",
+            "-O",
+            "ci",
+        ])
+        .unwrap();
+        let Cmd::Explain(args) = cli.cmd else {
+            panic!("expected Explain subcommand");
+        };
+        assert_eq!(args.var, "DB_URL");
+        assert_eq!(args.env, "prod");
+        assert!(args.show_value);
+        assert_eq!(cli.resolve.overrides, ["ci"]);
+    }
+
     #[test]
     fn render_alias_r_works() {
         let cli = Cli::try_parse_from(["envoke", "r", "prod"]).unwrap();
         let Cmd::Render(args) = cli.cmd else {
             panic!("expected Render subcommand");
         };
-        assert_eq!(args.env, "prod");
+        assert_eq!(args.env.as_deref(), Some("prod"));
     }
 
     #[test]
@@ -674,13 +1520,13 @@ mod cli_tests {
     #[test]
     fn global_tag_before_subcommand() {
         let cli = Cli::try_parse_from(["envoke", "--tag", "vault", "r", "prod"]).unwrap();
-        assert_eq!(cli.tags, vec!["vault".to_owned()]);
+        assert_eq!(cli.resolve.tags, vec!["vault".to_owned()]);
     }
 
     #[test]
     fn global_tag_after_subcommand() {
         let cli = Cli::try_parse_from(["envoke", "r", "prod", "--tag", "vault"]).unwrap();
-        assert_eq!(cli.tags, vec!["vault".to_owned()]);
+        assert_eq!(cli.resolve.tags, vec!["vault".to_owned()]);
     }
 
     #[test]
@@ -690,24 +1536,39 @@ mod cli_tests {
         // (not appending to) the root-level occurrences. Documented so the
         // behavior doesn't drift silently.
         let cli = Cli::try_parse_from(["envoke", "--tag", "a", "r", "prod", "--tag", "b"]).unwrap();
-        assert_eq!(cli.tags, vec!["b".to_owned()]);
+        assert_eq!(cli.resolve.tags, vec!["b".to_owned()]);
+    }
+
+    #[test]
+    fn parse_cli_records_where_env_came_from() {
+        let matches = Cli::command()
+            .try_get_matches_from(["envoke", "render", "prod", "--envs", "a,b", "-o", "{env}"])
+            .unwrap();
+        let Cmd::Render(args) = super::parse_cli(&matches).cmd else {
+            panic!("expected Render subcommand");
+        };
+        assert_eq!(args.env_source, Some(super::ValueSource::CommandLine));
     }
 
     #[test]
     fn envoke_env_fills_positional() {
-        // With ENVOKE_ENV set, the env positional becomes optional.
-        // SAFETY: test sets and unsets a process-wide env var; nextest runs
-        // each test in its own process, so this is isolated.
-        unsafe {
-            std::env::set_var("ENVOKE_ENV", "prod");
-        }
-        let cli = Cli::try_parse_from(["envoke", "r"]).unwrap();
-        unsafe {
-            std::env::remove_var("ENVOKE_ENV");
-        }
-        let Cmd::Render(args) = cli.cmd else {
+        // With ENVOKE_ENV set, the env positional becomes optional. Setting
+        // it here would race other tests, so read `PATH` (always set) in its
+        // place.
+        let mut cmd = Cli::command();
+        let render = cmd.find_subcommand("render").unwrap();
+        let env = render
+            .get_arguments()
+            .find(|a| a.get_id() == "env")
+            .unwrap();
+        assert_eq!(env.get_env(), Some(std::ffi::OsStr::new("ENVOKE_ENV")));
+
+        cmd = cmd.mut_subcommand("render", |render| render.mut_arg("env", |a| a.env("PATH")));
+        let matches = cmd.try_get_matches_from(["envoke", "r"]).unwrap();
+        let Cmd::Render(args) = super::parse_cli(&matches).cmd else {
             panic!("expected Render subcommand");
         };
-        assert_eq!(args.env, "prod");
+        assert_eq!(args.env, std::env::var("PATH").ok());
+        assert_eq!(args.env_source, Some(super::ValueSource::EnvVariable));
     }
 }
@@ -0,0 +1,236 @@
+//! Subprocess execution for `cmd:` and `sh:` sources.
+
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::process::Child;
+use std::process::Command;
+use std::process::ExitStatus;
+use std::process::Stdio;
+#[cfg(unix)]
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Captured result of a finished subprocess.
+pub struct Output {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Why a subprocess did not produce an [`Output`].
+pub enum RunError {
+    /// The process could not be spawned (or waited on).
+    Io(io::Error),
+    /// The process outlived its timeout and was killed.
+    TimedOut,
+    /// Cancellation was requested while the process ran, and it was killed.
+    Cancelled,
+}
+
+/// Longest pause between liveness checks while waiting.
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Run `command` to completion, capturing stdout and stderr.
+///
+/// `stdin`, when given, is written to the child's standard input and then
+/// closed; otherwise the child reads from a closed stream, as with
+/// [`Command::output`]. With a `timeout`, the child is killed once it runs
+/// longer than that and [`RunError::TimedOut`] is returned without waiting for
+/// any grandchildren that may still hold the output pipes open. `cancelled` is
+/// polled while the child runs; once it returns `true` the child is killed the
+/// same way and [`RunError::Cancelled`] is returned.
+///
+/// With a controlling terminal, the child stays in envoke's process group so
+/// it can prompt on the terminal (`gpg`, `ssh`, password managers) without
+/// being stopped by SIGTTIN; killing it kills the child alone. Without one
+/// (CI, cron), on Unix the child leads its own process group, so killing it
+/// also kills anything it started.
+pub fn run(
+    mut command: Command,
+    stdin: Option<&[u8]>,
+    timeout: Option<Duration>,
+    cancelled: &dyn Fn() -> bool,
+) -> Result<Output, RunError> {
+    #[cfg(unix)]
+    if !has_terminal() {
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    }
+    command
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = command.spawn().map_err(RunError::Io)?;
+
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        let input = input.to_vec();
+        // A child that never reads its stdin closes the pipe on exit; the
+        // resulting broken-pipe error is irrelevant to the outcome.
+        thread::spawn(move || {
+            let _ = pipe.write_all(&input);
+        });
+    }
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+
+    let status = wait(&mut child, timeout.map(|t| Instant::now() + t), cancelled)?;
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Whether envoke has a controlling terminal that a child could read from.
+/// Checked once per process.
+#[cfg(unix)]
+fn has_terminal() -> bool {
+    static HAS_TERMINAL: OnceLock<bool> = OnceLock::new();
+    *HAS_TERMINAL.get_or_init(|| std::fs::File::open("/dev/tty").is_ok())
+}
+
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+fn wait(
+    child: &mut Child,
+    deadline: Option<Instant>,
+    cancelled: &dyn Fn() -> bool,
+) -> Result<ExitStatus, RunError> {
+    let mut interval = Duration::from_millis(1);
+    loop {
+        if let Some(status) = child.try_wait().map_err(RunError::Io)? {
+            // A child sharing envoke's process group gets the terminal's
+            // Ctrl-C too, and may die of it before envoke notices.
+            #[cfg(unix)]
+            if std::os::unix::process::ExitStatusExt::signal(&status).is_some() && cancelled() {
+                return Err(RunError::Cancelled);
+            }
+            return Ok(status);
+        }
+        if cancelled() {
+            kill(child);
+            return Err(RunError::Cancelled);
+        }
+        let mut pause = interval;
+        if let Some(deadline) = deadline {
+            let now = Instant::now();
+            if now >= deadline {
+                kill(child);
+                return Err(RunError::TimedOut);
+            }
+            pause = pause.min(deadline - now);
+        }
+        thread::sleep(pause);
+        interval = (interval * 2).min(MAX_POLL_INTERVAL);
+    }
+}
+
+/// Kill the child, along with its process group when it leads one, then
+/// reap it.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    if !has_terminal()
+        && let Ok(pid) = libc::pid_t::try_from(child.id())
+    {
+        // SAFETY: kill(2) has no memory-safety preconditions. The child has
+        // not been reaped yet, so its pid (and process group) cannot have
+        // been reused.
+        unsafe {
+            libc::kill(-pid, libc::SIGKILL);
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        command
+    }
+
+    fn never() -> bool {
+        false
+    }
+
+    #[test]
+    fn captures_stdout_and_stderr() {
+        let Ok(out) = run(sh("echo out; echo err >&2"), None, None, &never) else {
+            panic!("run failed");
+        };
+        assert!(out.status.success());
+        assert_eq!(out.stdout, b"out\n");
+        assert_eq!(out.stderr, b"err\n");
+    }
+
+    #[test]
+    fn feeds_stdin() {
+        let Ok(out) = run(sh("tr a-z A-Z"), Some(b"hello"), None, &never) else {
+            panic!("run failed");
+        };
+        assert_eq!(out.stdout, b"HELLO");
+    }
+
+    #[test]
+    fn kills_on_timeout() {
+        let mut sleep = Command::new("sleep");
+        sleep.arg("5");
+        let started = Instant::now();
+        let result = run(sleep, None, Some(Duration::from_millis(100)), &never);
+        assert!(matches!(result, Err(RunError::TimedOut)));
+        assert!(started.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn completes_within_timeout() {
+        let Ok(out) = run(sh("echo fast"), None, Some(Duration::from_secs(10)), &never) else {
+            panic!("run failed");
+        };
+        assert_eq!(out.stdout, b"fast\n");
+    }
+
+    #[test]
+    fn cancel_kills_process_group_without_terminal() {
+        let dir =
+            std::env::temp_dir().join(format!("envoke-process-cancel-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let marker = dir.join("survived");
+        // The grandchild would create the marker if it outlived the kill.
+        let script = format!("(sleep 0.3; touch '{}') & sleep 5", marker.display());
+        let started = Instant::now();
+        let result = run(sh(&script), None, None, &|| {
+            started.elapsed() > Duration::from_millis(100)
+        });
+        assert!(matches!(result, Err(RunError::Cancelled)));
+        assert!(started.elapsed() < Duration::from_secs(4));
+        thread::sleep(Duration::from_millis(500));
+        // With a terminal the child shares envoke's group and is killed
+        // alone.
+        assert_eq!(
+            marker.exists(),
+            has_terminal(),
+            "grandchild outlived cancellation"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
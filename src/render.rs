@@ -5,6 +5,9 @@ use std::path::Path;
 use miette::Context;
 use miette::IntoDiagnostic;
 
+use crate::error::ResolveError;
+use crate::error::ResolveErrorKind;
+use crate::resolve::Origin;
 use crate::resolve::Resolved;
 
 const SHELL_EXPORT_TEMPLATE: &str = include_str!("templates/shell-export.j2");
@@ -56,7 +59,7 @@ impl Format {
 }
 
 /// Metadata about the current invocation, exposed to templates as `meta`.
-#[derive(serde::Serialize)]
+#[derive(Debug, Default, serde::Serialize)]
 pub struct Meta {
     /// RFC 3339 timestamp of when the invocation started.
     pub timestamp: String,
@@ -72,13 +75,30 @@ pub struct Meta {
     pub tags: Vec<String>,
     /// Active `--override` values.
     pub overrides: Vec<String>,
+    /// Whether `--provenance` was passed. The dotenv and shell-export
+    /// presets then add a comment naming each variable's source.
+    pub provenance: bool,
 }
 
 /// Rich variable entry exposed in the `variables` map.
 #[derive(serde::Serialize)]
-struct VariableEntry {
-    value: String,
-    description: Option<String>,
+struct VariableEntry<'a> {
+    value: &'a str,
+    description: Option<&'a str>,
+    /// Kind of the source that produced the value.
+    source: &'static str,
+    /// Where in the selection chain the source was found.
+    origin: &'a Origin,
+}
+
+/// A variable that failed to resolve, exposed in the `errors` map.
+#[derive(serde::Serialize)]
+struct ErrorEntry {
+    /// One-line summary of the error (see [`summary`]), without the variable
+    /// and environment prefix.
+    message: String,
+    /// Diagnostic code, e.g. `envoke::cmd_non_zero`.
+    code: Option<String>,
 }
 
 /// Everything needed to render output.
@@ -87,6 +107,57 @@ pub struct RenderContext {
     pub resolved: Vec<Resolved>,
     /// Invocation metadata exposed as `meta` in templates.
     pub meta: Meta,
+    /// Variables that failed to resolve under `--keep-going`, exposed as
+    /// `errors` in templates. Presets that support comments list them as
+    /// comments; the rest omit them.
+    pub errors: Vec<ResolveError>,
+    /// Fail on undefined values instead of rendering them as empty strings.
+    pub strict: bool,
+    /// Make the impure template functions (`env`, `file`, ...) errors, as
+    /// under `settings.pure_templates`. Otherwise `file()` paths are relative
+    /// to the directory of `meta.config_file`.
+    pub pure: bool,
+}
+
+/// Longest error summary written to output, in characters.
+const SUMMARY_LIMIT: usize = 120;
+
+/// Summarize an error for rendered output, which is often committed or
+/// shared: the first line of the message, truncated. A failed command is
+/// named by its source kind only, since its arguments and stderr may carry
+/// secrets. The full error is still reported on envoke's own stderr.
+fn summary(kind: &ResolveErrorKind) -> String {
+    let message = match kind {
+        ResolveErrorKind::CmdFailed {
+            source_kind,
+            reason,
+            ..
+        } => format!("`{source_kind}` source failed to start: {reason}"),
+        ResolveErrorKind::CmdNonZero {
+            source_kind,
+            exit_code,
+            ..
+        } => match exit_code {
+            Some(code) => format!("`{source_kind}` source exited with exit code {code}"),
+            None => format!("`{source_kind}` source was killed by a signal"),
+        },
+        ResolveErrorKind::Timeout { timeout, .. } => {
+            format!("`cmd` source timed out after {timeout:?}")
+        }
+        ResolveErrorKind::Cancelled { .. } => "killed because another source failed".to_owned(),
+        ResolveErrorKind::Interrupted { .. } => "interrupted".to_owned(),
+        ResolveErrorKind::RetriesExhausted { history, last } => format!(
+            "{} (gave up after {} attempts)",
+            summary(last),
+            history.len()
+        ),
+        kind => kind.to_string(),
+    };
+    let line = message.lines().next().unwrap_or_default();
+    match line.char_indices().nth(SUMMARY_LIMIT) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line.to_owned(),
+    }
 }
 
 /// Render a template string with the given context.
@@ -98,17 +169,34 @@ fn render(ctx: &RenderContext, template: &str) -> miette::Result<String> {
         variables.insert(
             &r.name,
             VariableEntry {
-                value: r.value.clone(),
-                description: r.description.clone(),
+                value: &r.value,
+                description: r.description.as_deref(),
+                source: r.source,
+                origin: &r.origin,
             },
         );
         v.insert(&r.name, &r.value);
     }
-
-    let mut env = minijinja::Environment::new();
-    env.add_filter("shell_escape", shell_escape);
-    env.add_filter("dotenv_escape", dotenv_escape);
-    env.add_filter("wrap", wrap);
+    let errors: BTreeMap<&str, ErrorEntry> = ctx
+        .errors
+        .iter()
+        .map(|e| {
+            let entry = ErrorEntry {
+                message: summary(&e.kind),
+                code: miette::Diagnostic::code(&e.kind).map(|c| c.to_string()),
+            };
+            (e.variable.as_str(), entry)
+        })
+        .collect();
+
+    let mut env = crate::filters::environment(
+        ctx.strict,
+        (!ctx.pure).then(|| {
+            Path::new(&ctx.meta.config_file)
+                .parent()
+                .unwrap_or(Path::new("."))
+        }),
+    );
     env.add_template("output", template)
         .into_diagnostic()
         .context("failed to parse output template")?;
@@ -119,6 +207,7 @@ fn render(ctx: &RenderContext, template: &str) -> miette::Result<String> {
             variables => variables,
             v => v,
             meta => &ctx.meta,
+            errors => errors,
         })
         .into_diagnostic()
         .context("failed to render output template")?;
@@ -127,11 +216,20 @@ fn render(ctx: &RenderContext, template: &str) -> miette::Result<String> {
 }
 
 /// Render using one of the built-in format presets.
+///
+/// # Errors
+///
+/// Fails if the template cannot be rendered with the given context.
 pub fn render_format(ctx: &RenderContext, format: Format) -> miette::Result<String> {
     render(ctx, format.template())
 }
 
 /// Render using a user-supplied template file.
+///
+/// # Errors
+///
+/// Fails if the file cannot be read, or is not a valid template for the
+/// given context.
 pub fn render_custom(ctx: &RenderContext, path: &Path) -> miette::Result<String> {
     let template = fs::read_to_string(path)
         .into_diagnostic()
@@ -139,62 +237,36 @@ pub fn render_custom(ctx: &RenderContext, path: &Path) -> miette::Result<String>
     render(ctx, &template)
 }
 
-/// Escape a value for safe inclusion in a single-quoted shell string.
-///
-/// Embedded single quotes are replaced with `'\''` (end quote, escaped quote,
-/// start quote).
-pub(crate) fn shell_escape(value: &str) -> String {
-    value.replace('\'', "'\\''")
-}
-
-/// Encode a value as a portable `.env` token, delimiters included.
-///
-/// Returns the fully-quoted form — do not wrap the output in additional
-/// quotes when using this filter in templates.
-///
-/// Encoding rule:
-/// - If the value contains no `'` and no newline, emit `'value'` (single-
-///   quoted literal). Every byte passes through unchanged; `$` is never
-///   expanded inside single quotes by any dotenv parser.
-/// - Otherwise, emit `"value"` with the conservative escape set shared by
-///   `dotenvy`, `godotenv`, `python-dotenv`, and similar parsers: `\\`, `\"`,
-///   `\$`, and `\n` for newline. All other bytes (including literal tab and CR)
-///   pass through as-is. `\t` and `\r` escape *sequences* are deliberately not
-///   emitted because `dotenvy` rejects unknown escapes as parse errors.
-pub(crate) fn dotenv_escape(value: &str) -> String {
-    let needs_double_quote = value.contains('\'') || value.contains('\n');
-    let mut out = String::with_capacity(value.len() + 2);
-    if !needs_double_quote {
-        out.push('\'');
-        out.push_str(value);
-        out.push('\'');
-        return out;
-    }
-    out.push('"');
-    for c in value.chars() {
-        match c {
-            '\\' => out.push_str(r"\\"),
-            '"' => out.push_str(r#"\""#),
-            '$' => out.push_str(r"\$"),
-            '\n' => out.push_str(r"\n"),
-            other => out.push(other),
-        }
+/// Compare freshly `rendered` output with the `existing` contents of a file
+/// rendered earlier, as `render --check` does. A line containing
+/// `@generated` matches any other such line at the same position, since the
+/// header names the invocation and timestamp. Returns a unified diff from
+/// `existing` to `rendered` (labelled with `path`) when they differ.
+#[must_use]
+pub fn drift(path: &str, existing: &str, rendered: &str) -> Option<String> {
+    let mut existing_lines = existing.split_inclusive('\n');
+    let expected: String = rendered
+        .split_inclusive('\n')
+        .map(|line| match existing_lines.next() {
+            Some(old) if line.contains("@generated") && old.contains("@generated") => old,
+            _ => line,
+        })
+        .collect();
+    if expected == existing {
+        return None;
     }
-    out.push('"');
-    out
-}
-
-/// Wraps a string into lines of maximum `width` columns.
-pub(crate) fn wrap(s: &str, width: usize) -> Vec<String> {
-    textwrap::wrap(s, width)
-        .into_iter()
-        .map(|c| c.to_string())
-        .collect()
+    let diff = similar::TextDiff::from_lines(existing, &expected);
+    Some(
+        diff.unified_diff()
+            .header(path, &format!("{path} (rendered)"))
+            .to_string(),
+    )
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::filters::dotenv_escape;
 
     fn test_meta() -> Meta {
         Meta {
@@ -205,6 +277,7 @@ mod tests {
             config_file: "envoke.yaml".to_owned(),
             tags: vec![],
             overrides: vec![],
+            provenance: false,
         }
     }
 
@@ -215,8 +288,12 @@ mod tests {
                 name: "FOO".to_owned(),
                 value: "bar".to_owned(),
                 description: None,
+                ..Default::default()
             }],
             meta: test_meta(),
+            errors: Vec::new(),
+            strict: false,
+            pure: false,
         };
         let output = render_format(&ctx, Format::ShellExport).unwrap();
         assert!(output.contains("export FOO='bar'"));
@@ -229,8 +306,12 @@ mod tests {
                 name: "DB".to_owned(),
                 value: "localhost".to_owned(),
                 description: Some("Database host".to_owned()),
+                ..Default::default()
             }],
             meta: test_meta(),
+            errors: Vec::new(),
+            strict: false,
+            pure: false,
         };
         let output = render_format(&ctx, Format::ShellExport).unwrap();
         assert!(output.contains("# Database host\n"));
@@ -244,8 +325,12 @@ mod tests {
                 name: "VAL".to_owned(),
                 value: "it's a test".to_owned(),
                 description: None,
+                ..Default::default()
             }],
             meta: test_meta(),
+            errors: Vec::new(),
+            strict: false,
+            pure: false,
         };
         let output = render_format(&ctx, Format::ShellExport).unwrap();
         assert!(output.contains("export VAL='it'\\''s a test'"));
@@ -259,14 +344,19 @@ mod tests {
                     name: "A".to_owned(),
                     value: "1".to_owned(),
                     description: None,
+                    ..Default::default()
                 },
                 Resolved {
                     name: "B".to_owned(),
                     value: "2".to_owned(),
                     description: None,
+                    ..Default::default()
                 },
             ],
             meta: test_meta(),
+            errors: Vec::new(),
+            strict: false,
+            pure: false,
         };
         let template =
             "{% for name, var in variables | items %}{{ name }}={{ var.value }}\n{% endfor %}";
@@ -274,6 +364,160 @@ mod tests {
         assert_eq!(output, "A=1\nB=2\n");
     }
 
+    #[test]
+    fn test_render_provenance() {
+        let ctx = RenderContext {
+            resolved: vec![Resolved {
+                name: "DB".to_owned(),
+                value: "localhost".to_owned(),
+                description: Some("Database host".to_owned()),
+                source: "cmd",
+                origin: Origin::OverrideEnv {
+                    name: "local".to_owned(),
+                    environment: "local".to_owned(),
+                },
+                secret: false,
+            }],
+            meta: Meta {
+                provenance: true,
+                ..test_meta()
+            },
+            errors: Vec::new(),
+            strict: false,
+            pure: false,
+        };
+        let output = render_format(&ctx, Format::Dotenv).unwrap();
+        assert!(output.contains(
+            "# Database host\n# source: cmd (overrides.local.envs.local)\nDB='localhost'\n"
+        ));
+        let output = render_format(&ctx, Format::ShellExport).unwrap();
+        assert!(output.contains("# source: cmd (overrides.local.envs.local)\nexport DB="));
+
+        let template = "{{ variables.DB.source }} {{ variables.DB.origin.kind }} \
+                        {{ variables.DB.origin.override }}";
+        assert_eq!(render(&ctx, template).unwrap(), "cmd env local");
+    }
+
+    #[test]
+    fn test_render_without_provenance_has_no_source_comment() {
+        let ctx = RenderContext {
+            resolved: vec![Resolved {
+                name: "DB".to_owned(),
+                value: "localhost".to_owned(),
+                source: "literal",
+                ..Default::default()
+            }],
+            meta: test_meta(),
+            errors: Vec::new(),
+            strict: false,
+            pure: false,
+        };
+        let output = render_format(&ctx, Format::Dotenv).unwrap();
+        assert!(!output.contains("# source:"));
+    }
+
+    #[test]
+    fn test_render_errors() {
+        let ctx = RenderContext {
+            resolved: vec![Resolved {
+                name: "A".to_owned(),
+                value: "1".to_owned(),
+                ..Default::default()
+            }],
+            meta: test_meta(),
+            errors: vec![ResolveError {
+                variable: "B".to_owned(),
+                environment: "local".to_owned(),
+                kind: crate::error::ResolveErrorKind::TemplateRender {
+                    reason: "line one\nline two".to_owned(),
+                },
+            }],
+            strict: false,
+            pure: false,
+        };
+        let output = render_format(&ctx, Format::Dotenv).unwrap();
+        assert!(output.ends_with("A='1'\n# B: not resolved: template error: line one\n"));
+        let output = render_format(&ctx, Format::Json).unwrap();
+        assert_eq!(output, r#"{"A":"1"}"#);
+
+        let template = "{% for name, e in errors | items %}{{ name }} {{ e.code }}{% endfor %}";
+        assert_eq!(render(&ctx, template).unwrap(), "B envoke::template_render");
+    }
+
+    #[test]
+    fn test_render_errors_leave_out_stderr() {
+        let failed = crate::error::ResolveErrorKind::CmdNonZero {
+            source_kind: "cmd",
+            command: vec!["vault".to_owned(), "--token=s3cr3t".to_owned()],
+            exit_code: Some(2),
+            stderr: "token s3cr3t rejected".to_owned(),
+        };
+        let ctx = RenderContext {
+            resolved: Vec::new(),
+            meta: test_meta(),
+            errors: vec![
+                ResolveError {
+                    variable: "LONG".to_owned(),
+                    environment: "local".to_owned(),
+                    kind: crate::error::ResolveErrorKind::TemplateRender {
+                        reason: "x".repeat(200),
+                    },
+                },
+                ResolveError {
+                    variable: "SIGNALED".to_owned(),
+                    environment: "local".to_owned(),
+                    kind: crate::error::ResolveErrorKind::CmdNonZero {
+                        source_kind: "sh",
+                        command: vec!["sh".to_owned(), "-c".to_owned(), "kill $$".to_owned()],
+                        exit_code: None,
+                        stderr: String::new(),
+                    },
+                },
+                ResolveError {
+                    variable: "TOKEN".to_owned(),
+                    environment: "local".to_owned(),
+                    kind: crate::error::ResolveErrorKind::RetriesExhausted {
+                        history: vec![failed.to_string(), failed.to_string()],
+                        last: Box::new(failed),
+                    },
+                },
+            ],
+            strict: false,
+            pure: false,
+        };
+        let output = render_format(&ctx, Format::Dotenv).unwrap();
+        assert!(!output.contains("s3cr3t"), "{output}");
+        assert!(output.ends_with(&format!(
+            "# LONG: not resolved: template error: {}...\n\
+             # SIGNALED: not resolved: `sh` source was killed by a signal\n\
+             # TOKEN: not resolved: `cmd` source exited with exit code 2 (gave up after 2 attempts)\n",
+            "x".repeat(SUMMARY_LIMIT - "template error: ".len())
+        )));
+    }
+
+    #[test]
+    fn test_render_strict() {
+        let mut ctx = RenderContext {
+            resolved: vec![Resolved {
+                name: "A".to_owned(),
+                value: "1".to_owned(),
+                ..Default::default()
+            }],
+            meta: test_meta(),
+            errors: Vec::new(),
+            strict: false,
+            pure: false,
+        };
+        let template = "[{{ meta.enviroment }}]";
+        assert_eq!(render(&ctx, template).unwrap(), "[]");
+        ctx.strict = true;
+        assert!(render(&ctx, template).is_err());
+        // Every preset stays valid under strict mode.
+        for format in <Format as clap::ValueEnum>::value_variants() {
+            render_format(&ctx, *format).unwrap();
+        }
+    }
+
     #[test]
     fn test_render_v_shorthand() {
         let ctx = RenderContext {
@@ -281,8 +525,12 @@ mod tests {
                 name: "DB_URL".to_owned(),
                 value: "postgres://localhost".to_owned(),
                 description: None,
+                ..Default::default()
             }],
             meta: test_meta(),
+            errors: Vec::new(),
+            strict: false,
+            pure: false,
         };
         let template = "url={{ v.DB_URL }}";
         let output = render(&ctx, template).unwrap();
@@ -294,6 +542,9 @@ mod tests {
         let ctx = RenderContext {
             resolved: vec![],
             meta: test_meta(),
+            errors: Vec::new(),
+            strict: false,
+            pure: false,
         };
         let template = "env={{ meta.environment }} file={{ meta.config_file }}";
         let output = render(&ctx, template).unwrap();
@@ -305,6 +556,9 @@ mod tests {
         let ctx = RenderContext {
             resolved: vec![],
             meta: test_meta(),
+            errors: Vec::new(),
+            strict: false,
+            pure: false,
         };
         let template = "{% for arg in meta.invocation_args %}[{{ arg }}]{% endfor %}";
         let output = render(&ctx, template).unwrap();
@@ -319,14 +573,19 @@ mod tests {
                     name: "A_VAR".to_owned(),
                     value: "hello".to_owned(),
                     description: Some("A description".to_owned()),
+                    ..Default::default()
                 },
                 Resolved {
                     name: "B_VAR".to_owned(),
                     value: "world".to_owned(),
                     description: None,
+                    ..Default::default()
                 },
             ],
             meta: test_meta(),
+            errors: Vec::new(),
+            strict: false,
+            pure: false,
         };
         let output = render_format(&ctx, Format::ShellExport).unwrap();
         insta::assert_snapshot!(output, @"
@@ -349,19 +608,25 @@ mod tests {
                     name: "A".to_owned(),
                     value: "hello".to_owned(),
                     description: Some("plain ascii".to_owned()),
+                    ..Default::default()
                 },
                 Resolved {
                     name: "B".to_owned(),
                     value: "it\"s\nmultiline".to_owned(),
                     description: None,
+                    ..Default::default()
                 },
                 Resolved {
                     name: "C".to_owned(),
                     value: "long description".to_owned(),
                     description: Some("Lorem ipsum dolor sit amet, consectetur adipiscing elit. Proin eget elementum libero, ut iaculis odio. Nulla vitae ante volutpat, tincidunt neque ut, sagittis arcu. Aenean sed arcu pretium purus sagittis.".to_owned()),
+                    ..Default::default()
                 },
             ],
             meta: test_meta(),
+            errors: Vec::new(),
+            strict: false,
+            pure: false,
         }
     }
 
@@ -531,12 +796,34 @@ mod tests {
                 name: "A".to_string(),
                 value: "a".to_string(),
                 description: Some("One two three four".to_string()),
+                ..Default::default()
             }],
             meta: test_meta(),
+            errors: Vec::new(),
+            strict: false,
+            pure: false,
         };
         let template =
             "{% for line in variables.A.description | wrap(10) %}# {{ line }}\n{% endfor %}";
         let output = render(&context, template).unwrap();
         assert_eq!(output, "# One two\n# three four\n");
     }
+
+    #[test]
+    fn test_drift_ignores_generated_header() {
+        let old = "# @generated by `envoke render ci -o .env` at 2025-01-01T00:00:00+00:00\nA='1'\nB='2'\n";
+        let same = "# @generated by `envoke render ci --check .env` at 2026-02-02T00:00:00+00:00\nA='1'\nB='2'\n";
+        assert_eq!(drift(".env", old, same), None);
+
+        let changed = "# @generated by `envoke render ci --check .env` at 2026-02-02T00:00:00+00:00\nA='1'\nB='3'\n";
+        let diff = drift(".env", old, changed).unwrap();
+        assert!(
+            diff.starts_with("--- .env\n+++ .env (rendered)\n"),
+            "{diff}"
+        );
+        assert!(diff.contains("-B='2'\n+B='3'\n"), "{diff}");
+        assert!(!diff.contains("2026-02-02"), "{diff}");
+
+        assert!(drift(".env", "", "A='1'\n").unwrap().contains("+A='1'"));
+    }
 }
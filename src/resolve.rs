@@ -1,40 +1,74 @@
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::collections::VecDeque;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::path::PathBuf;
 use std::process::Command;
 use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::mpsc;
+use std::time::Duration;
+use std::time::Instant;
 
 use tracing::debug;
 use tracing::warn;
 
+use crate::bundle;
+use crate::cache::Cache;
+use crate::config::BundleFormat;
+use crate::config::BundleRef;
 use crate::config::Config;
+use crate::config::ForEach;
+use crate::config::Settings;
 use crate::config::Source;
+use crate::config::Variable;
+use crate::dry_run::DryRun;
+use crate::dry_run::Step;
 use crate::error::ResolveError;
 use crate::error::ResolveErrorKind;
+use crate::error::ResolveErrors;
+use crate::expand;
+use crate::explain::Explanation;
+use crate::explain::OverrideCheck;
+use crate::explain::Selection;
+use crate::explain::TagDecision;
+use crate::interrupt;
+use crate::process;
+use crate::process::RunError;
+use crate::timings::SourceTiming;
 
-/// Upper bound on concurrent workers resolving `cmd:` / `sh:` sources.
+/// Default upper bound on concurrent workers resolving `cmd:` / `sh:`
+/// sources, used unless `--jobs` or `settings.jobs` says otherwise.
 ///
 /// External sources are blocking subprocess spawns, so CPU count is the wrong
 /// dimension to scale by. A small constant caps OS-thread and subprocess
 /// pressure on large configs while still giving real parallelism on typical
 /// hardware.
-const MAX_EXTERNAL_JOBS: usize = 8;
+pub const DEFAULT_JOBS: usize = 8;
 
-/// A successfully resolved variable with its value and optional description.
-#[derive(Debug, serde::Serialize)]
+/// A successfully resolved variable with its value, optional description,
+/// and where the value came from.
+#[derive(Debug, Default, serde::Serialize)]
 pub struct Resolved {
     pub name: String,
     pub value: String,
     pub description: Option<String>,
+    /// Kind of the source that produced the value (see [`Source::kind`]).
+    pub source: &'static str,
+    /// Where in the selection chain the source was found.
+    pub origin: Origin,
+    /// Whether the variable is marked `secret`.
+    pub secret: bool,
 }
 
 /// Metadata available in value source templates as `{{ meta.<field> }}`.
 ///
 /// Exposes `environment`, `tags`, `overrides`, and `timestamp`.
-#[derive(serde::Serialize)]
-struct TemplateMeta<'a> {
+#[derive(Default, serde::Serialize)]
+pub(crate) struct TemplateMeta<'a> {
     environment: &'a str,
     tags: &'a [String],
     overrides: &'a [String],
@@ -48,14 +82,59 @@ fn template_references(tmpl: &str) -> Result<HashSet<String>, minijinja::Error>
     Ok(parsed.undeclared_variables(false))
 }
 
-/// Topologically sort variables so dependencies are resolved before dependents.
+/// Graph node under which a bundle is scheduled. The prefix keeps bundles
+/// apart from variables, whose names are plain identifiers.
+fn bundle_node(name: &str) -> String {
+    format!("bundle:{name}")
+}
+
+/// Names a source depends on: variables referenced by a template, listed in
+/// the `needs` of a `cmd:` / `sh:` source, or the bundle a `from_bundle`
+/// source reads.
+fn source_references(source: &Source) -> Result<HashSet<String>, minijinja::Error> {
+    match source {
+        Source::Template(tmpl) => template_references(tmpl),
+        Source::FromBundle(r) => Ok(HashSet::from([bundle_node(&r.bundle)])),
+        other => Ok(other.needs().iter().cloned().collect()),
+    }
+}
+
+/// Nodes a source depends on, in name order: [`source_references`] without
+/// what templates get from envoke itself, i.e. `meta` and the template
+/// functions (unless `is_node` says a node shadows one).
+fn dependencies(
+    source: &Source,
+    is_node: impl Fn(&str) -> bool,
+) -> Result<Vec<String>, minijinja::Error> {
+    let template = matches!(source, Source::Template(_));
+    let mut refs: Vec<String> = source_references(source)?
+        .into_iter()
+        .filter(|dep| {
+            !template || !(dep == "meta" || (!is_node(dep) && crate::filters::is_function(dep)))
+        })
+        .collect();
+    refs.sort();
+    Ok(refs)
+}
+
+/// Dependency graph over the nodes (variables and bundles) of one resolution.
+struct Graph {
+    /// Direct dependencies of each node.
+    dependencies: HashMap<String, Vec<String>>,
+    /// Nodes that directly depend on each node.
+    dependents: HashMap<String, Vec<String>>,
+    /// Every node after all of its dependencies.
+    order: Vec<String>,
+}
+
+/// Build the dependency graph and check that it can be resolved.
 ///
-/// Returns the sorted variable names, or a list of errors for cycles or unknown
-/// references.
-fn topological_sort(
+/// A topological sort (Kahn's algorithm) verifies that the graph is acyclic.
+/// On failure, returns a list of errors for cycles or unknown references.
+fn build_graph(
     variables: &BTreeMap<String, Source>,
     environment: &str,
-) -> Result<Vec<String>, Vec<ResolveError>> {
+) -> Result<Graph, Vec<ResolveError>> {
     let mut in_degree: HashMap<String, usize> = HashMap::new();
     let mut dependents: HashMap<String, Vec<String>> = HashMap::new();
     let mut errors = Vec::new();
@@ -65,35 +144,30 @@ fn topological_sort(
     }
 
     for (name, source) in variables {
-        if let Source::Template(tmpl) = source {
-            let refs = match template_references(tmpl) {
-                Ok(refs) => refs,
-                Err(e) => {
-                    errors.push(ResolveError {
-                        variable: name.clone(),
-                        environment: environment.to_owned(),
-                        kind: ResolveErrorKind::TemplateRender {
-                            reason: e.to_string(),
-                        },
-                    });
-                    continue;
-                }
-            };
-            for dep in refs {
-                if dep == "meta" {
-                    continue;
-                }
-                if !variables.contains_key(&dep) {
-                    errors.push(ResolveError {
-                        variable: name.clone(),
-                        environment: environment.to_owned(),
-                        kind: ResolveErrorKind::UnknownReference { name: dep },
-                    });
-                    continue;
-                }
-                *in_degree.entry(name.clone()).or_insert(0) += 1;
-                dependents.entry(dep).or_default().push(name.clone());
+        let refs = match dependencies(source, |dep| variables.contains_key(dep)) {
+            Ok(refs) => refs,
+            Err(e) => {
+                errors.push(ResolveError {
+                    variable: name.clone(),
+                    environment: environment.to_owned(),
+                    kind: ResolveErrorKind::TemplateRender {
+                        reason: e.to_string(),
+                    },
+                });
+                continue;
+            }
+        };
+        for dep in refs {
+            if !variables.contains_key(&dep) {
+                errors.push(ResolveError {
+                    variable: name.clone(),
+                    environment: environment.to_owned(),
+                    kind: ResolveErrorKind::UnknownReference { name: dep },
+                });
+                continue;
             }
+            *in_degree.entry(name.clone()).or_insert(0) += 1;
+            dependents.entry(dep).or_default().push(name.clone());
         }
     }
 
@@ -101,40 +175,45 @@ fn topological_sort(
         return Err(errors);
     }
 
-    // Kahn's algorithm.
-    let mut queue: VecDeque<String> = in_degree
+    // Kahn's algorithm; any node left over is part of a cycle. Roots are
+    // taken in name order so the order is stable between runs.
+    let mut queue: Vec<String> = in_degree
         .iter()
         .filter(|(_, deg)| **deg == 0)
         .map(|(name, _)| name.clone())
         .collect();
-    let mut queue_vec: Vec<String> = queue.drain(..).collect();
-    queue_vec.sort_unstable();
-    queue = queue_vec.into_iter().collect();
-
-    let mut sorted = Vec::new();
-
-    while let Some(name) = queue.pop_front() {
-        sorted.push(name.clone());
-        if let Some(deps) = dependents.get(&name) {
-            let mut next = Vec::new();
-            for dep in deps {
-                let deg = in_degree.get_mut(dep).expect("in_degree entry must exist");
-                *deg -= 1;
-                if *deg == 0 {
-                    next.push(dep.clone());
-                }
+    queue.sort_unstable_by(|a, b| b.cmp(a));
+    let mut order = Vec::with_capacity(variables.len());
+    while let Some(name) = queue.pop() {
+        for dep in dependents.get(&name).into_iter().flatten() {
+            let deg = in_degree.get_mut(dep).expect("in_degree entry must exist");
+            *deg -= 1;
+            if *deg == 0 {
+                queue.push(dep.clone());
             }
-            next.sort_unstable();
-            queue.extend(next);
         }
+        order.push(name);
     }
 
-    if sorted.len() != variables.len() {
+    if order.len() != variables.len() {
         let errors = find_cycles(&in_degree, &dependents, environment);
         return Err(errors);
     }
 
-    Ok(sorted)
+    let mut dependencies: HashMap<String, Vec<String>> = HashMap::new();
+    for (dep, nodes) in &dependents {
+        for node in nodes {
+            dependencies
+                .entry(node.clone())
+                .or_default()
+                .push(dep.clone());
+        }
+    }
+    Ok(Graph {
+        dependencies,
+        dependents,
+        order,
+    })
 }
 
 /// Trace cycles among nodes that remain after Kahn's algorithm.
@@ -212,73 +291,270 @@ fn find_cycles(
     errors
 }
 
-/// Resolve a single source to its string value.
+/// Invocation-wide inputs shared by every source resolved in one
+/// [`resolve_all`] call.
+struct Scope<'a> {
+    environment: &'a str,
+    tags: &'a [String],
+    overrides: &'a [String],
+    timestamp: &'a str,
+    settings: &'a Settings,
+    cache: Option<&'a Cache>,
+    /// Size of the worker pool for `cmd:` / `sh:` sources.
+    jobs: usize,
+    /// Kill running sources as soon as one fails.
+    fail_fast: bool,
+    /// Fail templates that read undefined values.
+    strict: bool,
+    /// Directory `file()` paths in templates are relative to.
+    config_dir: &'a Path,
+    /// Never run `cmd:` / `sh:` sources. A `for_each` list they would
+    /// produce is empty.
+    dry_run: bool,
+    /// Set once running sources should be killed.
+    cancel: AtomicBool,
+}
+
+impl Scope<'_> {
+    /// Base directory for template functions, or `None` when
+    /// `settings.pure_templates` disables them.
+    fn functions(&self) -> Option<&Path> {
+        (!self.settings.pure_templates).then_some(self.config_dir)
+    }
+
+    /// Whether running sources should be killed: after a failure under
+    /// `fail_fast`, or once envoke is interrupted.
+    fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed) || interrupt::interrupted()
+    }
+
+    /// Error for a source whose `command` was killed by [`Self::cancelled`].
+    fn cancelled_kind(command: Vec<String>) -> ResolveErrorKind {
+        if interrupt::interrupted() {
+            ResolveErrorKind::Interrupted {
+                command: Some(command),
+            }
+        } else {
+            ResolveErrorKind::Cancelled { command }
+        }
+    }
+}
+
+/// Sleep for `delay`, waking early if the scope is cancelled. Returns whether
+/// the full delay elapsed.
+fn sleep_unless_cancelled(scope: &Scope<'_>, delay: Duration) -> bool {
+    const STEP: Duration = Duration::from_millis(50);
+    let deadline = Instant::now() + delay;
+    loop {
+        if scope.cancelled() {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        std::thread::sleep(STEP.min(deadline - now));
+    }
+}
+
+/// Environment entries for the resolved values a source `needs`.
+fn needed_env<'a>(
+    source: &'a Source,
+    resolved: &'a HashMap<String, String>,
+) -> impl Iterator<Item = (&'a String, &'a String)> {
+    source
+        .needs()
+        .iter()
+        .map(move |name| (name, &resolved[name]))
+}
+
+/// Resolve a single source to its string value, consulting the on-disk cache
+/// for sources that opt in to it.
 fn resolve_source(
     source: &Source,
     variable: &str,
-    environment: &str,
-    tags: &[String],
-    overrides: &[String],
-    timestamp: &str,
+    scope: &Scope<'_>,
+    resolved: &HashMap<String, String>,
+) -> Result<String, ResolveError> {
+    let Some((cache, ttl)) = scope.cache.zip(source.cache_ttl()) else {
+        return run_with_retry(source, variable, scope, resolved);
+    };
+    let needs: Vec<(&str, &str)> = needed_env(source, resolved)
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    if let Some(value) = cache.get(
+        scope.environment,
+        variable,
+        source,
+        scope.settings,
+        &needs,
+        ttl,
+    ) {
+        return Ok(value);
+    }
+    let value = run_with_retry(source, variable, scope, resolved)?;
+    cache.put(
+        scope.environment,
+        variable,
+        source,
+        scope.settings,
+        &needs,
+        &value,
+    );
+    Ok(value)
+}
+
+/// Run a source, retrying failures according to its `retry` policy.
+///
+/// When more than one attempt was made, the final error is wrapped in
+/// [`ResolveErrorKind::RetriesExhausted`] so every attempt's failure shows up
+/// in the diagnostic.
+fn run_with_retry(
+    source: &Source,
+    variable: &str,
+    scope: &Scope<'_>,
+    resolved: &HashMap<String, String>,
+) -> Result<String, ResolveError> {
+    let Some(retry) = source.retry() else {
+        return run_source(source, variable, scope, resolved);
+    };
+    let mut history = Vec::new();
+    loop {
+        let err = match run_source(source, variable, scope, resolved) {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+        history.push(err.kind.to_string());
+        let attempt = u32::try_from(history.len()).unwrap_or(u32::MAX);
+        let retryable = match &err.kind {
+            ResolveErrorKind::CmdNonZero {
+                exit_code, stderr, ..
+            } => retry.should_retry(*exit_code, Some(stderr)),
+            ResolveErrorKind::Timeout { .. } => retry.should_retry(None, None),
+            _ => false,
+        };
+        if !retryable || attempt >= retry.attempts {
+            if history.len() == 1 {
+                return Err(err);
+            }
+            return Err(ResolveError {
+                kind: ResolveErrorKind::RetriesExhausted {
+                    history,
+                    last: Box::new(err.kind),
+                },
+                ..err
+            });
+        }
+        let delay = retry.delay(attempt);
+        warn!(variable, attempt, error = %err.kind, ?delay, "source failed; retrying");
+        if !sleep_unless_cancelled(scope, delay) {
+            return Err(err);
+        }
+    }
+}
+
+/// Resolve a single source to its string value.
+fn run_source(
+    source: &Source,
+    variable: &str,
+    scope: &Scope<'_>,
     resolved: &HashMap<String, String>,
 ) -> Result<String, ResolveError> {
+    let environment = scope.environment;
     match source {
         Source::Literal(value) => {
             debug!(variable, "resolved from literal");
             Ok(value.clone())
         }
-        Source::Cmd(args) => {
+        Source::Cmd(cmd) => {
+            let cmd = cmd.options();
+            let args = &cmd.args;
             debug!(variable, ?args, "executing command");
-            let output = Command::new(&args[0])
+            let mut command = Command::new(&args[0]);
+            command
                 .args(&args[1..])
-                .output()
-                .map_err(|e| ResolveError {
-                    variable: variable.to_owned(),
-                    environment: environment.to_owned(),
-                    kind: ResolveErrorKind::CmdFailed {
+                .envs(needed_env(source, resolved))
+                .envs(&cmd.env);
+            if let Some(cwd) = &cmd.cwd {
+                command.current_dir(scope.config_dir.join(cwd));
+            }
+            let output = process::run(
+                command,
+                cmd.stdin.as_deref().map(str::as_bytes),
+                cmd.timeout.map(|t| t.0),
+                &|| scope.cancelled(),
+            )
+            .map_err(|e| ResolveError {
+                variable: variable.to_owned(),
+                environment: environment.to_owned(),
+                kind: match e {
+                    RunError::Io(e) => ResolveErrorKind::CmdFailed {
+                        source_kind: "cmd",
                         command: args.clone(),
                         reason: e.to_string(),
                     },
-                })?;
-
-            if !output.status.success() {
-                return Err(ResolveError {
-                    variable: variable.to_owned(),
-                    environment: environment.to_owned(),
-                    kind: ResolveErrorKind::CmdNonZero {
+                    RunError::TimedOut => ResolveErrorKind::Timeout {
                         command: args.clone(),
-                        exit_code: output.status.code(),
-                        stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+                        timeout: cmd.timeout.map(|t| t.0).unwrap_or_default(),
                     },
-                });
+                    RunError::Cancelled => Scope::cancelled_kind(args.clone()),
+                },
+            })?;
+
+            if !output.status.success() {
+                if !cmd.allow_failure {
+                    return Err(ResolveError {
+                        variable: variable.to_owned(),
+                        environment: environment.to_owned(),
+                        kind: ResolveErrorKind::CmdNonZero {
+                            source_kind: "cmd",
+                            command: args.clone(),
+                            exit_code: output.status.code(),
+                            stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+                        },
+                    });
+                }
+                warn!(
+                    variable,
+                    exit_code = ?output.status.code(),
+                    "command failed; using its output because allow_failure is set"
+                );
             }
 
-            let value = String::from_utf8_lossy(&output.stdout)
-                .trim_end()
+            let value = cmd
+                .trim
+                .apply(&String::from_utf8_lossy(&output.stdout))
                 .to_owned();
             debug!(variable, "resolved from command");
             Ok(value)
         }
-        Source::Sh(script) => {
-            let command = vec!["sh".to_owned(), "-c".to_owned(), script.clone()];
-            debug!(variable, %script, "executing shell script");
-            let output = Command::new("sh")
-                .args(["-c", script])
-                .output()
-                .map_err(|e| ResolveError {
+        Source::Sh(sh) => {
+            let command = sh.argv(scope.settings);
+            debug!(variable, script = sh.script(), shell = %command[0], "executing shell script");
+            let mut child = Command::new(&command[0]);
+            child.args(&command[1..]).envs(needed_env(source, resolved));
+            let output = process::run(child, None, None, &|| scope.cancelled()).map_err(|e| {
+                ResolveError {
                     variable: variable.to_owned(),
                     environment: environment.to_owned(),
-                    kind: ResolveErrorKind::CmdFailed {
-                        command: command.clone(),
-                        reason: e.to_string(),
+                    kind: match e {
+                        RunError::Io(e) => ResolveErrorKind::CmdFailed {
+                            source_kind: "sh",
+                            command: command.clone(),
+                            reason: e.to_string(),
+                        },
+                        RunError::TimedOut => unreachable!("no timeout was set"),
+                        RunError::Cancelled => Scope::cancelled_kind(command.clone()),
                     },
-                })?;
+                }
+            })?;
 
             if !output.status.success() {
                 return Err(ResolveError {
                     variable: variable.to_owned(),
                     environment: environment.to_owned(),
                     kind: ResolveErrorKind::CmdNonZero {
+                        source_kind: "sh",
                         command,
                         exit_code: output.status.code(),
                         stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
@@ -293,9 +569,7 @@ fn resolve_source(
             Ok(value)
         }
         Source::Template(tmpl) => {
-            let mut env = minijinja::Environment::new();
-            env.add_filter("shell_escape", crate::render::shell_escape);
-            env.add_filter("dotenv_escape", crate::render::dotenv_escape);
+            let env = crate::filters::environment(scope.strict, scope.functions());
             let mut ctx: BTreeMap<&str, minijinja::Value> = resolved
                 .iter()
                 .map(|(k, v)| (k.as_str(), minijinja::Value::from(v.as_str())))
@@ -304,9 +578,9 @@ fn resolve_source(
                 "meta",
                 minijinja::Value::from_serialize(&TemplateMeta {
                     environment,
-                    tags,
-                    overrides,
-                    timestamp,
+                    tags: scope.tags,
+                    overrides: scope.overrides,
+                    timestamp: scope.timestamp,
                 }),
             );
             let value = env.render_str(tmpl, ctx).map_err(|e| ResolveError {
@@ -319,180 +593,158 @@ fn resolve_source(
             debug!(variable, "resolved from template");
             Ok(value)
         }
+        Source::FromBundle(_) => unreachable!("bundle keys are read by resolve_all"),
         Source::Skip => unreachable!("skip sources are filtered before resolution"),
     }
 }
 
-/// Resolve all variables for the given environment.
-///
-/// Returns either all resolved values (in deterministic order) or all errors
-/// encountered.
-///
-/// Variables with tags are only included when at least one of their tags is
-/// passed via the `tags` parameter. Untagged variables are always included.
-///
-/// Active overrides select alternative sources per variable. At most one
-/// active override may be defined on any given variable; conflicts are
-/// reported as errors.
-///
-/// # Concurrency
-///
-/// Literals and templates always run on the main thread in topological order.
-/// When `parallel` is true, `cmd:` / `sh:` sources are resolved by a bounded
-/// worker pool of at most [`MAX_EXTERNAL_JOBS`] threads — so a config with N
-/// external sources never spawns more than `min(N, MAX_EXTERNAL_JOBS)`
-/// concurrent subprocesses. Workers pull jobs from a shared queue. When
-/// `parallel` is false, external sources are resolved sequentially in
-/// topological order. Errors from the parallel phase are batched (all workers
-/// run to completion before any error is returned); the sequential phase
-/// fails fast on the first error.
-pub fn resolve_all(
-    config: &Config,
+/// Run the source of a `for_each` list. It runs before anything else is
+/// resolved, so it may not reference other variables.
+fn list_for_each(
+    source: &Source,
+    variable: &str,
+    scope: &Scope<'_>,
+) -> Result<String, ResolveError> {
+    let invalid = |reason: String| ResolveError {
+        variable: variable.to_owned(),
+        environment: scope.environment.to_owned(),
+        kind: ResolveErrorKind::InvalidSource { reason },
+    };
+    source.validate().map_err(invalid)?;
+    let references_variables = match source {
+        Source::FromBundle(_) | Source::Skip => true,
+        Source::Template(_) => !dependencies(source, |_| false)
+            .map_err(|e| invalid(e.to_string()))?
+            .is_empty(),
+        other => !other.needs().is_empty(),
+    };
+    if references_variables {
+        return Err(invalid(
+            "a `for_each` source cannot reference variables or bundles".to_owned(),
+        ));
+    }
+    if scope.dry_run && matches!(source, Source::Cmd(_) | Source::Sh(_)) {
+        debug!(variable, "dry run; not listing items");
+        return Ok(String::new());
+    }
+    resolve_source(source, variable, scope, &HashMap::new())
+}
+
+/// Look up the key a `from_bundle` source selects in its bundle's parsed
+/// output.
+fn read_bundle_key(
+    r: &BundleRef,
+    variable: &str,
     environment: &str,
-    tags: &[String],
-    overrides: &[String],
-    timestamp: &str,
-    parallel: bool,
-) -> Result<Vec<Resolved>, Vec<ResolveError>> {
-    let active_tags: HashSet<&str> = tags.iter().map(String::as_str).collect();
-    let mut sources: BTreeMap<String, Source> = BTreeMap::new();
-    let mut errors = Vec::new();
+    bundles: &HashMap<String, BTreeMap<String, String>>,
+) -> Result<String, ResolveError> {
+    let key = r.key.as_deref().unwrap_or(variable);
+    bundles[&r.bundle]
+        .get(key)
+        .cloned()
+        .ok_or_else(|| ResolveError {
+            variable: variable.to_owned(),
+            environment: environment.to_owned(),
+            kind: ResolveErrorKind::MissingBundleKey {
+                bundle: r.bundle.clone(),
+                key: key.to_owned(),
+            },
+        })
+}
 
-    // Track which override names are actually defined on at least one variable,
-    // so we can warn about completely unknown override names.
-    let mut defined_overrides: HashSet<&str> = HashSet::new();
+/// Whether a source runs a subprocess, and so belongs on the worker pool.
+fn is_external(source: &Source) -> bool {
+    matches!(source, Source::Cmd(_) | Source::Sh(_))
+}
 
-    for (name, variable) in &config.variables {
-        // Tag filtering: tagged variables require at least one matching
-        // --tag flag; untagged variables are always included.
-        if !variable.tags.is_empty()
-            && !variable
-                .tags
-                .iter()
-                .any(|t| active_tags.contains(t.as_str()))
-        {
-            debug!(variable = name.as_str(), "excluded by tag filter");
-            continue;
-        }
+/// State of one dependency-driven resolution over a [`Graph`].
+struct Run<'a> {
+    sources: &'a BTreeMap<String, Source>,
+    bundle_formats: &'a HashMap<String, (&'a str, BundleFormat)>,
+    graph: &'a Graph,
+    environment: &'a str,
+    /// Number of unresolved dependencies per node.
+    pending: HashMap<&'a str, usize>,
+    /// Nodes whose dependencies are all resolved, in name order.
+    ready: BTreeSet<&'a str>,
+    /// Values of resolved variables.
+    resolved: HashMap<String, String>,
+    /// Parsed output of resolved bundles, keyed by bundle name.
+    bundles: HashMap<String, BTreeMap<String, String>>,
+    errors: Vec<ResolveError>,
+    /// How long each node took, in completion order.
+    timings: Vec<SourceTiming>,
+    /// Keep starting nodes after an error instead of stopping.
+    keep_going: bool,
+}
 
-        // Collect which active overrides are defined on this variable.
-        let matching: Vec<&str> = overrides
+impl<'a> Run<'a> {
+    fn new(
+        sources: &'a BTreeMap<String, Source>,
+        bundle_formats: &'a HashMap<String, (&'a str, BundleFormat)>,
+        graph: &'a Graph,
+        environment: &'a str,
+    ) -> Self {
+        let pending: HashMap<&str, usize> = sources
+            .keys()
+            .map(|name| {
+                (
+                    name.as_str(),
+                    graph.dependencies.get(name).map_or(0, Vec::len),
+                )
+            })
+            .collect();
+        let ready = pending
             .iter()
-            .filter(|o| variable.overrides.contains_key(o.as_str()))
-            .map(String::as_str)
+            .filter(|(_, count)| **count == 0)
+            .map(|(name, _)| *name)
             .collect();
-
-        for &m in &matching {
-            defined_overrides.insert(m);
-        }
-
-        if matching.len() > 1 {
-            errors.push(ResolveError {
-                variable: name.clone(),
-                environment: environment.to_owned(),
-                kind: ResolveErrorKind::ConflictingOverrides {
-                    names: matching.iter().map(|s| (*s).to_owned()).collect(),
-                },
-            });
-            continue;
-        }
-
-        let source = if matching.len() == 1 {
-            let ovr = &variable.overrides[matching[0]];
-            ovr.envs
-                .get(environment)
-                .or(ovr.default.as_ref())
-                .or_else(|| variable.envs.get(environment))
-                .or(variable.default.as_ref())
-        } else {
-            variable.envs.get(environment).or(variable.default.as_ref())
-        };
-
-        match source {
-            Some(source) => {
-                if let Err(msg) = source.validate() {
-                    errors.push(ResolveError {
-                        variable: name.clone(),
-                        environment: environment.to_owned(),
-                        kind: ResolveErrorKind::InvalidSource {
-                            reason: msg.to_owned(),
-                        },
-                    });
-                } else if matches!(source, Source::Skip) {
-                    debug!(variable = name.as_str(), "skipped");
-                } else {
-                    sources.insert(name.clone(), source.clone());
-                }
-            }
-            None => {
-                errors.push(ResolveError {
-                    variable: name.clone(),
-                    environment: environment.to_owned(),
-                    kind: ResolveErrorKind::NoConfig,
-                });
-            }
-        }
-    }
-
-    // Warn about override names that don't appear on any variable.
-    for o in overrides {
-        if !defined_overrides.contains(o.as_str()) {
-            warn!(name = o.as_str(), "override not defined on any variable");
+        Self {
+            sources,
+            bundle_formats,
+            graph,
+            environment,
+            pending,
+            ready,
+            resolved: HashMap::new(),
+            bundles: HashMap::new(),
+            errors: Vec::new(),
+            timings: Vec::new(),
+            keep_going: false,
         }
     }
 
-    if !errors.is_empty() {
-        return Err(errors);
-    }
-
-    let order = topological_sort(&sources, environment)?;
-
-    // Partition into three groups: literals (instant), external commands
-    // (subprocess I/O — worth parallelizing), and templates (depend on
-    // previously resolved values — must stay sequential).
-    let mut literals: Vec<&String> = Vec::new();
-    let mut external: Vec<&String> = Vec::new();
-    let mut templates: Vec<&String> = Vec::new();
-
-    for name in &order {
-        match &sources[name.as_str()] {
-            Source::Literal(_) => literals.push(name),
-            Source::Cmd(_) | Source::Sh(_) => external.push(name),
-            Source::Template(_) => templates.push(name),
-            Source::Skip => unreachable!("skip sources are filtered before resolution"),
+    /// Resolve every node, each as soon as its dependencies are resolved.
+    ///
+    /// With `parallel`, `cmd:` / `sh:` nodes are handed to a worker pool of
+    /// at most `scope.jobs` threads while the main thread resolves literals,
+    /// templates, and bundle keys as they become ready. A node whose
+    /// `concurrency_group` already has a node in flight waits (still counted
+    /// as queued) until that one finishes.
+    /// After the first error no new nodes are started, but nodes already
+    /// running finish and their errors are collected too, unless
+    /// `scope.fail_fast` is set, in which case they are killed. Without
+    /// `parallel`, nodes run one at a time in name order among the ready ones
+    /// and the first error stops resolution.
+    fn schedule(&mut self, scope: &Scope<'_>, parallel: bool) {
+        let sources = self.sources;
+        let external_count = sources.values().filter(|s| is_external(s)).count();
+        if !parallel || external_count <= 1 {
+            while !self.stopped(scope)
+                && let Some(name) = self.ready.pop_first()
+            {
+                self.run_inline(name, scope);
+            }
+            return;
         }
-    }
 
-    // Resolve literals inline — no I/O, no threads needed.
-    let mut resolved_values: HashMap<String, String> = HashMap::new();
-
-    for name in &literals {
-        let source = &sources[name.as_str()];
-        let value = resolve_source(
-            source,
-            name,
-            environment,
-            tags,
-            overrides,
-            timestamp,
-            &resolved_values,
-        )
-        .map_err(|e| vec![e])?;
-        resolved_values.insert((*name).clone(), value);
-    }
-
-    // Resolve cmd/sh sources — in parallel via a bounded worker pool, or
-    // sequentially if parallel resolution is disabled.
-    if parallel && !external.is_empty() {
-        let pool_size = external.len().min(MAX_EXTERNAL_JOBS);
-        let resolved_ref = &resolved_values;
-        let sources_ref = &sources;
-
-        let (job_tx, job_rx) = mpsc::channel::<&String>();
-        let (res_tx, res_rx) = mpsc::channel::<(String, Result<String, ResolveError>)>();
+        let pool_size = external_count.min(scope.jobs);
+        let (job_tx, job_rx) = mpsc::channel::<(&str, HashMap<String, String>, Instant)>();
+        let (res_tx, res_rx) =
+            mpsc::channel::<(&str, Result<String, ResolveError>, Duration, Duration)>();
         let job_rx = Mutex::new(job_rx);
 
-        let results: Vec<(String, Result<String, ResolveError>)> = std::thread::scope(|s| {
+        std::thread::scope(|s| {
             for _ in 0..pool_size {
                 let job_rx = &job_rx;
                 let res_tx = res_tx.clone();
@@ -501,1244 +753,3718 @@ pub fn resolve_all(
                         // Short critical section: pull one job, release
                         // the lock, then do the (slow) subprocess work.
                         let next = job_rx.lock().expect("job queue mutex poisoned").recv();
-                        let Ok(name) = next else { return };
-                        let source = &sources_ref[name.as_str()];
-                        let value = resolve_source(
-                            source,
-                            name,
-                            environment,
-                            tags,
-                            overrides,
-                            timestamp,
-                            resolved_ref,
-                        );
-                        if res_tx.send((name.clone(), value)).is_err() {
+                        let Ok((name, inputs, queued_at)) = next else {
+                            return;
+                        };
+                        let started = Instant::now();
+                        let value = resolve_source(&sources[name], name, scope, &inputs);
+                        let message = (name, value, started - queued_at, started.elapsed());
+                        if res_tx.send(message).is_err() {
                             return;
                         }
                     }
                 });
             }
-            for name in external.iter().copied() {
-                job_tx.send(name).expect("workers still alive");
-            }
-            drop(job_tx);
             drop(res_tx);
-            res_rx.into_iter().collect()
-        });
 
-        let mut errors: Vec<ResolveError> = Vec::new();
-        for (name, result) in results {
-            match result {
-                Ok(value) => {
-                    resolved_values.insert(name, value);
+            let mut in_flight = 0;
+            // Concurrency groups with a source in flight, and ready sources
+            // waiting for their group (with when they started waiting).
+            let mut busy_groups: HashSet<&str> = HashSet::new();
+            let mut parked: HashMap<&str, Instant> = HashMap::new();
+            loop {
+                let mut still_parked = Vec::new();
+                while !self.stopped(scope)
+                    && let Some(name) = self.ready.pop_first()
+                {
+                    let source = &sources[name];
+                    if !is_external(source) {
+                        self.run_inline(name, scope);
+                        continue;
+                    }
+                    if let Some(group) = source.concurrency_group()
+                        && !busy_groups.insert(group)
+                    {
+                        parked.entry(name).or_insert_with(Instant::now);
+                        still_parked.push(name);
+                        continue;
+                    }
+                    let queued_at = parked.remove(name).unwrap_or_else(Instant::now);
+                    job_tx
+                        .send((name, self.inputs(name), queued_at))
+                        .expect("workers still alive");
+                    in_flight += 1;
+                }
+                self.ready.extend(still_parked);
+                if in_flight == 0 {
+                    break;
+                }
+                let (name, result, queued, run) = res_rx.recv().expect("workers still alive");
+                in_flight -= 1;
+                if let Some(group) = sources[name].concurrency_group() {
+                    busy_groups.remove(group);
+                }
+                self.record(name, queued, run, result.is_ok());
+                self.complete(name, result);
+                if scope.fail_fast && self.stopped(scope) {
+                    scope.cancel.store(true, Ordering::Relaxed);
                 }
-                Err(e) => errors.push(e),
             }
-        }
-        if !errors.is_empty() {
-            return Err(errors);
-        }
-    } else {
-        for name in &external {
-            let source = &sources[name.as_str()];
-            let value = resolve_source(
-                source,
-                name,
-                environment,
-                tags,
-                overrides,
-                timestamp,
-                &resolved_values,
-            )
-            .map_err(|e| vec![e])?;
-            resolved_values.insert((*name).clone(), value);
-        }
+            drop(job_tx);
+        });
     }
 
-    // Resolve templates sequentially in topological order.
-    for name in &templates {
-        let source = &sources[name.as_str()];
-        let value = resolve_source(
-            source,
-            name,
-            environment,
-            tags,
-            overrides,
-            timestamp,
-            &resolved_values,
-        )
-        .map_err(|e| vec![e])?;
-        resolved_values.insert((*name).clone(), value);
+    /// Whether to stop starting nodes: after the first error, unless keeping
+    /// going, and once the scope is cancelled.
+    fn stopped(&self, scope: &Scope<'_>) -> bool {
+        scope.cancelled() || (!self.keep_going && !self.errors.is_empty())
     }
 
-    let mut results: Vec<Resolved> = order
-        .iter()
-        .map(|name| {
-            let description = config.variables[name].description.clone();
-            Resolved {
-                name: name.clone(),
-                value: resolved_values.remove(name.as_str()).unwrap(),
-                description,
+    /// Report every node that never finished because envoke was interrupted
+    /// before it started.
+    fn report_interrupted(&mut self) {
+        let finished: HashSet<&str> = self.timings.iter().map(|t| t.name.as_str()).collect();
+        let mut unfinished: Vec<&str> = self
+            .pending
+            .keys()
+            .copied()
+            .filter(|name| !finished.contains(name))
+            .collect();
+        unfinished.sort_unstable();
+        let reported: Vec<ResolveError> = unfinished
+            .into_iter()
+            .map(|name| ResolveError {
+                variable: name.to_owned(),
+                environment: self.environment.to_owned(),
+                kind: ResolveErrorKind::Interrupted { command: None },
+            })
+            .collect();
+        self.errors.extend(reported);
+    }
+
+    /// Report every node that never became ready because something it
+    /// depends on failed.
+    fn report_blocked(&mut self) {
+        let mut failed: HashSet<&str> = self.errors.iter().map(|e| e.variable.as_str()).collect();
+        let blocked: Vec<&str> = self
+            .sources
+            .keys()
+            .map(String::as_str)
+            .filter(|name| self.pending[name] > 0)
+            .collect();
+        failed.extend(blocked.iter().copied());
+        let mut reported = Vec::new();
+        for name in blocked {
+            let dependency = self.graph.dependencies[name]
+                .iter()
+                .filter(|dep| failed.contains(dep.as_str()))
+                .min()
+                .expect("a blocked node has an unresolved dependency");
+            reported.push(ResolveError {
+                variable: name.to_owned(),
+                environment: self.environment.to_owned(),
+                kind: ResolveErrorKind::DependencyFailed {
+                    dependency: dependency.clone(),
+                },
+            });
+        }
+        self.errors.extend(reported);
+    }
+
+    /// Resolve one node on the current thread and record its outcome.
+    fn run_inline(&mut self, name: &'a str, scope: &Scope<'_>) {
+        let started = Instant::now();
+        let result = self.resolve_node(name, scope);
+        self.record(name, Duration::ZERO, started.elapsed(), result.is_ok());
+        self.complete(name, result);
+    }
+
+    fn record(&mut self, name: &str, queued: Duration, run: Duration, ok: bool) {
+        self.timings.push(SourceTiming {
+            name: name.to_owned(),
+            source: self.sources[name].kind(),
+            queued,
+            run,
+            ok,
+        });
+    }
+
+    /// Resolve one node on the current thread.
+    fn resolve_node(&self, name: &str, scope: &Scope<'_>) -> Result<String, ResolveError> {
+        match &self.sources[name] {
+            Source::FromBundle(r) => read_bundle_key(r, name, self.environment, &self.bundles),
+            source => resolve_source(source, name, scope, &self.resolved),
+        }
+    }
+
+    /// Values of a node's dependencies, for handing it to a worker.
+    fn inputs(&self, name: &str) -> HashMap<String, String> {
+        self.graph
+            .dependencies
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter_map(|dep| Some((dep.clone(), self.resolved.get(dep)?.clone())))
+            .collect()
+    }
+
+    /// Record a node's outcome and mark the dependents it unblocks as ready.
+    ///
+    /// Bundle output is parsed once here and is only reachable through
+    /// `from_bundle`; every other value becomes visible to dependents.
+    fn complete(&mut self, name: &'a str, result: Result<String, ResolveError>) {
+        let outcome = result.and_then(|value| match self.bundle_formats.get(name) {
+            None => {
+                self.resolved.insert(name.to_owned(), value);
+                Ok(())
             }
-        })
-        .collect();
-    results.sort_by(|a, b| a.name.cmp(&b.name));
+            Some(&(bundle, format)) => match bundle::parse(format, &value) {
+                Ok(parsed) => {
+                    self.bundles.insert(bundle.to_owned(), parsed);
+                    Ok(())
+                }
+                Err(reason) => Err(ResolveError {
+                    variable: name.to_owned(),
+                    environment: self.environment.to_owned(),
+                    kind: ResolveErrorKind::BundleParse {
+                        bundle: bundle.to_owned(),
+                        reason,
+                    },
+                }),
+            },
+        });
+        if let Err(e) = outcome {
+            self.errors.push(e);
+            return;
+        }
+        let graph = self.graph;
+        for dependent in graph.dependents.get(name).into_iter().flatten() {
+            // Dependents outside a restricted run have no entry.
+            let Some(count) = self.pending.get_mut(dependent.as_str()) else {
+                continue;
+            };
+            *count -= 1;
+            if *count == 0 {
+                self.ready.insert(dependent);
+            }
+        }
+    }
+}
 
-    Ok(results)
+/// Outcome of [`Resolver::resolve_partial`].
+#[derive(Debug, Default)]
+pub struct Partial {
+    /// Variables that resolved, in name order.
+    pub resolved: Vec<Resolved>,
+    /// Everything that failed, sorted by variable.
+    pub errors: Vec<ResolveError>,
+    /// How long each source took, in completion order.
+    pub timings: Vec<SourceTiming>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::Override;
+/// Builder that resolves a [`Config`] for one environment.
+///
+/// ```no_run
+/// # let config = envoke_cli::Config::default();
+/// let resolved = envoke_cli::Resolver::new(&config, "prod")
+///     .tags(["vault"])
+///     .overrides(["read-replica"])
+///     .resolve()?;
+/// # Ok::<(), envoke_cli::ResolveErrors>(())
+/// ```
+pub struct Resolver<'a> {
+    config: &'a Config,
+    environment: String,
+    tags: Vec<String>,
+    overrides: Vec<String>,
+    timestamp: Option<String>,
+    parallel: bool,
+    jobs: Option<NonZeroUsize>,
+    fail_fast: bool,
+    strict: bool,
+    config_dir: PathBuf,
+    cache: Option<Cache>,
+}
 
-    const TS: &str = "2025-01-01T00:00:00+00:00";
+impl<'a> Resolver<'a> {
+    /// Start resolving `config` for `environment`: no tags or overrides
+    /// active, parallel resolution, and no cache.
+    #[must_use]
+    pub fn new(config: &'a Config, environment: impl Into<String>) -> Self {
+        Self {
+            config,
+            environment: environment.into(),
+            tags: Vec::new(),
+            overrides: Vec::new(),
+            timestamp: None,
+            parallel: true,
+            jobs: None,
+            fail_fast: false,
+            strict: false,
+            config_dir: PathBuf::from("."),
+            cache: None,
+        }
+    }
 
-    fn resolve(
-        config: &Config,
-        environment: &str,
-        tags: &[String],
-        overrides: &[String],
-    ) -> Result<Vec<Resolved>, Vec<ResolveError>> {
-        resolve_all(config, environment, tags, overrides, TS, true)
+    /// Activate tags. Tagged variables are only included when at least one of
+    /// their tags is active; untagged variables are always included.
+    #[must_use]
+    pub fn tags<I>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.tags.extend(tags.into_iter().map(Into::into));
+        self
     }
 
-    fn literal(value: &str) -> Source {
-        Source::Literal(value.to_owned())
+    /// Activate every tag used in the config.
+    #[must_use]
+    pub fn all_tags(mut self) -> Self {
+        self.tags = self.config.tag_names();
+        self
     }
 
-    fn template(value: &str) -> Source {
-        Source::Template(value.to_owned())
+    /// Activate named overrides. At most one active override may be defined
+    /// on any given variable.
+    #[must_use]
+    pub fn overrides<I>(mut self, overrides: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.overrides.extend(overrides.into_iter().map(Into::into));
+        self
     }
 
-    fn cmd(args: Vec<&str>) -> Source {
-        Source::Cmd(args.into_iter().map(ToOwned::to_owned).collect())
+    /// Timestamp exposed to templates as `meta.timestamp`. Defaults to the
+    /// current local time in RFC 3339 format.
+    #[must_use]
+    pub fn timestamp(mut self, timestamp: impl Into<String>) -> Self {
+        self.timestamp = Some(timestamp.into());
+        self
     }
 
-    fn sh(script: &str) -> Source {
-        Source::Sh(script.to_owned())
+    /// Whether `cmd:` / `sh:` sources may run concurrently. Defaults to
+    /// `true`.
+    #[must_use]
+    pub fn parallel(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
     }
 
-    fn skip() -> Source {
-        Source::Skip
+    /// Maximum number of `cmd:` / `sh:` sources run at once, overriding
+    /// `settings.jobs`. Defaults to [`DEFAULT_JOBS`].
+    #[must_use]
+    pub fn jobs(mut self, jobs: NonZeroUsize) -> Self {
+        self.jobs = Some(jobs);
+        self
     }
 
-    fn var(envs: BTreeMap<String, Source>) -> crate::config::Variable {
-        crate::config::Variable {
-            description: None,
-            tags: vec![],
-            default: None,
-            envs,
-            overrides: BTreeMap::new(),
-        }
+    /// Kill the `cmd:` / `sh:` sources still running as soon as one source
+    /// fails, instead of letting them finish. Their errors are reported as
+    /// [`ResolveErrorKind::Cancelled`]. Has no effect on
+    /// [`resolve_partial`](Self::resolve_partial). Defaults to `false`.
+    #[must_use]
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
     }
 
-    fn var_with_default(
-        default: Source,
-        envs: BTreeMap<String, Source>,
-    ) -> crate::config::Variable {
-        crate::config::Variable {
-            description: None,
-            tags: vec![],
-            default: Some(default),
-            envs,
-            overrides: BTreeMap::new(),
-        }
+    /// Make `template:` sources fail when they read an undefined value,
+    /// instead of rendering it as an empty string. Also enabled by
+    /// `settings.strict_templates`. Defaults to `false`.
+    #[must_use]
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
     }
 
-    fn var_tagged(tags: Vec<&str>, envs: BTreeMap<String, Source>) -> crate::config::Variable {
-        crate::config::Variable {
-            description: None,
-            tags: tags.into_iter().map(ToOwned::to_owned).collect(),
-            default: None,
-            envs,
-            overrides: BTreeMap::new(),
-        }
+    /// Directory that `file()` paths in templates and `cmd:` `cwd` paths are
+    /// relative to, normally the one holding the config file. Defaults to the
+    /// current directory.
+    #[must_use]
+    pub fn config_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config_dir = dir.into();
+        self
     }
 
-    #[test]
-    fn test_template_references() {
-        let refs =
-            template_references("postgresql://{{ USER }}:{{ PASS }}@localhost/{{ DB }}").unwrap();
-        assert_eq!(
-            refs,
-            HashSet::from(["USER".to_owned(), "PASS".to_owned(), "DB".to_owned()])
-        );
+    /// Use an on-disk cache for sources that declare `cache: { ttl }`.
+    #[must_use]
+    pub fn cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(cache);
+        self
     }
 
-    #[test]
-    fn test_template_references_empty() {
-        let refs = template_references("no references here").unwrap();
-        assert!(refs.is_empty());
+    /// Resolve every selected variable. See [`resolve_all`] for the rules.
+    ///
+    /// # Errors
+    ///
+    /// Returns every [`ResolveError`] encountered, wrapped in
+    /// [`ResolveErrors`].
+    pub fn resolve(&self) -> Result<Vec<Resolved>, ResolveErrors> {
+        self.resolve_timed().0
     }
 
-    #[test]
-    fn test_resolve_literal() {
-        let config = Config {
-            variables: BTreeMap::from([("FOO".to_owned(), {
-                let mut v = var(BTreeMap::from([("local".to_owned(), literal("bar"))]));
-                v.description = Some("A foo".to_owned());
-                v
-            })]),
-        };
-        let resolved = resolve(&config, "local", &[], &[]).unwrap();
-        assert_eq!(resolved.len(), 1);
-        assert_eq!(resolved[0].name, "FOO");
-        assert_eq!(resolved[0].value, "bar");
+    /// Like [`resolve`](Self::resolve), also returning how long each source
+    /// took, including time spent waiting for a worker. Timings are returned
+    /// even when resolution fails, in completion order.
+    pub fn resolve_timed(&self) -> (Result<Vec<Resolved>, ResolveErrors>, Vec<SourceTiming>) {
+        let Partial {
+            resolved,
+            errors,
+            timings,
+        } = self.run(false);
+        if errors.is_empty() {
+            (Ok(resolved), timings)
+        } else {
+            (Err(ResolveErrors { errors }), timings)
+        }
     }
 
-    #[test]
-    fn test_resolve_template() {
-        let config = Config {
-            variables: BTreeMap::from([
-                (
-                    "USER".to_owned(),
-                    var(BTreeMap::from([("local".to_owned(), literal("alice"))])),
-                ),
-                (
-                    "GREETING".to_owned(),
-                    var(BTreeMap::from([(
-                        "local".to_owned(),
-                        template("hello {{ USER }}!"),
-                    )])),
-                ),
-            ]),
-        };
-        let resolved = resolve(&config, "local", &[], &[]).unwrap();
-        let greeting = resolved.iter().find(|r| r.name == "GREETING").unwrap();
-        assert_eq!(greeting.value, "hello alice!");
+    /// Resolve as much as possible: a failing variable (and whatever depends
+    /// on it) is reported in [`Partial::errors`] while every other variable
+    /// still resolves. See [`resolve_all`] for which errors still stop
+    /// everything.
+    #[must_use]
+    pub fn resolve_partial(&self) -> Partial {
+        self.run(true)
     }
 
-    #[test]
-    fn test_resolve_template_urlencode() {
-        let config = Config {
-            variables: BTreeMap::from([
-                (
-                    "USER".to_owned(),
-                    var(BTreeMap::from([("local".to_owned(), literal("alice"))])),
-                ),
-                (
-                    "PASS".to_owned(),
-                    var(BTreeMap::from([("local".to_owned(), literal("p@ss:word"))])),
-                ),
-                (
-                    "CONN".to_owned(),
-                    var(BTreeMap::from([(
-                        "local".to_owned(),
-                        template("{{ USER | urlencode }}:{{ PASS | urlencode }}"),
-                    )])),
-                ),
-            ]),
-        };
-        let resolved = resolve(&config, "local", &[], &[]).unwrap();
-        let conn = resolved.iter().find(|r| r.name == "CONN").unwrap();
-        assert_eq!(conn.value, "alice:p%40ss%3Aword");
+    fn run(&self, keep_going: bool) -> Partial {
+        let timestamp = self.timestamp_or_now();
+        let mut timings = Vec::new();
+        let (resolved, errors) = resolve_all(
+            self.config,
+            &self.scope(&timestamp),
+            self.parallel,
+            keep_going,
+            &mut timings,
+        );
+        Partial {
+            resolved,
+            errors,
+            timings,
+        }
     }
 
-    #[test]
-    fn test_missing_environment() {
-        let config = Config {
-            variables: BTreeMap::from([(
-                "FOO".to_owned(),
-                var(BTreeMap::from([("prod".to_owned(), literal("x"))])),
-            )]),
-        };
-        let err = resolve(&config, "local", &[], &[]).unwrap_err();
-        assert_eq!(err.len(), 1);
-        assert!(matches!(err[0].kind, ResolveErrorKind::NoConfig));
+    fn timestamp_or_now(&self) -> String {
+        self.timestamp
+            .clone()
+            .unwrap_or_else(|| chrono::Local::now().to_rfc3339())
     }
 
-    #[test]
-    fn test_circular_dependency() {
-        let config = Config {
-            variables: BTreeMap::from([
-                (
-                    "A".to_owned(),
-                    var(BTreeMap::from([("local".to_owned(), template("{{ B }}"))])),
-                ),
-                (
-                    "B".to_owned(),
-                    var(BTreeMap::from([("local".to_owned(), template("{{ A }}"))])),
-                ),
-            ]),
-        };
-        let err = resolve(&config, "local", &[], &[]).unwrap_err();
-        assert!(err
-            .iter()
-            .any(|e| matches!(&e.kind, ResolveErrorKind::CircularDependency { chain } if chain.len() >= 3)));
+    fn scope<'s>(&'s self, timestamp: &'s str) -> Scope<'s> {
+        Scope {
+            environment: &self.environment,
+            tags: &self.tags,
+            overrides: &self.overrides,
+            timestamp,
+            settings: &self.config.settings,
+            cache: self.cache.as_ref(),
+            jobs: self
+                .jobs
+                .or(self.config.settings.jobs)
+                .map_or(DEFAULT_JOBS, NonZeroUsize::get),
+            fail_fast: self.fail_fast,
+            strict: self.strict || self.config.settings.strict_templates,
+            config_dir: &self.config_dir,
+            dry_run: false,
+            cancel: AtomicBool::new(false),
+        }
     }
 
-    #[test]
-    fn test_unknown_reference() {
-        let config = Config {
-            variables: BTreeMap::from([(
-                "A".to_owned(),
-                var(BTreeMap::from([(
-                    "local".to_owned(),
-                    template("{{ NONEXISTENT }}"),
-                )])),
-            )]),
+    /// Validate the environment's selection without running any source:
+    /// report variables with no source, invalid sources, conflicting
+    /// overrides, and unknown references or cycles among the selected
+    /// variables. Variables depending on one of these are not reported
+    /// again. A `for_each` list that comes from a `cmd:` / `sh:` source is
+    /// treated as empty.
+    #[must_use]
+    pub fn check(&self) -> Vec<ResolveError> {
+        let timestamp = self.timestamp_or_now();
+        let mut scope = self.scope(&timestamp);
+        scope.dry_run = true;
+        let plan = match plan(self.config, &scope, true) {
+            Ok(plan) => plan,
+            Err(errors) => return errors,
         };
-        let err = resolve(&config, "local", &[], &[]).unwrap_err();
-        assert!(err.iter().any(
-            |e| matches!(&e.kind, ResolveErrorKind::UnknownReference { name } if name == "NONEXISTENT")
-        ));
+        let mut errors: Vec<ResolveError> = plan
+            .errors
+            .into_iter()
+            .filter(|e| !matches!(e.kind, ResolveErrorKind::DependencyFailed { .. }))
+            .collect();
+        if let Err(graph_errors) = build_graph(&plan.sources, &self.environment) {
+            errors.extend(graph_errors);
+        }
+        errors
     }
 
-    #[test]
-    fn test_resolve_cmd_echo() {
-        let config = Config {
-            variables: BTreeMap::from([(
-                "VAL".to_owned(),
-                var(BTreeMap::from([(
-                    "local".to_owned(),
-                    cmd(vec!["echo", "hello"]),
-                )])),
-            )]),
-        };
-        let resolved = resolve(&config, "local", &[], &[]).unwrap();
-        assert_eq!(resolved[0].value, "hello");
+    /// Plan the resolution without running any source: every node in the
+    /// order it would be resolved, with the source selected for it. A
+    /// `for_each` list that comes from a `cmd:` / `sh:` source is not run;
+    /// its definition is listed in [`DryRun::unlisted`] instead.
+    ///
+    /// # Errors
+    ///
+    /// Fails on anything that would fail the plan before a source runs: a
+    /// variable with no source, conflicting overrides, an invalid source, an
+    /// unknown reference, or a cycle.
+    pub fn dry_run(&self) -> Result<DryRun, ResolveErrors> {
+        let timestamp = self.timestamp_or_now();
+        let mut scope = self.scope(&timestamp);
+        scope.dry_run = true;
+        let fail = |errors| ResolveErrors { errors };
+        let plan = plan(self.config, &scope, false).map_err(fail)?;
+        let mut graph = build_graph(&plan.sources, &self.environment).map_err(fail)?;
+
+        let steps = graph
+            .order
+            .iter()
+            .map(|name| {
+                let variable = plan.variables.get(name);
+                let mut dependencies = graph.dependencies.remove(name).unwrap_or_default();
+                dependencies.sort();
+                Step {
+                    name: name.clone(),
+                    origin: plan.origins.get(name).cloned(),
+                    source: plan.sources[name].clone(),
+                    dependencies,
+                    export: variable.is_some_and(|v| v.export),
+                    secret: variable.is_some_and(|v| v.secret),
+                }
+            })
+            .collect();
+        let unlisted = self
+            .config
+            .variables
+            .iter()
+            .filter(|(_, variable)| tag_selected(variable, scope.tags))
+            .filter_map(|(name, variable)| match &variable.for_each {
+                Some(ForEach::Source(source @ (Source::Cmd(_) | Source::Sh(_)))) => {
+                    Some((name.clone(), source.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        Ok(DryRun {
+            environment: self.environment.clone(),
+            steps,
+            unlisted,
+        })
     }
 
-    #[test]
-    fn test_default_fallback() {
-        let config = Config {
-            variables: BTreeMap::from([(
-                "FOO".to_owned(),
-                var_with_default(literal("fallback"), BTreeMap::new()),
-            )]),
+    /// Explain how `name` is selected for the environment: the tag filter
+    /// decision, the overrides defined on it, the source that wins and why,
+    /// and the variables it depends on. With `show_value`, also resolve the
+    /// variable and everything it depends on (nothing else).
+    ///
+    /// # Errors
+    ///
+    /// Fails if `name` is not defined (after `for_each` expansion), if a
+    /// `for_each` source fails, or, with `show_value`, if resolution fails.
+    pub fn explain(&self, name: &str, show_value: bool) -> Result<Explanation, ResolveErrors> {
+        let timestamp = self.timestamp_or_now();
+        let scope = self.scope(&timestamp);
+        let fail = |errors| ResolveErrors { errors };
+        let error = |kind| ResolveErrors {
+            errors: vec![ResolveError {
+                variable: name.to_owned(),
+                environment: self.environment.clone(),
+                kind,
+            }],
         };
-        let resolved = resolve(&config, "any-env", &[], &[]).unwrap();
-        assert_eq!(resolved[0].value, "fallback");
-    }
 
-    #[test]
-    fn test_env_overrides_default() {
-        let config = Config {
-            variables: BTreeMap::from([(
-                "FOO".to_owned(),
-                var_with_default(
-                    literal("fallback"),
-                    BTreeMap::from([("local".to_owned(), literal("override"))]),
-                ),
-            )]),
+        let variables = expand_variables(self.config, &scope).map_err(fail)?;
+        let Some(variable) = variables.get(name) else {
+            return Err(error(ResolveErrorKind::UnknownVariable));
         };
-        let resolved = resolve(&config, "local", &[], &[]).unwrap();
-        assert_eq!(resolved[0].value, "override");
-    }
 
-    #[test]
-    fn test_circular_dependency_chain_message() {
+        let tags = TagDecision {
+            tags: variable.tags.clone(),
+            matched: variable
+                .tags
+                .iter()
+                .filter(|t| self.tags.contains(t))
+                .cloned()
+                .collect(),
+            included: tag_selected(variable, &self.tags),
+        };
+        let overrides = variable
+            .overrides
+            .iter()
+            .map(|(o, ovr)| OverrideCheck {
+                name: o.clone(),
+                active: self.overrides.contains(o),
+                has_env: ovr.envs.contains_key(&self.environment),
+                has_default: ovr.default.is_some(),
+            })
+            .collect();
+        let selection = match select_source(variable, &self.environment, &self.overrides) {
+            Ok(Some((source, origin))) => Selection::Selected {
+                origin,
+                source: Box::new(source.clone()),
+            },
+            Ok(None) => Selection::NoConfig,
+            Err(names) => Selection::Conflict { names },
+        };
+
+        let mut dependencies = Vec::new();
+        let mut resolvable = tags.included;
+        if let Selection::Selected { source, .. } = &selection {
+            dependencies =
+                self::dependencies(source, |dep| variables.contains_key(dep)).map_err(|e| {
+                    error(ResolveErrorKind::TemplateRender {
+                        reason: e.to_string(),
+                    })
+                })?;
+            resolvable &= !matches!(**source, Source::Skip);
+        } else {
+            resolvable = false;
+        }
+
+        let value = if show_value && resolvable {
+            // Keep going past variables that cannot be planned, so a broken
+            // one elsewhere does not hide this value.
+            let mut plan = plan(self.config, &scope, true).map_err(fail)?;
+            let (mut values, mut errors) = execute(
+                &plan,
+                &scope,
+                self.parallel,
+                false,
+                Some(name),
+                &mut Vec::new(),
+            );
+            // If the target itself was left out, report why: its own error
+            // and those of the failed dependencies behind it.
+            let mut next = Some(name.to_owned());
+            while let Some(variable) = next.take()
+                && let Some(i) = plan.errors.iter().position(|e| e.variable == variable)
+            {
+                let error = plan.errors.swap_remove(i);
+                if let ResolveErrorKind::DependencyFailed { dependency } = &error.kind {
+                    next = Some(dependency.clone());
+                }
+                errors.push(error);
+            }
+            if !errors.is_empty() {
+                return Err(fail(errors));
+            }
+            values.remove(name)
+        } else {
+            None
+        };
+
+        Ok(Explanation {
+            name: name.to_owned(),
+            environment: self.environment.clone(),
+            description: variable.description.clone(),
+            export: variable.export,
+            tags,
+            overrides,
+            selection,
+            dependencies,
+            value,
+        })
+    }
+}
+
+/// Resolve all variables for the scope's environment.
+///
+/// Returns either all resolved values (in deterministic order) or all errors
+/// encountered.
+///
+/// Variables with tags are only included when at least one of their tags is
+/// active in the scope. Untagged variables are always included.
+///
+/// Active overrides select alternative sources per variable. At most one
+/// active override may be defined on any given variable; conflicts are
+/// reported as errors.
+///
+/// Variables with `export: false` are resolved and visible to templates, but
+/// are omitted from the returned list.
+///
+/// # Concurrency
+///
+/// Each variable is resolved as soon as everything it depends on (see
+/// [`build_graph`]) is resolved, so a slow command delays only its own
+/// dependents, and `cmd:` / `sh:` sources may `needs` templates as well as
+/// the other way round. Literals and templates always run on the main
+/// thread. When `parallel` is true, `cmd:` / `sh:` sources are resolved by a
+/// bounded worker pool of at most `scope.jobs` threads (see
+/// [`DEFAULT_JOBS`]), so no more than that many subprocesses ever run at
+/// once, and sources sharing a `concurrency_group` run one at a time. When
+/// `parallel` is false, everything is resolved sequentially. Errors from parallel
+/// resolution are batched (no new sources start after the first error, but
+/// running ones finish and report theirs); sequential resolution fails fast
+/// on the first error. The result is the same whatever order sources finish
+/// in: values are returned sorted by name, and errors sorted by variable.
+///
+/// # Cancellation
+///
+/// With `scope.fail_fast`, the first error also kills every `cmd:` / `sh:`
+/// source still running (reported as [`ResolveErrorKind::Cancelled`]). Once
+/// envoke is [interrupted](crate::interrupt), running sources are killed and
+/// every variable that did not finish is reported as
+/// [`ResolveErrorKind::Interrupted`], with or without `keep_going`.
+///
+/// # Caching
+///
+/// With a `cache`, `cmd:` / `sh:` sources that declare `cache: { ttl }` reuse
+/// a stored value while it is fresh instead of running again.
+///
+/// # Bundles
+///
+/// Each bundle referenced by a selected `from_bundle` source is scheduled as
+/// its own node (`bundle:<name>`), so its source runs once — on the worker
+/// pool like any other `cmd:` / `sh:` source — and every variable reading it
+/// waits for that single run. Unreferenced bundles are never run.
+///
+/// # Keep going
+///
+/// With `keep_going`, a variable that fails (or depends on one that does,
+/// see [`ResolveErrorKind::DependencyFailed`]) does not stop the others:
+/// every other variable is still resolved and returned alongside the errors.
+/// Config errors that concern the graph as a whole (cycles, unknown
+/// references, `for_each` expansion) still resolve nothing. Without it, any
+/// error means no values are returned.
+///
+/// # Timings
+///
+/// How long each node took, including time spent waiting for a worker, is
+/// appended to `timings` whether or not resolution succeeds.
+fn resolve_all(
+    config: &Config,
+    scope: &Scope<'_>,
+    parallel: bool,
+    keep_going: bool,
+    timings: &mut Vec<SourceTiming>,
+) -> (Vec<Resolved>, Vec<ResolveError>) {
+    let mut plan = match plan(config, scope, keep_going) {
+        Ok(plan) => plan,
+        Err(errors) => return (Vec::new(), errors),
+    };
+    let (mut resolved_values, run_errors) =
+        execute(&plan, scope, parallel, keep_going, None, timings);
+    let mut errors = std::mem::take(&mut plan.errors);
+    errors.extend(run_errors);
+    if !keep_going && !errors.is_empty() {
+        return (Vec::new(), errors);
+    }
+    errors.sort_by(|a, b| a.variable.cmp(&b.variable));
+
+    // Non-exported helpers have served their purpose as template inputs.
+    let results: Vec<Resolved> = plan
+        .sources
+        .iter()
+        .filter(|(name, _)| {
+            !plan.bundle_formats.contains_key(name.as_str()) && plan.variables[name.as_str()].export
+        })
+        .filter_map(|(name, source)| {
+            Some(Resolved {
+                name: name.clone(),
+                value: resolved_values.remove(name.as_str())?,
+                description: plan.variables[name].description.clone(),
+                source: source.kind(),
+                origin: plan.origins[name.as_str()].clone(),
+                secret: plan.variables[name].secret,
+            })
+        })
+        .collect();
+
+    (results, errors)
+}
+
+/// Where a variable's source was found in the selection chain.
+///
+/// Serializes as `{ kind, override, path }`: `kind` is `env` for an entry
+/// specific to the environment and `default` otherwise, `override` names the
+/// override that provided the source (or is null), and `path` is the
+/// [`Display`](std::fmt::Display) form, e.g. `overrides.local.envs.dev`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Origin {
+    /// `overrides.<name>.envs.<environment>`
+    OverrideEnv { name: String, environment: String },
+    /// `overrides.<name>.default`
+    OverrideDefault { name: String },
+    /// `envs.<environment>`
+    Env { environment: String },
+    /// `default`
+    #[default]
+    Default,
+}
+
+impl Origin {
+    /// Name of the override that provided the source, if any.
+    #[must_use]
+    pub fn override_name(&self) -> Option<&str> {
+        match self {
+            Self::OverrideEnv { name, .. } | Self::OverrideDefault { name } => Some(name),
+            Self::Env { .. } | Self::Default => None,
+        }
+    }
+
+    /// Whether the source is an entry for the specific environment rather
+    /// than a default.
+    #[must_use]
+    pub fn is_env_specific(&self) -> bool {
+        matches!(self, Self::OverrideEnv { .. } | Self::Env { .. })
+    }
+}
+
+impl serde::Serialize for Origin {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut s = serializer.serialize_struct("Origin", 3)?;
+        s.serialize_field(
+            "kind",
+            if self.is_env_specific() {
+                "env"
+            } else {
+                "default"
+            },
+        )?;
+        s.serialize_field("override", &self.override_name())?;
+        s.serialize_field("path", &self.to_string())?;
+        s.end()
+    }
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OverrideEnv { name, environment } => {
+                write!(f, "overrides.{name}.envs.{environment}")
+            }
+            Self::OverrideDefault { name } => write!(f, "overrides.{name}.default"),
+            Self::Env { environment } => write!(f, "envs.{environment}"),
+            Self::Default => f.write_str("default"),
+        }
+    }
+}
+
+/// Whether a variable passes the tag filter: tagged variables require at
+/// least one active tag; untagged variables are always included.
+fn tag_selected(variable: &Variable, tags: &[String]) -> bool {
+    variable.tags.is_empty() || variable.tags.iter().any(|t| tags.contains(t))
+}
+
+/// Pick a variable's source for `environment`.
+///
+/// With an active override defined on the variable, the chain is the
+/// override's `envs` entry, the override's `default`, the base `envs` entry,
+/// then the base `default`; otherwise only the last two. Returns the names of
+/// the conflicting overrides if more than one active override is defined on
+/// the variable, and `None` if nothing in the chain matches.
+fn select_source<'v>(
+    variable: &'v Variable,
+    environment: &str,
+    overrides: &[String],
+) -> Result<Option<(&'v Source, Origin)>, Vec<String>> {
+    let matching: Vec<&String> = overrides
+        .iter()
+        .filter(|o| variable.overrides.contains_key(o.as_str()))
+        .collect();
+    if matching.len() > 1 {
+        return Err(matching.into_iter().cloned().collect());
+    }
+    if let Some(&name) = matching.first() {
+        let ovr = &variable.overrides[name];
+        if let Some(source) = ovr.envs.get(environment) {
+            let origin = Origin::OverrideEnv {
+                name: name.clone(),
+                environment: environment.to_owned(),
+            };
+            return Ok(Some((source, origin)));
+        }
+        if let Some(source) = &ovr.default {
+            let origin = Origin::OverrideDefault { name: name.clone() };
+            return Ok(Some((source, origin)));
+        }
+    }
+    if let Some(source) = variable.envs.get(environment) {
+        let origin = Origin::Env {
+            environment: environment.to_owned(),
+        };
+        return Ok(Some((source, origin)));
+    }
+    Ok(variable.default.as_ref().map(|s| (s, Origin::Default)))
+}
+
+/// The config's variables with every selected `for_each` definition
+/// expanded.
+fn expand_variables(
+    config: &Config,
+    scope: &Scope<'_>,
+) -> Result<BTreeMap<String, Variable>, Vec<ResolveError>> {
+    expand::expand(
+        &config.variables,
+        scope.environment,
+        scope.functions(),
+        |variable| tag_selected(variable, scope.tags),
+        |name, source| list_for_each(source, name, scope),
+    )
+}
+
+/// Everything selected for one environment, ready to schedule.
+struct Plan<'c> {
+    /// Variable definitions after `for_each` expansion.
+    variables: BTreeMap<String, Variable>,
+    /// Source of every node to resolve: selected variables and the bundles
+    /// they read.
+    sources: BTreeMap<String, Source>,
+    /// Bundle nodes, with the bundle's name and output format.
+    bundle_formats: HashMap<String, (&'c str, BundleFormat)>,
+    /// Where each selected variable's source was found.
+    origins: HashMap<String, Origin>,
+    /// Variables that could not be planned, when keeping going. Their
+    /// dependents have been dropped from `sources` and are reported here too.
+    errors: Vec<ResolveError>,
+}
+
+/// Select a source for every variable that passes the tag filter, plus the
+/// bundles they read.
+///
+/// Any error fails the whole plan unless `keep_going`, in which case failed
+/// variables and everything depending on them are left out and reported in
+/// [`Plan::errors`].
+fn plan<'c>(
+    config: &'c Config,
+    scope: &Scope<'_>,
+    keep_going: bool,
+) -> Result<Plan<'c>, Vec<ResolveError>> {
+    let environment = scope.environment;
+    let variables = expand_variables(config, scope)?;
+
+    let mut sources: BTreeMap<String, Source> = BTreeMap::new();
+    let mut origins = HashMap::new();
+    let mut errors = Vec::new();
+
+    // Track which override names are actually defined on at least one variable,
+    // so we can warn about completely unknown override names.
+    let mut defined_overrides: HashSet<&str> = HashSet::new();
+
+    for (name, variable) in &variables {
+        if !tag_selected(variable, scope.tags) {
+            debug!(variable = name.as_str(), "excluded by tag filter");
+            continue;
+        }
+
+        defined_overrides.extend(
+            scope
+                .overrides
+                .iter()
+                .filter(|o| variable.overrides.contains_key(o.as_str()))
+                .map(String::as_str),
+        );
+
+        match select_source(variable, environment, scope.overrides) {
+            Err(names) => {
+                errors.push(ResolveError {
+                    variable: name.clone(),
+                    environment: environment.to_owned(),
+                    kind: ResolveErrorKind::ConflictingOverrides { names },
+                });
+            }
+            Ok(Some((source, origin))) => {
+                if let Err(msg) = source.validate() {
+                    errors.push(ResolveError {
+                        variable: name.clone(),
+                        environment: environment.to_owned(),
+                        kind: ResolveErrorKind::InvalidSource { reason: msg },
+                    });
+                } else if matches!(source, Source::Skip) {
+                    debug!(variable = name.as_str(), "skipped");
+                } else {
+                    sources.insert(name.clone(), source.clone());
+                    origins.insert(name.clone(), origin);
+                }
+            }
+            Ok(None) => {
+                errors.push(ResolveError {
+                    variable: name.clone(),
+                    environment: environment.to_owned(),
+                    kind: ResolveErrorKind::NoConfig,
+                });
+            }
+        }
+    }
+    // Schedule every bundle that a selected variable reads from, as a node
+    // of its own so its source runs once no matter how many keys are used.
+    let mut bundle_formats: HashMap<String, (&str, BundleFormat)> = HashMap::new();
+    let refs: Vec<(String, String)> = sources
+        .iter()
+        .filter_map(|(name, source)| match source {
+            Source::FromBundle(r) => Some((name.clone(), r.bundle.clone())),
+            _ => None,
+        })
+        .collect();
+    for (variable, bundle_name) in refs {
+        let node = bundle_node(&bundle_name);
+        if bundle_formats.contains_key(&node) {
+            continue;
+        }
+        let Some((bundle_name, bundle)) = config.bundles.get_key_value(&bundle_name) else {
+            errors.push(ResolveError {
+                variable,
+                environment: environment.to_owned(),
+                kind: ResolveErrorKind::UnknownBundle { name: bundle_name },
+            });
+            continue;
+        };
+        let source = bundle.envs.get(environment).or(bundle.default.as_ref());
+        let invalid = |reason: String| ResolveError {
+            variable: node.clone(),
+            environment: environment.to_owned(),
+            kind: ResolveErrorKind::InvalidSource { reason },
+        };
+        match source {
+            None => errors.push(ResolveError {
+                variable: node.clone(),
+                environment: environment.to_owned(),
+                kind: ResolveErrorKind::NoConfig,
+            }),
+            Some(Source::FromBundle(_) | Source::Skip) => errors.push(invalid(
+                "a bundle must use a `cmd`, `sh`, `literal`, or `template` source".to_owned(),
+            )),
+            Some(source) => {
+                if let Err(msg) = source.validate() {
+                    errors.push(invalid(msg));
+                } else {
+                    sources.insert(node.clone(), source.clone());
+                }
+            }
+        }
+        bundle_formats.insert(node, (bundle_name, bundle.format));
+    }
+
+    // Warn about override names that don't appear on any variable.
+    for o in scope.overrides {
+        if !defined_overrides.contains(o.as_str()) {
+            warn!(name = o.as_str(), "override not defined on any variable");
+        }
+    }
+
+    if !errors.is_empty() {
+        if !keep_going {
+            return Err(errors);
+        }
+        drop_failed(&mut sources, &mut errors, environment);
+    }
+
+    Ok(Plan {
+        variables,
+        sources,
+        bundle_formats,
+        origins,
+        errors,
+    })
+}
+
+/// Remove the nodes named in `errors` from `sources`, then every node that
+/// (transitively) references one of them, reporting each as
+/// [`ResolveErrorKind::DependencyFailed`].
+fn drop_failed(
+    sources: &mut BTreeMap<String, Source>,
+    errors: &mut Vec<ResolveError>,
+    environment: &str,
+) {
+    let mut failed: HashSet<String> = errors.iter().map(|e| e.variable.clone()).collect();
+    sources.retain(|name, _| !failed.contains(name));
+    loop {
+        let blocked: Vec<(String, String)> = sources
+            .iter()
+            .filter_map(|(name, source)| {
+                let refs = source_references(source).ok()?;
+                let dependency = refs.into_iter().filter(|r| failed.contains(r)).min()?;
+                Some((name.clone(), dependency))
+            })
+            .collect();
+        if blocked.is_empty() {
+            return;
+        }
+        for (name, dependency) in blocked {
+            sources.remove(&name);
+            failed.insert(name.clone());
+            errors.push(ResolveError {
+                variable: name,
+                environment: environment.to_owned(),
+                kind: ResolveErrorKind::DependencyFailed { dependency },
+            });
+        }
+    }
+}
+
+/// `target` and every node it transitively depends on, out of `sources`.
+/// Problems elsewhere in `sources` (unknown references, cycles) are left for
+/// [`build_graph`] to report only if they are part of this subset.
+fn closure(sources: &BTreeMap<String, Source>, target: &str) -> BTreeMap<String, Source> {
+    let mut needed = BTreeMap::new();
+    let mut stack = vec![target.to_owned()];
+    while let Some(name) = stack.pop() {
+        let Some(source) = sources.get(&name) else {
+            continue;
+        };
+        if needed.contains_key(&name) {
+            continue;
+        }
+        stack.extend(dependencies(source, |dep| sources.contains_key(dep)).unwrap_or_default());
+        needed.insert(name, source.clone());
+    }
+    needed
+}
+
+/// Resolve the nodes of a plan and return the variables' values.
+///
+/// With a `target`, only that variable and what it transitively depends on
+/// are resolved; the rest of the plan may be broken.
+fn execute(
+    plan: &Plan<'_>,
+    scope: &Scope<'_>,
+    parallel: bool,
+    keep_going: bool,
+    target: Option<&str>,
+    timings: &mut Vec<SourceTiming>,
+) -> (HashMap<String, String>, Vec<ResolveError>) {
+    let restricted;
+    let sources = match target {
+        None => &plan.sources,
+        Some(target) => {
+            restricted = closure(&plan.sources, target);
+            &restricted
+        }
+    };
+    let graph = match build_graph(sources, scope.environment) {
+        Ok(graph) => graph,
+        Err(errors) => return (HashMap::new(), errors),
+    };
+    let mut run = Run::new(sources, &plan.bundle_formats, &graph, scope.environment);
+    run.keep_going = keep_going;
+    run.schedule(scope, parallel);
+    if interrupt::interrupted() {
+        run.report_interrupted();
+    } else if keep_going {
+        run.report_blocked();
+    }
+    timings.append(&mut run.timings);
+    let mut errors = run.errors;
+    errors.sort_by(|a, b| a.variable.cmp(&b.variable));
+    (run.resolved, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CmdOptions;
+    use crate::config::CmdSource;
+    use crate::config::Override;
+    use crate::config::RetryOptions;
+    use crate::config::ShOptions;
+    use crate::config::ShSource;
+    use crate::config::Shell;
+    use crate::config::StderrPattern;
+    use crate::config::Trim;
+    use crate::duration::HumanDuration;
+
+    const TS: &str = "2025-01-01T00:00:00+00:00";
+
+    fn resolve(
+        config: &Config,
+        environment: &str,
+        tags: &[String],
+        overrides: &[String],
+    ) -> Result<Vec<Resolved>, Vec<ResolveError>> {
+        resolve_with(config, environment, tags, overrides, true, None)
+    }
+
+    fn resolve_with(
+        config: &Config,
+        environment: &str,
+        tags: &[String],
+        overrides: &[String],
+        parallel: bool,
+        cache: Option<&Cache>,
+    ) -> Result<Vec<Resolved>, Vec<ResolveError>> {
+        let scope = Scope {
+            environment,
+            tags,
+            overrides,
+            timestamp: TS,
+            settings: &config.settings,
+            cache,
+            jobs: DEFAULT_JOBS,
+            fail_fast: false,
+            strict: false,
+            config_dir: Path::new("."),
+            dry_run: false,
+            cancel: AtomicBool::new(false),
+        };
+        let (resolved, errors) = resolve_all(config, &scope, parallel, false, &mut Vec::new());
+        if errors.is_empty() {
+            Ok(resolved)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn literal(value: &str) -> Source {
+        Source::Literal(value.to_owned())
+    }
+
+    fn template(value: &str) -> Source {
+        Source::Template(value.to_owned())
+    }
+
+    fn cmd(args: Vec<&str>) -> Source {
+        Source::Cmd(CmdSource::Args(
+            args.into_iter().map(ToOwned::to_owned).collect(),
+        ))
+    }
+
+    fn cmd_with(args: Vec<&str>, configure: impl FnOnce(&mut CmdOptions)) -> Source {
+        let mut options = CmdOptions {
+            args: args.into_iter().map(ToOwned::to_owned).collect(),
+            ..CmdOptions::default()
+        };
+        configure(&mut options);
+        Source::Cmd(CmdSource::Options(Box::new(options)))
+    }
+
+    fn sh(script: &str) -> Source {
+        Source::Sh(ShSource::Script(script.to_owned()))
+    }
+
+    fn skip() -> Source {
+        Source::Skip
+    }
+
+    fn var(envs: BTreeMap<String, Source>) -> crate::config::Variable {
+        crate::config::Variable {
+            description: None,
+            tags: vec![],
+            default: None,
+            envs,
+            overrides: BTreeMap::new(),
+            ..Default::default()
+        }
+    }
+
+    fn var_with_default(
+        default: Source,
+        envs: BTreeMap<String, Source>,
+    ) -> crate::config::Variable {
+        crate::config::Variable {
+            description: None,
+            tags: vec![],
+            default: Some(default),
+            envs,
+            overrides: BTreeMap::new(),
+            ..Default::default()
+        }
+    }
+
+    fn var_tagged(tags: Vec<&str>, envs: BTreeMap<String, Source>) -> crate::config::Variable {
+        crate::config::Variable {
+            description: None,
+            tags: tags.into_iter().map(ToOwned::to_owned).collect(),
+            default: None,
+            envs,
+            overrides: BTreeMap::new(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_template_references() {
+        let refs =
+            template_references("postgresql://{{ USER }}:{{ PASS }}@localhost/{{ DB }}").unwrap();
+        assert_eq!(
+            refs,
+            HashSet::from(["USER".to_owned(), "PASS".to_owned(), "DB".to_owned()])
+        );
+    }
+
+    #[test]
+    fn test_template_references_empty() {
+        let refs = template_references("no references here").unwrap();
+        assert!(refs.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_literal() {
+        let config = Config {
+            variables: BTreeMap::from([("FOO".to_owned(), {
+                let mut v = var(BTreeMap::from([("local".to_owned(), literal("bar"))]));
+                v.description = Some("A foo".to_owned());
+                v
+            })]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].name, "FOO");
+        assert_eq!(resolved[0].value, "bar");
+    }
+
+    #[test]
+    fn test_resolve_template() {
+        let config = Config {
+            variables: BTreeMap::from([
+                (
+                    "USER".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), literal("alice"))])),
+                ),
+                (
+                    "GREETING".to_owned(),
+                    var(BTreeMap::from([(
+                        "local".to_owned(),
+                        template("hello {{ USER }}!"),
+                    )])),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        let greeting = resolved.iter().find(|r| r.name == "GREETING").unwrap();
+        assert_eq!(greeting.value, "hello alice!");
+    }
+
+    #[test]
+    fn test_resolve_template_urlencode() {
+        let config = Config {
+            variables: BTreeMap::from([
+                (
+                    "USER".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), literal("alice"))])),
+                ),
+                (
+                    "PASS".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), literal("p@ss:word"))])),
+                ),
+                (
+                    "CONN".to_owned(),
+                    var(BTreeMap::from([(
+                        "local".to_owned(),
+                        template("{{ USER | urlencode }}:{{ PASS | urlencode }}"),
+                    )])),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        let conn = resolved.iter().find(|r| r.name == "CONN").unwrap();
+        assert_eq!(conn.value, "alice:p%40ss%3Aword");
+    }
+
+    #[test]
+    fn test_missing_environment() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "FOO".to_owned(),
+                var(BTreeMap::from([("prod".to_owned(), literal("x"))])),
+            )]),
+            ..Default::default()
+        };
+        let err = resolve(&config, "local", &[], &[]).unwrap_err();
+        assert_eq!(err.len(), 1);
+        assert!(matches!(err[0].kind, ResolveErrorKind::NoConfig));
+    }
+
+    #[test]
+    fn test_circular_dependency() {
+        let config = Config {
+            variables: BTreeMap::from([
+                (
+                    "A".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), template("{{ B }}"))])),
+                ),
+                (
+                    "B".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), template("{{ A }}"))])),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let err = resolve(&config, "local", &[], &[]).unwrap_err();
+        assert!(err
+            .iter()
+            .any(|e| matches!(&e.kind, ResolveErrorKind::CircularDependency { chain } if chain.len() >= 3)));
+    }
+
+    #[test]
+    fn test_unknown_reference() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "A".to_owned(),
+                var(BTreeMap::from([(
+                    "local".to_owned(),
+                    template("{{ NONEXISTENT }}"),
+                )])),
+            )]),
+            ..Default::default()
+        };
+        let err = resolve(&config, "local", &[], &[]).unwrap_err();
+        assert!(err.iter().any(
+            |e| matches!(&e.kind, ResolveErrorKind::UnknownReference { name } if name == "NONEXISTENT")
+        ));
+    }
+
+    #[test]
+    fn test_resolve_cmd_echo() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "VAL".to_owned(),
+                var(BTreeMap::from([(
+                    "local".to_owned(),
+                    cmd(vec!["echo", "hello"]),
+                )])),
+            )]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        assert_eq!(resolved[0].value, "hello");
+    }
+
+    fn single(source: Source) -> Config {
+        Config {
+            variables: BTreeMap::from([(
+                "VAL".to_owned(),
+                var(BTreeMap::from([("local".to_owned(), source)])),
+            )]),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_cmd_options() {
+        let source = cmd_with(
+            vec![
+                "sh",
+                "-c",
+                "printf '  %s:%s:' \"$GREETING\" \"$(pwd)\"; cat",
+            ],
+            |o| {
+                o.env = BTreeMap::from([("GREETING".to_owned(), "hi".to_owned())]);
+                o.cwd = Some("/".into());
+                o.stdin = Some("piped  \n".to_owned());
+                o.trim = Trim::Both;
+            },
+        );
+        let resolved = resolve(&single(source), "local", &[], &[]).unwrap();
+        assert_eq!(resolved[0].value, "hi:/:piped");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_cmd_trim_none() {
+        let source = cmd_with(vec!["echo", "x"], |o| o.trim = Trim::None);
+        let resolved = resolve(&single(source), "local", &[], &[]).unwrap();
+        assert_eq!(resolved[0].value, "x\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_cmd_allow_failure() {
+        let source = cmd_with(vec!["sh", "-c", "echo partial; exit 3"], |o| {
+            o.allow_failure = true;
+        });
+        let resolved = resolve(&single(source), "local", &[], &[]).unwrap();
+        assert_eq!(resolved[0].value, "partial");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_cmd_timeout() {
+        let source = cmd_with(vec!["sleep", "5"], |o| {
+            o.timeout = Some(HumanDuration(std::time::Duration::from_millis(100)));
+        });
+        let err = resolve(&single(source), "local", &[], &[]).unwrap_err();
+        assert!(matches!(
+            &err[0].kind,
+            ResolveErrorKind::Timeout { command, .. } if command[0] == "sleep"
+        ));
+    }
+
+    #[test]
+    fn test_default_fallback() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "FOO".to_owned(),
+                var_with_default(literal("fallback"), BTreeMap::new()),
+            )]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "any-env", &[], &[]).unwrap();
+        assert_eq!(resolved[0].value, "fallback");
+    }
+
+    #[test]
+    fn test_env_overrides_default() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "FOO".to_owned(),
+                var_with_default(
+                    literal("fallback"),
+                    BTreeMap::from([("local".to_owned(), literal("override"))]),
+                ),
+            )]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        assert_eq!(resolved[0].value, "override");
+    }
+
+    #[test]
+    fn test_circular_dependency_chain_message() {
+        let config = Config {
+            variables: BTreeMap::from([
+                (
+                    "A".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), template("{{ B }}"))])),
+                ),
+                (
+                    "B".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), template("{{ C }}"))])),
+                ),
+                (
+                    "C".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), template("{{ A }}"))])),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let err = resolve(&config, "local", &[], &[]).unwrap_err();
+        let cycle = err
+            .iter()
+            .find_map(|e| match &e.kind {
+                ResolveErrorKind::CircularDependency { chain } => Some(chain),
+                _ => None,
+            })
+            .expect("should have a cycle error");
+        // Chain should be e.g. ["A", "B", "C", "A"]
+        assert_eq!(
+            cycle.first(),
+            cycle.last(),
+            "chain should start and end with same node"
+        );
+        assert_eq!(
+            cycle.len(),
+            4,
+            "3-node cycle should have 4 entries (A->B->C->A)"
+        );
+    }
+
+    #[test]
+    fn test_skip_omits_variable() {
+        let config = Config {
+            variables: BTreeMap::from([
+                (
+                    "KEEP".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), literal("yes"))])),
+                ),
+                (
+                    "DROP".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), skip())])),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].name, "KEEP");
+    }
+
+    #[test]
+    fn test_skip_as_default() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "VAR".to_owned(),
+                var_with_default(
+                    skip(),
+                    BTreeMap::from([("staging".to_owned(), literal("yes"))]),
+                ),
+            )]),
+            ..Default::default()
+        };
+        // In staging, the env override provides a value.
+        let resolved = resolve(&config, "staging", &[], &[]).unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].value, "yes");
+        // In prod, the default skip applies — variable is omitted.
+        let resolved = resolve(&config, "prod", &[], &[]).unwrap();
+        assert!(resolved.is_empty());
+    }
+
+    #[test]
+    fn test_skip_reference_errors() {
+        let config = Config {
+            variables: BTreeMap::from([
+                (
+                    "SKIPPED".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), skip())])),
+                ),
+                (
+                    "USER".to_owned(),
+                    var(BTreeMap::from([(
+                        "local".to_owned(),
+                        template("hi {{ SKIPPED }}"),
+                    )])),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let err = resolve(&config, "local", &[], &[]).unwrap_err();
+        assert!(err.iter().any(
+            |e| matches!(&e.kind, ResolveErrorKind::UnknownReference { name } if name == "SKIPPED")
+        ));
+    }
+
+    #[test]
+    fn test_resolve_sh() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "VAL".to_owned(),
+                var(BTreeMap::from([("local".to_owned(), sh("echo hello"))])),
+            )]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        assert_eq!(resolved[0].value, "hello");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_sh_uses_configured_shell() {
+        // `$0` is the interpreter name when a script runs under `-c`.
+        let mut config = single(sh("echo \"$0\""));
+        config.settings.shell = Shell::Argv(vec!["sh".to_owned(), "-c".to_owned()]);
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        assert_eq!(resolved[0].value, "sh");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_sh_strict_fails_on_unset_variable() {
+        let script = "echo \"${ENVOKE_SURELY_UNSET_VARIABLE}\"";
+        let lenient = resolve(&single(sh(script)), "local", &[], &[]).unwrap();
+        assert_eq!(lenient[0].value, "");
+
+        let strict = Source::Sh(ShSource::Options(ShOptions {
+            script: script.to_owned(),
+            shell: None,
+            strict: Some(true),
+            needs: vec![],
+            cache: None,
+            retry: None,
+            concurrency_group: None,
+        }));
+        let err = resolve(&single(strict), "local", &[], &[]).unwrap_err();
+        assert!(matches!(
+            &err[0].kind,
+            ResolveErrorKind::CmdNonZero { command, .. }
+                if command[..4] == ["sh", "-e", "-u", "-c"]
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cmd_needs_template_value() {
+        let config = Config {
+            variables: BTreeMap::from([
+                (
+                    "HOST".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), literal("db"))])),
+                ),
+                (
+                    "URL".to_owned(),
+                    var(BTreeMap::from([(
+                        "local".to_owned(),
+                        template("postgres://{{ HOST }}/app"),
+                    )])),
+                ),
+                (
+                    "PROBE".to_owned(),
+                    var(BTreeMap::from([(
+                        "local".to_owned(),
+                        cmd_with(vec!["sh", "-c", "echo \"probe $URL\""], |o| {
+                            o.needs = vec!["URL".to_owned()];
+                        }),
+                    )])),
+                ),
+                (
+                    "WRAPPED".to_owned(),
+                    var(BTreeMap::from([(
+                        "local".to_owned(),
+                        template("[{{ PROBE }}]"),
+                    )])),
+                ),
+            ]),
+            ..Default::default()
+        };
+        for parallel in [true, false] {
+            let resolved = resolve_with(&config, "local", &[], &[], parallel, None).unwrap();
+            let wrapped = resolved.iter().find(|r| r.name == "WRAPPED").unwrap();
+            assert_eq!(wrapped.value, "[probe postgres://db/app]");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sh_needs_external_value() {
+        let config = Config {
+            variables: BTreeMap::from([
+                (
+                    "FIRST".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), sh("echo one"))])),
+                ),
+                (
+                    "SECOND".to_owned(),
+                    var(BTreeMap::from([(
+                        "local".to_owned(),
+                        Source::Sh(ShSource::Options(ShOptions {
+                            script: "echo \"$FIRST two\"".to_owned(),
+                            shell: None,
+                            strict: None,
+                            needs: vec!["FIRST".to_owned()],
+                            cache: None,
+                            retry: None,
+                            concurrency_group: None,
+                        })),
+                    )])),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        let second = resolved.iter().find(|r| r.name == "SECOND").unwrap();
+        assert_eq!(second.value, "one two");
+    }
+
+    #[test]
+    fn test_needs_unknown_and_cycle_errors() {
+        let config = single(cmd_with(vec!["true"], |o| {
+            o.needs = vec!["MISSING".to_owned()];
+        }));
+        let err = resolve(&config, "local", &[], &[]).unwrap_err();
+        assert!(err.iter().any(
+            |e| matches!(&e.kind, ResolveErrorKind::UnknownReference { name } if name == "MISSING")
+        ));
+
+        let config = Config {
+            variables: BTreeMap::from([
+                (
+                    "A".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), template("{{ B }}"))])),
+                ),
+                (
+                    "B".to_owned(),
+                    var(BTreeMap::from([(
+                        "local".to_owned(),
+                        cmd_with(vec!["true"], |o| o.needs = vec!["A".to_owned()]),
+                    )])),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let err = resolve(&config, "local", &[], &[]).unwrap_err();
+        assert!(
+            err.iter()
+                .any(|e| matches!(e.kind, ResolveErrorKind::CircularDependency { .. }))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cached_source_skips_second_run() {
+        let dir = std::env::temp_dir().join(format!("envoke-resolve-cache-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let counter = dir.join("runs");
+        let script = format!(
+            "echo x >> '{}'; wc -l < '{}'",
+            counter.display(),
+            counter.display()
+        );
+        let source = Source::Sh(ShSource::Options(ShOptions {
+            script,
+            shell: None,
+            strict: None,
+            needs: vec![],
+            cache: Some(crate::config::CacheOptions {
+                ttl: HumanDuration(std::time::Duration::from_hours(1)),
+            }),
+            retry: None,
+            concurrency_group: None,
+        }));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cache = Cache::new(dir.join("cache"), std::path::Path::new("envoke.yaml"));
+        let config = single(source);
+
+        let run = |cache| resolve_with(&config, "local", &[], &[], true, cache).unwrap();
+        assert_eq!(run(Some(&cache))[0].value.trim(), "1");
+        assert_eq!(
+            run(Some(&cache))[0].value.trim(),
+            "1",
+            "second run is cached"
+        );
+        assert_eq!(run(None)[0].value.trim(), "2", "no cache always runs");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // --- Tag filtering tests ---
+
+    #[test]
+    fn test_no_cli_tags_excludes_tagged() {
+        let config = Config {
+            variables: BTreeMap::from([
+                (
+                    "UNTAGGED".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), literal("a"))])),
+                ),
+                (
+                    "TAGGED".to_owned(),
+                    var_tagged(
+                        vec!["vault"],
+                        BTreeMap::from([("local".to_owned(), literal("b"))]),
+                    ),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].name, "UNTAGGED");
+    }
+
+    #[test]
+    fn test_cli_tags_match_includes_variable() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "SECRET".to_owned(),
+                var_tagged(
+                    vec!["vault"],
+                    BTreeMap::from([("local".to_owned(), literal("s3cret"))]),
+                ),
+            )]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &["vault".to_owned()], &[]).unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].value, "s3cret");
+    }
+
+    #[test]
+    fn test_cli_tags_no_match_excludes_variable() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "SECRET".to_owned(),
+                var_tagged(
+                    vec!["vault"],
+                    BTreeMap::from([("local".to_owned(), literal("s3cret"))]),
+                ),
+            )]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &["oauth".to_owned()], &[]).unwrap();
+        assert!(resolved.is_empty());
+    }
+
+    #[test]
+    fn test_untagged_always_included_with_cli_tags() {
+        let config = Config {
+            variables: BTreeMap::from([
+                (
+                    "ALWAYS".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), literal("yes"))])),
+                ),
+                (
+                    "CONDITIONAL".to_owned(),
+                    var_tagged(
+                        vec!["vault"],
+                        BTreeMap::from([("local".to_owned(), literal("maybe"))]),
+                    ),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &["other".to_owned()], &[]).unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].name, "ALWAYS");
+    }
+
+    #[test]
+    fn test_or_semantics_one_tag_matches() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "MULTI".to_owned(),
+                var_tagged(
+                    vec!["a", "b", "c"],
+                    BTreeMap::from([("local".to_owned(), literal("val"))]),
+                ),
+            )]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &["b".to_owned()], &[]).unwrap();
+        assert_eq!(resolved.len(), 1);
+    }
+
+    #[test]
+    fn test_multiple_cli_tags() {
+        let config = Config {
+            variables: BTreeMap::from([
+                (
+                    "VAULT_VAR".to_owned(),
+                    var_tagged(
+                        vec!["vault"],
+                        BTreeMap::from([("local".to_owned(), literal("v"))]),
+                    ),
+                ),
+                (
+                    "OAUTH_VAR".to_owned(),
+                    var_tagged(
+                        vec!["oauth"],
+                        BTreeMap::from([("local".to_owned(), literal("o"))]),
+                    ),
+                ),
+                (
+                    "OTHER_VAR".to_owned(),
+                    var_tagged(
+                        vec!["other"],
+                        BTreeMap::from([("local".to_owned(), literal("x"))]),
+                    ),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let resolved = resolve(
+            &config,
+            "local",
+            &["vault".to_owned(), "oauth".to_owned()],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(resolved.len(), 2);
+        let names: Vec<&str> = resolved.iter().map(|r| r.name.as_str()).collect();
+        assert!(names.contains(&"VAULT_VAR"));
+        assert!(names.contains(&"OAUTH_VAR"));
+    }
+
+    #[test]
+    fn test_excluded_variable_in_template_causes_error() {
+        let config = Config {
+            variables: BTreeMap::from([
+                (
+                    "SECRET".to_owned(),
+                    var_tagged(
+                        vec!["vault"],
+                        BTreeMap::from([("local".to_owned(), literal("s3cret"))]),
+                    ),
+                ),
+                (
+                    "CONN".to_owned(),
+                    var(BTreeMap::from([(
+                        "local".to_owned(),
+                        template("dsn://{{ SECRET }}@host"),
+                    )])),
+                ),
+            ]),
+            ..Default::default()
+        };
+        // SECRET is excluded by tag filter (no matching tag), so CONN's template
+        // reference fails
+        let err = resolve(&config, "local", &[], &[]).unwrap_err();
+        assert!(err.iter().any(
+            |e| matches!(&e.kind, ResolveErrorKind::UnknownReference { name } if name == "SECRET")
+        ));
+    }
+
+    #[test]
+    fn test_empty_tags_behaves_as_untagged() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "VAR".to_owned(),
+                crate::config::Variable {
+                    description: None,
+                    tags: vec![],
+                    default: None,
+                    envs: BTreeMap::from([("local".to_owned(), literal("val"))]),
+                    overrides: BTreeMap::new(),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &["something".to_owned()], &[]).unwrap();
+        assert_eq!(resolved.len(), 1);
+    }
+
+    #[test]
+    fn test_tag_filtering_and_skip_are_orthogonal() {
+        let config = Config {
+            variables: BTreeMap::from([
+                (
+                    "TAGGED_SKIP".to_owned(),
+                    var_tagged(
+                        vec!["vault"],
+                        BTreeMap::from([("local".to_owned(), skip())]),
+                    ),
+                ),
+                (
+                    "TAGGED_KEEP".to_owned(),
+                    var_tagged(
+                        vec!["vault"],
+                        BTreeMap::from([("local".to_owned(), literal("kept"))]),
+                    ),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &["vault".to_owned()], &[]).unwrap();
+        // TAGGED_SKIP is included by tag but skipped by source
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].name, "TAGGED_KEEP");
+    }
+
+    #[test]
+    fn test_tag_excluded_variable_no_config_no_error() {
+        let config = Config {
+            variables: BTreeMap::from([
+                (
+                    "ALWAYS".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), literal("yes"))])),
+                ),
+                (
+                    "PROD_ONLY".to_owned(),
+                    var_tagged(
+                        vec!["prod-secrets"],
+                        // No config for "local" environment, and no default
+                        BTreeMap::from([("prod".to_owned(), literal("secret"))]),
+                    ),
+                ),
+            ]),
+            ..Default::default()
+        };
+        // PROD_ONLY is tagged, so without --tag prod-secrets it's excluded,
+        // avoiding the NoConfig error it would otherwise produce for "local".
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].name, "ALWAYS");
+    }
+
+    // --- Override tests ---
+
+    fn var_with_overrides(
+        default: Option<Source>,
+        envs: BTreeMap<String, Source>,
+        overrides: BTreeMap<String, Override>,
+    ) -> crate::config::Variable {
+        crate::config::Variable {
+            description: None,
+            tags: vec![],
+            default,
+            envs,
+            overrides,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_override_env_specific() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "DB_HOST".to_owned(),
+                var_with_overrides(
+                    Some(literal("localhost")),
+                    BTreeMap::from([("prod".to_owned(), literal("172.10.0.1"))]),
+                    BTreeMap::from([(
+                        "read-replica".to_owned(),
+                        Override {
+                            default: None,
+                            envs: BTreeMap::from([("prod".to_owned(), literal("172.10.0.2"))]),
+                        },
+                    )]),
+                ),
+            )]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "prod", &[], &["read-replica".to_owned()]).unwrap();
+        assert_eq!(resolved[0].value, "172.10.0.2");
+    }
+
+    #[test]
+    fn test_override_default() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "DB_HOST".to_owned(),
+                var_with_overrides(
+                    Some(literal("localhost")),
+                    BTreeMap::new(),
+                    BTreeMap::from([(
+                        "read-replica".to_owned(),
+                        Override {
+                            default: Some(literal("localhost-ro")),
+                            envs: BTreeMap::new(),
+                        },
+                    )]),
+                ),
+            )]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "any-env", &[], &["read-replica".to_owned()]).unwrap();
+        assert_eq!(resolved[0].value, "localhost-ro");
+    }
+
+    #[test]
+    fn test_override_fallback_to_base_env() {
+        // Override is defined but has no entry for this env, falls to base env.
+        let config = Config {
+            variables: BTreeMap::from([(
+                "DB_HOST".to_owned(),
+                var_with_overrides(
+                    Some(literal("localhost")),
+                    BTreeMap::from([("staging".to_owned(), literal("staging-host"))]),
+                    BTreeMap::from([(
+                        "read-replica".to_owned(),
+                        Override {
+                            default: None,
+                            envs: BTreeMap::from([("prod".to_owned(), literal("prod-ro"))]),
+                        },
+                    )]),
+                ),
+            )]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "staging", &[], &["read-replica".to_owned()]).unwrap();
+        assert_eq!(resolved[0].value, "staging-host");
+    }
+
+    #[test]
+    fn test_override_fallback_to_base_default() {
+        // Override defined but no source at any level except base default.
+        let config = Config {
+            variables: BTreeMap::from([(
+                "DB_HOST".to_owned(),
+                var_with_overrides(
+                    Some(literal("fallback")),
+                    BTreeMap::new(),
+                    BTreeMap::from([(
+                        "read-replica".to_owned(),
+                        Override {
+                            default: None,
+                            envs: BTreeMap::from([("prod".to_owned(), literal("prod-ro"))]),
+                        },
+                    )]),
+                ),
+            )]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "staging", &[], &["read-replica".to_owned()]).unwrap();
+        assert_eq!(resolved[0].value, "fallback");
+    }
+
+    #[test]
+    fn test_override_full_chain() {
+        // Verify all four fallback levels with a single variable.
+        let config = Config {
+            variables: BTreeMap::from([(
+                "VAR".to_owned(),
+                var_with_overrides(
+                    Some(literal("base-default")),
+                    BTreeMap::from([("prod".to_owned(), literal("base-prod"))]),
+                    BTreeMap::from([(
+                        "alt".to_owned(),
+                        Override {
+                            default: Some(literal("ovr-default")),
+                            envs: BTreeMap::from([("prod".to_owned(), literal("ovr-prod"))]),
+                        },
+                    )]),
+                ),
+            )]),
+            ..Default::default()
+        };
+
+        // Level 1: override env
+        let r = resolve(&config, "prod", &[], &["alt".to_owned()]).unwrap();
+        assert_eq!(r[0].value, "ovr-prod");
+
+        // Level 2: override default (env not in override)
+        let r = resolve(&config, "staging", &[], &["alt".to_owned()]).unwrap();
+        assert_eq!(r[0].value, "ovr-default");
+
+        // Without override active, base env
+        let r = resolve(&config, "prod", &[], &[]).unwrap();
+        assert_eq!(r[0].value, "base-prod");
+
+        // Without override active, base default
+        let r = resolve(&config, "staging", &[], &[]).unwrap();
+        assert_eq!(r[0].value, "base-default");
+    }
+
+    #[test]
+    fn test_resolved_provenance() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "VAR".to_owned(),
+                var_with_overrides(
+                    Some(literal("base-default")),
+                    BTreeMap::from([("prod".to_owned(), cmd(vec!["echo", "base-prod"]))]),
+                    BTreeMap::from([(
+                        "alt".to_owned(),
+                        Override {
+                            default: Some(template("ovr-default")),
+                            envs: BTreeMap::new(),
+                        },
+                    )]),
+                ),
+            )]),
+            ..Default::default()
+        };
+
+        let r = resolve(&config, "prod", &[], &[]).unwrap();
+        assert_eq!(r[0].source, "cmd");
+        assert_eq!(
+            r[0].origin,
+            Origin::Env {
+                environment: "prod".to_owned()
+            }
+        );
+
+        let r = resolve(&config, "prod", &[], &["alt".to_owned()]).unwrap();
+        assert_eq!(r[0].source, "template");
+        assert_eq!(r[0].origin.override_name(), Some("alt"));
+        assert!(!r[0].origin.is_env_specific());
+        assert_eq!(
+            serde_json::to_value(&r[0].origin).unwrap(),
+            serde_json::json!({
+                "kind": "default",
+                "override": "alt",
+                "path": "overrides.alt.default",
+            })
+        );
+
+        let r = resolve(&config, "staging", &[], &[]).unwrap();
+        assert_eq!((r[0].source, &r[0].origin), ("literal", &Origin::Default));
+    }
+
+    #[test]
+    fn test_no_override_ignores_data() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "DB_HOST".to_owned(),
+                var_with_overrides(
+                    Some(literal("base")),
+                    BTreeMap::new(),
+                    BTreeMap::from([(
+                        "read-replica".to_owned(),
+                        Override {
+                            default: Some(literal("override-val")),
+                            envs: BTreeMap::new(),
+                        },
+                    )]),
+                ),
+            )]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        assert_eq!(resolved[0].value, "base");
+    }
+
+    #[test]
+    fn test_override_with_skip() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "VAR".to_owned(),
+                var_with_overrides(
+                    Some(literal("present")),
+                    BTreeMap::new(),
+                    BTreeMap::from([(
+                        "disable".to_owned(),
+                        Override {
+                            default: Some(skip()),
+                            envs: BTreeMap::new(),
+                        },
+                    )]),
+                ),
+            )]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &[], &["disable".to_owned()]).unwrap();
+        assert!(resolved.is_empty());
+    }
+
+    #[test]
+    fn test_override_with_template() {
         let config = Config {
             variables: BTreeMap::from([
                 (
-                    "A".to_owned(),
-                    var(BTreeMap::from([("local".to_owned(), template("{{ B }}"))])),
+                    "HOST".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), literal("db.local"))])),
                 ),
                 (
-                    "B".to_owned(),
-                    var(BTreeMap::from([("local".to_owned(), template("{{ C }}"))])),
+                    "CONN".to_owned(),
+                    var_with_overrides(
+                        Some(template("postgres://{{ HOST }}/main")),
+                        BTreeMap::new(),
+                        BTreeMap::from([(
+                            "alt".to_owned(),
+                            Override {
+                                default: Some(template("postgres://{{ HOST }}/replica")),
+                                envs: BTreeMap::new(),
+                            },
+                        )]),
+                    ),
                 ),
+            ]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &[], &["alt".to_owned()]).unwrap();
+        let conn = resolved.iter().find(|r| r.name == "CONN").unwrap();
+        assert_eq!(conn.value, "postgres://db.local/replica");
+    }
+
+    #[test]
+    fn test_override_with_tags() {
+        // Tags and overrides are orthogonal.
+        let config = Config {
+            variables: BTreeMap::from([
                 (
-                    "C".to_owned(),
-                    var(BTreeMap::from([("local".to_owned(), template("{{ A }}"))])),
+                    "TAGGED".to_owned(),
+                    crate::config::Variable {
+                        description: None,
+                        tags: vec!["vault".to_owned()],
+                        default: Some(literal("base")),
+                        envs: BTreeMap::new(),
+                        overrides: BTreeMap::from([(
+                            "alt".to_owned(),
+                            Override {
+                                default: Some(literal("alt-val")),
+                                envs: BTreeMap::new(),
+                            },
+                        )]),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "ALWAYS".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), literal("yes"))])),
                 ),
             ]),
+            ..Default::default()
         };
-        let err = resolve(&config, "local", &[], &[]).unwrap_err();
-        let cycle = err
-            .iter()
-            .find_map(|e| match &e.kind {
-                ResolveErrorKind::CircularDependency { chain } => Some(chain),
-                _ => None,
-            })
-            .expect("should have a cycle error");
-        // Chain should be e.g. ["A", "B", "C", "A"]
-        assert_eq!(
-            cycle.first(),
-            cycle.last(),
-            "chain should start and end with same node"
-        );
-        assert_eq!(
-            cycle.len(),
-            4,
-            "3-node cycle should have 4 entries (A->B->C->A)"
+        // Tag not matched: TAGGED excluded, override irrelevant.
+        let resolved = resolve(&config, "local", &[], &["alt".to_owned()]).unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].name, "ALWAYS");
+
+        // Tag matched and override active: override value used.
+        let resolved =
+            resolve(&config, "local", &["vault".to_owned()], &["alt".to_owned()]).unwrap();
+        let tagged = resolved.iter().find(|r| r.name == "TAGGED").unwrap();
+        assert_eq!(tagged.value, "alt-val");
+    }
+
+    #[test]
+    fn test_override_no_config_error() {
+        // No source at any level → NoConfig.
+        let config = Config {
+            variables: BTreeMap::from([(
+                "VAR".to_owned(),
+                var_with_overrides(
+                    None,
+                    BTreeMap::new(),
+                    BTreeMap::from([(
+                        "alt".to_owned(),
+                        Override {
+                            default: None,
+                            envs: BTreeMap::new(),
+                        },
+                    )]),
+                ),
+            )]),
+            ..Default::default()
+        };
+        let err = resolve(&config, "local", &[], &["alt".to_owned()]).unwrap_err();
+        assert!(
+            err.iter()
+                .any(|e| matches!(e.kind, ResolveErrorKind::NoConfig))
         );
     }
 
     #[test]
-    fn test_skip_omits_variable() {
+    fn test_override_undefined_for_variable() {
+        // Requested override not defined on this variable, falls to base.
+        let config = Config {
+            variables: BTreeMap::from([(
+                "VAR".to_owned(),
+                var_with_default(literal("base"), BTreeMap::new()),
+            )]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "any", &[], &["nonexistent".to_owned()]).unwrap();
+        assert_eq!(resolved[0].value, "base");
+    }
+
+    #[test]
+    fn test_multiple_overrides_disjoint() {
+        let config = Config {
+            variables: BTreeMap::from([
+                (
+                    "DB_HOST".to_owned(),
+                    var_with_overrides(
+                        Some(literal("main-db")),
+                        BTreeMap::new(),
+                        BTreeMap::from([(
+                            "read-replica".to_owned(),
+                            Override {
+                                default: Some(literal("replica-db")),
+                                envs: BTreeMap::new(),
+                            },
+                        )]),
+                    ),
+                ),
+                (
+                    "CACHE".to_owned(),
+                    var_with_overrides(
+                        Some(literal("lru")),
+                        BTreeMap::new(),
+                        BTreeMap::from([(
+                            "aggressive".to_owned(),
+                            Override {
+                                default: Some(literal("lfu")),
+                                envs: BTreeMap::new(),
+                            },
+                        )]),
+                    ),
+                ),
+            ]),
+            ..Default::default()
+        };
+        let resolved = resolve(
+            &config,
+            "prod",
+            &[],
+            &["read-replica".to_owned(), "aggressive".to_owned()],
+        )
+        .unwrap();
+        let db = resolved.iter().find(|r| r.name == "DB_HOST").unwrap();
+        assert_eq!(db.value, "replica-db");
+        let cache = resolved.iter().find(|r| r.name == "CACHE").unwrap();
+        assert_eq!(cache.value, "lfu");
+    }
+
+    #[test]
+    fn test_resolve_template_shell_escape() {
         let config = Config {
             variables: BTreeMap::from([
                 (
-                    "KEEP".to_owned(),
-                    var(BTreeMap::from([("local".to_owned(), literal("yes"))])),
+                    "PASS".to_owned(),
+                    var(BTreeMap::from([(
+                        "local".to_owned(),
+                        literal("it's a secret"),
+                    )])),
                 ),
                 (
-                    "DROP".to_owned(),
-                    var(BTreeMap::from([("local".to_owned(), skip())])),
+                    "ESCAPED".to_owned(),
+                    var(BTreeMap::from([(
+                        "local".to_owned(),
+                        template("{{ PASS | shell_escape }}"),
+                    )])),
                 ),
             ]),
+            ..Default::default()
         };
         let resolved = resolve(&config, "local", &[], &[]).unwrap();
-        assert_eq!(resolved.len(), 1);
-        assert_eq!(resolved[0].name, "KEEP");
-    }
-
-    #[test]
-    fn test_skip_as_default() {
-        let config = Config {
-            variables: BTreeMap::from([(
-                "VAR".to_owned(),
-                var_with_default(
-                    skip(),
-                    BTreeMap::from([("staging".to_owned(), literal("yes"))]),
-                ),
-            )]),
-        };
-        // In staging, the env override provides a value.
-        let resolved = resolve(&config, "staging", &[], &[]).unwrap();
-        assert_eq!(resolved.len(), 1);
-        assert_eq!(resolved[0].value, "yes");
-        // In prod, the default skip applies — variable is omitted.
-        let resolved = resolve(&config, "prod", &[], &[]).unwrap();
-        assert!(resolved.is_empty());
+        let escaped = resolved.iter().find(|r| r.name == "ESCAPED").unwrap();
+        assert_eq!(escaped.value, "it'\\''s a secret");
     }
 
     #[test]
-    fn test_skip_reference_errors() {
+    fn test_resolve_template_builtin_filter() {
         let config = Config {
             variables: BTreeMap::from([
                 (
-                    "SKIPPED".to_owned(),
-                    var(BTreeMap::from([("local".to_owned(), skip())])),
+                    "NAME".to_owned(),
+                    var(BTreeMap::from([("local".to_owned(), literal("hello"))])),
                 ),
                 (
-                    "USER".to_owned(),
+                    "UPPER".to_owned(),
                     var(BTreeMap::from([(
                         "local".to_owned(),
-                        template("hi {{ SKIPPED }}"),
+                        template("{{ NAME | upper }}"),
                     )])),
                 ),
             ]),
+            ..Default::default()
         };
-        let err = resolve(&config, "local", &[], &[]).unwrap_err();
-        assert!(err.iter().any(
-            |e| matches!(&e.kind, ResolveErrorKind::UnknownReference { name } if name == "SKIPPED")
-        ));
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        let upper = resolved.iter().find(|r| r.name == "UPPER").unwrap();
+        assert_eq!(upper.value, "HELLO");
     }
 
     #[test]
-    fn test_resolve_sh() {
+    fn test_multiple_overrides_conflict() {
         let config = Config {
             variables: BTreeMap::from([(
-                "VAL".to_owned(),
-                var(BTreeMap::from([("local".to_owned(), sh("echo hello"))])),
+                "VAR".to_owned(),
+                var_with_overrides(
+                    Some(literal("base")),
+                    BTreeMap::new(),
+                    BTreeMap::from([
+                        (
+                            "a".to_owned(),
+                            Override {
+                                default: Some(literal("a-val")),
+                                envs: BTreeMap::new(),
+                            },
+                        ),
+                        (
+                            "b".to_owned(),
+                            Override {
+                                default: Some(literal("b-val")),
+                                envs: BTreeMap::new(),
+                            },
+                        ),
+                    ]),
+                ),
             )]),
+            ..Default::default()
         };
-        let resolved = resolve(&config, "local", &[], &[]).unwrap();
-        assert_eq!(resolved[0].value, "hello");
+        let err = resolve(&config, "prod", &[], &["a".to_owned(), "b".to_owned()]).unwrap_err();
+        assert!(err.iter().any(|e| matches!(
+            &e.kind,
+            ResolveErrorKind::ConflictingOverrides { names }
+            if names.len() == 2
+        )));
     }
 
-    // --- Tag filtering tests ---
+    // --- Export tests ---
 
     #[test]
-    fn test_no_cli_tags_excludes_tagged() {
+    fn test_non_exported_variable_feeds_template_but_is_omitted() {
         let config = Config {
             variables: BTreeMap::from([
+                ("DB_HOST_BASE".to_owned(), {
+                    let mut v = var(BTreeMap::from([("local".to_owned(), literal("db"))]));
+                    v.export = false;
+                    v
+                }),
                 (
-                    "UNTAGGED".to_owned(),
-                    var(BTreeMap::from([("local".to_owned(), literal("a"))])),
-                ),
-                (
-                    "TAGGED".to_owned(),
-                    var_tagged(
-                        vec!["vault"],
-                        BTreeMap::from([("local".to_owned(), literal("b"))]),
-                    ),
+                    "DATABASE_URL".to_owned(),
+                    var(BTreeMap::from([(
+                        "local".to_owned(),
+                        template("postgres://{{ DB_HOST_BASE }}.internal/app"),
+                    )])),
                 ),
             ]),
+            ..Default::default()
         };
         let resolved = resolve(&config, "local", &[], &[]).unwrap();
         assert_eq!(resolved.len(), 1);
-        assert_eq!(resolved[0].name, "UNTAGGED");
+        assert_eq!(resolved[0].name, "DATABASE_URL");
+        assert_eq!(resolved[0].value, "postgres://db.internal/app");
     }
 
     #[test]
-    fn test_cli_tags_match_includes_variable() {
-        let config = Config {
-            variables: BTreeMap::from([(
-                "SECRET".to_owned(),
-                var_tagged(
-                    vec!["vault"],
-                    BTreeMap::from([("local".to_owned(), literal("s3cret"))]),
-                ),
-            )]),
-        };
-        let resolved = resolve(&config, "local", &["vault".to_owned()], &[]).unwrap();
-        assert_eq!(resolved.len(), 1);
-        assert_eq!(resolved[0].value, "s3cret");
+    fn test_export_defaults_to_true() {
+        let config: Config =
+            serde_yml::from_str("variables:\n  A:\n    default:\n      literal: x\n").unwrap();
+        assert!(config.variables["A"].export);
+        let config: Config = serde_yml::from_str(
+            "variables:\n  A:\n    export: false\n    default:\n      literal: x\n",
+        )
+        .unwrap();
+        assert!(!config.variables["A"].export);
     }
 
+    // --- Meta object tests ---
+
     #[test]
-    fn test_cli_tags_no_match_excludes_variable() {
+    fn test_template_meta_environment() {
         let config = Config {
             variables: BTreeMap::from([(
-                "SECRET".to_owned(),
-                var_tagged(
-                    vec!["vault"],
-                    BTreeMap::from([("local".to_owned(), literal("s3cret"))]),
-                ),
+                "URL".to_owned(),
+                var(BTreeMap::from([(
+                    "staging".to_owned(),
+                    template("https://{{ meta.environment }}.example.com"),
+                )])),
             )]),
+            ..Default::default()
         };
-        let resolved = resolve(&config, "local", &["oauth".to_owned()], &[]).unwrap();
-        assert!(resolved.is_empty());
+        let resolved = resolve(&config, "staging", &[], &[]).unwrap();
+        assert_eq!(resolved[0].value, "https://staging.example.com");
     }
 
     #[test]
-    fn test_untagged_always_included_with_cli_tags() {
+    fn test_template_meta_with_variable_refs() {
         let config = Config {
             variables: BTreeMap::from([
                 (
-                    "ALWAYS".to_owned(),
-                    var(BTreeMap::from([("local".to_owned(), literal("yes"))])),
+                    "HOST".to_owned(),
+                    var(BTreeMap::from([("prod".to_owned(), literal("db.prod"))])),
                 ),
                 (
-                    "CONDITIONAL".to_owned(),
-                    var_tagged(
-                        vec!["vault"],
-                        BTreeMap::from([("local".to_owned(), literal("maybe"))]),
-                    ),
+                    "CONN".to_owned(),
+                    var(BTreeMap::from([(
+                        "prod".to_owned(),
+                        template("postgres://{{ HOST }}/{{ meta.environment }}"),
+                    )])),
                 ),
             ]),
+            ..Default::default()
         };
-        let resolved = resolve(&config, "local", &["other".to_owned()], &[]).unwrap();
-        assert_eq!(resolved.len(), 1);
-        assert_eq!(resolved[0].name, "ALWAYS");
+        let resolved = resolve(&config, "prod", &[], &[]).unwrap();
+        let conn = resolved.iter().find(|r| r.name == "CONN").unwrap();
+        assert_eq!(conn.value, "postgres://db.prod/prod");
     }
 
     #[test]
-    fn test_or_semantics_one_tag_matches() {
+    fn test_template_meta_tags() {
         let config = Config {
             variables: BTreeMap::from([(
-                "MULTI".to_owned(),
-                var_tagged(
-                    vec!["a", "b", "c"],
-                    BTreeMap::from([("local".to_owned(), literal("val"))]),
-                ),
+                "TAGS".to_owned(),
+                var_with_default(template("{{ meta.tags | join(\",\") }}"), BTreeMap::new()),
             )]),
+            ..Default::default()
         };
-        let resolved = resolve(&config, "local", &["b".to_owned()], &[]).unwrap();
-        assert_eq!(resolved.len(), 1);
+        let resolved = resolve(
+            &config,
+            "local",
+            &["vault".to_owned(), "oauth".to_owned()],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(resolved[0].value, "vault,oauth");
     }
 
     #[test]
-    fn test_multiple_cli_tags() {
+    fn test_template_meta_overrides() {
         let config = Config {
-            variables: BTreeMap::from([
-                (
-                    "VAULT_VAR".to_owned(),
-                    var_tagged(
-                        vec!["vault"],
-                        BTreeMap::from([("local".to_owned(), literal("v"))]),
-                    ),
-                ),
-                (
-                    "OAUTH_VAR".to_owned(),
-                    var_tagged(
-                        vec!["oauth"],
-                        BTreeMap::from([("local".to_owned(), literal("o"))]),
-                    ),
-                ),
-                (
-                    "OTHER_VAR".to_owned(),
-                    var_tagged(
-                        vec!["other"],
-                        BTreeMap::from([("local".to_owned(), literal("x"))]),
-                    ),
+            variables: BTreeMap::from([(
+                "OVERRIDES".to_owned(),
+                var_with_default(
+                    template("{{ meta.overrides | join(\",\") }}"),
+                    BTreeMap::new(),
                 ),
-            ]),
+            )]),
+            ..Default::default()
         };
         let resolved = resolve(
             &config,
             "local",
-            &["vault".to_owned(), "oauth".to_owned()],
             &[],
+            &["alt".to_owned(), "fast".to_owned()],
         )
         .unwrap();
-        assert_eq!(resolved.len(), 2);
-        let names: Vec<&str> = resolved.iter().map(|r| r.name.as_str()).collect();
-        assert!(names.contains(&"VAULT_VAR"));
-        assert!(names.contains(&"OAUTH_VAR"));
+        assert_eq!(resolved[0].value, "alt,fast");
+    }
+
+    #[test]
+    fn test_template_meta_timestamp() {
+        let config = Config {
+            variables: BTreeMap::from([(
+                "TS".to_owned(),
+                var_with_default(template("{{ meta.timestamp }}"), BTreeMap::new()),
+            )]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        assert_eq!(resolved[0].value, TS);
     }
 
     #[test]
-    fn test_excluded_variable_in_template_causes_error() {
+    fn test_template_filters_match_output_templates() {
         let config = Config {
-            variables: BTreeMap::from([
-                (
-                    "SECRET".to_owned(),
-                    var_tagged(
-                        vec!["vault"],
-                        BTreeMap::from([("local".to_owned(), literal("s3cret"))]),
-                    ),
+            variables: BTreeMap::from([(
+                "STAMP".to_owned(),
+                var_with_default(
+                    template("{{ meta.timestamp | date('%Y') }}/{{ 'a b' | wrap(1) | join(',') }}"),
+                    BTreeMap::new(),
                 ),
-                (
-                    "CONN".to_owned(),
-                    var(BTreeMap::from([(
-                        "local".to_owned(),
-                        template("dsn://{{ SECRET }}@host"),
-                    )])),
+            )]),
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        assert_eq!(resolved[0].value, "2025/a,b");
+    }
+
+    #[test]
+    fn test_strict_templates_reject_undefined_meta_fields() {
+        let mut config = Config {
+            variables: BTreeMap::from([(
+                "URL".to_owned(),
+                var_with_default(
+                    template("https://{{ meta.enviroment }}.example.com"),
+                    BTreeMap::new(),
                 ),
-            ]),
+            )]),
+            ..Default::default()
         };
-        // SECRET is excluded by tag filter (no matching tag), so CONN's template
-        // reference fails
-        let err = resolve(&config, "local", &[], &[]).unwrap_err();
-        assert!(err.iter().any(
-            |e| matches!(&e.kind, ResolveErrorKind::UnknownReference { name } if name == "SECRET")
+        let resolved = Resolver::new(&config, "local").resolve().unwrap();
+        assert_eq!(resolved[0].value, "https://.example.com");
+
+        let errors = Resolver::new(&config, "local")
+            .strict(true)
+            .resolve()
+            .unwrap_err()
+            .errors;
+        assert!(matches!(
+            &errors[0].kind,
+            ResolveErrorKind::TemplateRender { reason } if reason.contains("undefined")
         ));
+
+        config.settings.strict_templates = true;
+        assert!(Resolver::new(&config, "local").resolve().is_err());
     }
 
     #[test]
-    fn test_empty_tags_behaves_as_untagged() {
-        let config = Config {
+    fn test_template_functions_read_relative_to_config_dir() {
+        let dir = std::env::temp_dir().join(format!("envoke-config-dir-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("VERSION"), "2.0.1\n").unwrap();
+        let mut config = Config {
             variables: BTreeMap::from([(
-                "VAR".to_owned(),
-                crate::config::Variable {
-                    description: None,
-                    tags: vec![],
-                    default: None,
-                    envs: BTreeMap::from([("local".to_owned(), literal("val"))]),
-                    overrides: BTreeMap::new(),
-                },
+                "VERSION".to_owned(),
+                var_with_default(template(r#"{{ file("VERSION") | trim }}"#), BTreeMap::new()),
             )]),
+            ..Default::default()
         };
-        let resolved = resolve(&config, "local", &["something".to_owned()], &[]).unwrap();
-        assert_eq!(resolved.len(), 1);
+        let resolved = Resolver::new(&config, "local")
+            .config_dir(&dir)
+            .resolve()
+            .unwrap();
+        assert_eq!(resolved[0].value, "2.0.1");
+
+        config.settings.pure_templates = true;
+        let errors = Resolver::new(&config, "local")
+            .config_dir(&dir)
+            .resolve()
+            .unwrap_err()
+            .errors;
+        assert!(matches!(
+            &errors[0].kind,
+            ResolveErrorKind::TemplateRender { reason } if reason.contains("pure_templates")
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_tag_filtering_and_skip_are_orthogonal() {
+    fn test_cmd_cwd_is_relative_to_config_dir() {
+        let dir = std::env::temp_dir().join(format!("envoke-cwd-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("infra")).unwrap();
+        std::fs::write(dir.join("infra/marker"), "here").unwrap();
+        let config = single(cmd_with(vec!["cat", "marker"], |options| {
+            options.cwd = Some("infra".into());
+        }));
+        let resolved = Resolver::new(&config, "local")
+            .config_dir(&dir)
+            .resolve()
+            .unwrap();
+        assert_eq!(resolved[0].value, "here");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dry_run_plans_in_order_without_running() {
+        let marker = std::env::temp_dir().join(format!("envoke-dry-run-{}", std::process::id()));
+        let touch = format!("touch {}; echo x", marker.display());
+        let config: Config = serde_yml::from_str(&format!(
+            r"
+bundles:
+  db:
+    format: json
+    default: {{ sh: '{touch}' }}
+variables:
+  URL:
+    default: {{ template: '{{{{ USER }}}}@{{{{ HOST }}}}' }}
+  HOST:
+    default: {{ sh: '{touch}' }}
+  USER:
+    default: {{ from_bundle: {{ bundle: db }} }}
+  ITEM_{{{{ item }}}}:
+    for_each: {{ cmd: [sh, -c, '{touch}'] }}
+    default: {{ literal: x }}
+"
+        ))
+        .unwrap();
+        let plan = Resolver::new(&config, "local").dry_run().unwrap();
+        let order: Vec<&str> = plan.steps.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(order, ["HOST", "bundle:db", "USER", "URL"]);
+        assert_eq!(plan.steps[3].dependencies, ["HOST", "USER"]);
+        assert!(plan.steps[1].origin.is_none());
+        assert_eq!(plan.unlisted[0].0, "ITEM_{{ item }}");
+        assert_eq!(plan.commands(), 3);
+        assert!(!marker.exists());
+    }
+
+    fn cmd_saturation_config(count: usize) -> (Config, Vec<(String, String)>) {
+        assert!(count > DEFAULT_JOBS, "fixture must exceed pool size");
+        let mut variables = BTreeMap::new();
+        let mut expected = Vec::with_capacity(count);
+        for i in 0..count {
+            let name = format!("VAR_{i:02}");
+            let value = format!("value-{i}");
+            variables.insert(
+                name.clone(),
+                var(BTreeMap::from([(
+                    "local".to_owned(),
+                    cmd(vec!["echo", &value]),
+                )])),
+            );
+            expected.push((name, value));
+        }
+        (
+            Config {
+                variables,
+                ..Default::default()
+            },
+            expected,
+        )
+    }
+
+    #[test]
+    fn test_parallel_queue_saturation() {
+        let (config, expected) = cmd_saturation_config(DEFAULT_JOBS * 3);
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        let got: Vec<(String, String)> = resolved.into_iter().map(|r| (r.name, r.value)).collect();
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn test_parallel_mixed_success_and_error() {
+        let mut variables = BTreeMap::new();
+        for name in ["OK_A", "OK_B", "OK_C"] {
+            variables.insert(
+                name.to_owned(),
+                var(BTreeMap::from([(
+                    "local".to_owned(),
+                    cmd(vec!["echo", "ok"]),
+                )])),
+            );
+        }
+        for name in ["FAIL_X", "FAIL_Y"] {
+            variables.insert(
+                name.to_owned(),
+                var(BTreeMap::from([("local".to_owned(), cmd(vec!["false"]))])),
+            );
+        }
         let config = Config {
-            variables: BTreeMap::from([
-                (
-                    "TAGGED_SKIP".to_owned(),
-                    var_tagged(
-                        vec!["vault"],
-                        BTreeMap::from([("local".to_owned(), skip())]),
-                    ),
-                ),
-                (
-                    "TAGGED_KEEP".to_owned(),
-                    var_tagged(
-                        vec!["vault"],
-                        BTreeMap::from([("local".to_owned(), literal("kept"))]),
-                    ),
-                ),
-            ]),
+            variables,
+            ..Default::default()
         };
-        let resolved = resolve(&config, "local", &["vault".to_owned()], &[]).unwrap();
-        // TAGGED_SKIP is included by tag but skipped by source
-        assert_eq!(resolved.len(), 1);
-        assert_eq!(resolved[0].name, "TAGGED_KEEP");
+        let errors = resolve(&config, "local", &[], &[]).unwrap_err();
+        let mut failing: Vec<String> = errors.into_iter().map(|e| e.variable).collect();
+        failing.sort();
+        assert_eq!(failing, vec!["FAIL_X".to_owned(), "FAIL_Y".to_owned()]);
     }
 
     #[test]
-    fn test_tag_excluded_variable_no_config_no_error() {
+    fn test_sequential_resolves_same_values_as_parallel() {
+        let (config, expected) = cmd_saturation_config(DEFAULT_JOBS * 3);
+        let resolved = resolve_with(&config, "local", &[], &[], false, None).unwrap();
+        let got: Vec<(String, String)> = resolved.into_iter().map(|r| (r.name, r.value)).collect();
+        assert_eq!(got, expected);
+    }
+
+    /// Config whose sources all take the same lock directory, so any two
+    /// running at once makes one of them fail.
+    fn exclusive_config(dir: &std::path::Path, group: Option<&str>) -> Config {
+        let lock = dir.join("lock");
+        let script = format!(
+            "mkdir '{l}' || exit 7; sleep 0.05; rmdir '{l}'; echo ok",
+            l = lock.display()
+        );
+        let variables = (0..6)
+            .map(|i| {
+                let source = Source::Sh(ShSource::Options(ShOptions {
+                    script: script.clone(),
+                    shell: None,
+                    strict: None,
+                    needs: vec![],
+                    cache: None,
+                    retry: None,
+                    concurrency_group: group.map(str::to_owned),
+                }));
+                (
+                    format!("VAR_{i}"),
+                    var_with_default(source, BTreeMap::new()),
+                )
+            })
+            .collect();
+        Config {
+            variables,
+            ..Default::default()
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_concurrency_group_runs_serially() {
+        let dir = temp_dir("group");
+        let config = exclusive_config(&dir, Some("vendor-cli"));
+        let resolved = Resolver::new(&config, "local").resolve().unwrap();
+        assert_eq!(resolved.len(), 6);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_jobs_limits_pool() {
+        let dir = temp_dir("jobs");
+        let mut config = exclusive_config(&dir, None);
+        config.settings.jobs = NonZeroUsize::new(1);
+        assert_eq!(Resolver::new(&config, "local").resolve().unwrap().len(), 6);
+
+        // The builder takes precedence over settings.
+        config.settings.jobs = NonZeroUsize::new(6);
+        let resolver = Resolver::new(&config, "local").jobs(NonZeroUsize::MIN);
+        assert_eq!(resolver.resolve().unwrap().len(), 6);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fail_fast_kills_running_sources() {
         let config = Config {
             variables: BTreeMap::from([
                 (
-                    "ALWAYS".to_owned(),
-                    var(BTreeMap::from([("local".to_owned(), literal("yes"))])),
+                    "BROKEN".to_owned(),
+                    var_with_default(sh("sleep 0.1; exit 3"), BTreeMap::new()),
                 ),
                 (
-                    "PROD_ONLY".to_owned(),
-                    var_tagged(
-                        vec!["prod-secrets"],
-                        // No config for "local" environment, and no default
-                        BTreeMap::from([("prod".to_owned(), literal("secret"))]),
-                    ),
+                    "SLOW".to_owned(),
+                    var_with_default(sh("sleep 5; echo done"), BTreeMap::new()),
                 ),
             ]),
+            ..Default::default()
         };
-        // PROD_ONLY is tagged, so without --tag prod-secrets it's excluded,
-        // avoiding the NoConfig error it would otherwise produce for "local".
-        let resolved = resolve(&config, "local", &[], &[]).unwrap();
-        assert_eq!(resolved.len(), 1);
-        assert_eq!(resolved[0].name, "ALWAYS");
+
+        let started = Instant::now();
+        let errors = Resolver::new(&config, "local")
+            .fail_fast(true)
+            .resolve()
+            .unwrap_err()
+            .errors;
+        assert!(started.elapsed() < Duration::from_secs(4));
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(
+            errors[0].kind,
+            ResolveErrorKind::CmdNonZero {
+                exit_code: Some(3),
+                ..
+            }
+        ));
+        assert_eq!(errors[1].variable, "SLOW");
+        assert!(matches!(errors[1].kind, ResolveErrorKind::Cancelled { .. }));
     }
 
-    // --- Override tests ---
+    fn flaky(dir: &std::path::Path, fail_runs: u32, retry: RetryOptions) -> Source {
+        let counter = dir.join("runs");
+        let script = format!(
+            "echo x >> '{c}'; n=$(wc -l < '{c}'); \
+             if [ $n -le {fail_runs} ]; then echo \"503 run $n\" >&2; exit 3; fi; echo ok",
+            c = counter.display()
+        );
+        Source::Sh(ShSource::Options(ShOptions {
+            script,
+            shell: None,
+            strict: None,
+            needs: vec![],
+            cache: None,
+            retry: Some(retry),
+            concurrency_group: None,
+        }))
+    }
 
-    fn var_with_overrides(
-        default: Option<Source>,
-        envs: BTreeMap<String, Source>,
-        overrides: BTreeMap<String, Override>,
-    ) -> crate::config::Variable {
-        crate::config::Variable {
-            description: None,
-            tags: vec![],
-            default,
-            envs,
-            overrides,
+    fn retry_policy(attempts: u32) -> RetryOptions {
+        RetryOptions {
+            attempts,
+            backoff: HumanDuration(std::time::Duration::from_millis(1)),
+            on_exit_codes: vec![],
+            on_stderr: vec![],
         }
     }
 
-    #[test]
-    fn test_override_env_specific() {
-        let config = Config {
-            variables: BTreeMap::from([(
-                "DB_HOST".to_owned(),
-                var_with_overrides(
-                    Some(literal("localhost")),
-                    BTreeMap::from([("prod".to_owned(), literal("172.10.0.1"))]),
-                    BTreeMap::from([(
-                        "read-replica".to_owned(),
-                        Override {
-                            default: None,
-                            envs: BTreeMap::from([("prod".to_owned(), literal("172.10.0.2"))]),
-                        },
-                    )]),
-                ),
-            )]),
-        };
-        let resolved = resolve(&config, "prod", &[], &["read-replica".to_owned()]).unwrap();
-        assert_eq!(resolved[0].value, "172.10.0.2");
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("envoke-retry-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_override_default() {
-        let config = Config {
-            variables: BTreeMap::from([(
-                "DB_HOST".to_owned(),
-                var_with_overrides(
-                    Some(literal("localhost")),
-                    BTreeMap::new(),
-                    BTreeMap::from([(
-                        "read-replica".to_owned(),
-                        Override {
-                            default: Some(literal("localhost-ro")),
-                            envs: BTreeMap::new(),
-                        },
-                    )]),
-                ),
-            )]),
-        };
-        let resolved = resolve(&config, "any-env", &[], &["read-replica".to_owned()]).unwrap();
-        assert_eq!(resolved[0].value, "localhost-ro");
+    fn test_retry_succeeds_after_failures() {
+        let dir = temp_dir("succeeds");
+        let config = single(flaky(&dir, 2, retry_policy(3)));
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        assert_eq!(resolved[0].value, "ok");
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_override_fallback_to_base_env() {
-        // Override is defined but has no entry for this env, falls to base env.
-        let config = Config {
-            variables: BTreeMap::from([(
-                "DB_HOST".to_owned(),
-                var_with_overrides(
-                    Some(literal("localhost")),
-                    BTreeMap::from([("staging".to_owned(), literal("staging-host"))]),
-                    BTreeMap::from([(
-                        "read-replica".to_owned(),
-                        Override {
-                            default: None,
-                            envs: BTreeMap::from([("prod".to_owned(), literal("prod-ro"))]),
-                        },
-                    )]),
-                ),
-            )]),
+    fn test_retry_exhausted_reports_history() {
+        let dir = temp_dir("exhausted");
+        let config = single(flaky(&dir, 5, retry_policy(3)));
+        let err = resolve(&config, "local", &[], &[]).unwrap_err();
+        let ResolveErrorKind::RetriesExhausted { history, last } = &err[0].kind else {
+            panic!("expected RetriesExhausted, got {:?}", err[0].kind);
         };
-        let resolved = resolve(&config, "staging", &[], &["read-replica".to_owned()]).unwrap();
-        assert_eq!(resolved[0].value, "staging-host");
-    }
-
-    #[test]
-    fn test_override_fallback_to_base_default() {
-        // Override defined but no source at any level except base default.
-        let config = Config {
-            variables: BTreeMap::from([(
-                "DB_HOST".to_owned(),
-                var_with_overrides(
-                    Some(literal("fallback")),
-                    BTreeMap::new(),
-                    BTreeMap::from([(
-                        "read-replica".to_owned(),
-                        Override {
-                            default: None,
-                            envs: BTreeMap::from([("prod".to_owned(), literal("prod-ro"))]),
-                        },
-                    )]),
-                ),
-            )]),
+        assert_eq!(history.len(), 3);
+        assert!(history[0].contains("503 run 1"));
+        assert!(history[2].contains("503 run 3"));
+        assert!(matches!(
+            **last,
+            ResolveErrorKind::CmdNonZero {
+                exit_code: Some(3),
+                ..
+            }
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_retry_filters() {
+        let dir = temp_dir("filters");
+        // Exit code 3 is not in the list: fail on the first run.
+        let policy = RetryOptions {
+            on_exit_codes: vec![75],
+            ..retry_policy(3)
         };
-        let resolved = resolve(&config, "staging", &[], &["read-replica".to_owned()]).unwrap();
-        assert_eq!(resolved[0].value, "fallback");
+        let err = resolve(&single(flaky(&dir, 1, policy)), "local", &[], &[]).unwrap_err();
+        assert!(matches!(err[0].kind, ResolveErrorKind::CmdNonZero { .. }));
+
+        // A matching stderr pattern is enough even if the code does not match.
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        let policy = RetryOptions {
+            on_exit_codes: vec![75],
+            on_stderr: vec![StderrPattern::new("^50[0-9]")],
+            ..retry_policy(3)
+        };
+        let resolved = resolve(&single(flaky(&dir, 1, policy)), "local", &[], &[]).unwrap();
+        assert_eq!(resolved[0].value, "ok");
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_override_full_chain() {
-        // Verify all four fallback levels with a single variable.
-        let config = Config {
-            variables: BTreeMap::from([(
-                "VAR".to_owned(),
-                var_with_overrides(
-                    Some(literal("base-default")),
-                    BTreeMap::from([("prod".to_owned(), literal("base-prod"))]),
-                    BTreeMap::from([(
-                        "alt".to_owned(),
-                        Override {
-                            default: Some(literal("ovr-default")),
-                            envs: BTreeMap::from([("prod".to_owned(), literal("ovr-prod"))]),
-                        },
-                    )]),
-                ),
+    fn test_retry_rejects_zero_attempts() {
+        let source = cmd_with(vec!["true"], |o| o.retry = Some(retry_policy(0)));
+        let err = resolve(&single(source), "local", &[], &[]).unwrap_err();
+        assert!(matches!(
+            err[0].kind,
+            ResolveErrorKind::InvalidSource { .. }
+        ));
+    }
+
+    fn from_bundle(bundle: &str, key: Option<&str>) -> Source {
+        Source::FromBundle(BundleRef {
+            bundle: bundle.to_owned(),
+            key: key.map(ToOwned::to_owned),
+        })
+    }
+
+    fn bundle_config(
+        format: BundleFormat,
+        source: Source,
+        variables: Vec<(&str, Source)>,
+    ) -> Config {
+        Config {
+            bundles: BTreeMap::from([(
+                "secrets".to_owned(),
+                crate::config::Bundle {
+                    format,
+                    default: Some(source),
+                    envs: BTreeMap::new(),
+                },
             )]),
-        };
+            variables: variables
+                .into_iter()
+                .map(|(name, source)| (name.to_owned(), var_with_default(source, BTreeMap::new())))
+                .collect(),
+            ..Default::default()
+        }
+    }
 
-        // Level 1: override env
-        let r = resolve(&config, "prod", &[], &["alt".to_owned()]).unwrap();
-        assert_eq!(r[0].value, "ovr-prod");
+    #[cfg(unix)]
+    #[test]
+    fn test_bundle_runs_once_for_many_keys() {
+        let dir = temp_dir("bundle-once");
+        let counter = dir.join("runs");
+        let script = format!(
+            "echo x >> '{c}'; echo USER=app; echo \"PASS='s3cret'\"",
+            c = counter.display()
+        );
+        let config = bundle_config(
+            BundleFormat::Dotenv,
+            sh(&script),
+            vec![
+                ("USER", from_bundle("secrets", None)),
+                ("DB_PASS", from_bundle("secrets", Some("PASS"))),
+                ("URL", template("{{ USER }}:{{ DB_PASS }}")),
+            ],
+        );
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        let values: Vec<(&str, &str)> = resolved
+            .iter()
+            .map(|r| (r.name.as_str(), r.value.as_str()))
+            .collect();
+        assert_eq!(
+            values,
+            [
+                ("DB_PASS", "s3cret"),
+                ("URL", "app:s3cret"),
+                ("USER", "app")
+            ]
+        );
+        let runs = std::fs::read_to_string(&counter).unwrap();
+        assert_eq!(runs.lines().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 
-        // Level 2: override default (env not in override)
-        let r = resolve(&config, "staging", &[], &["alt".to_owned()]).unwrap();
-        assert_eq!(r[0].value, "ovr-default");
+    #[test]
+    fn test_bundle_json_and_errors() {
+        let json = literal(r#"{"token": "abc", "port": 8080}"#);
+        let config = bundle_config(
+            BundleFormat::Json,
+            json.clone(),
+            vec![("PORT", from_bundle("secrets", Some("port")))],
+        );
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        assert_eq!(resolved[0].value, "8080");
 
-        // Without override active, base env
-        let r = resolve(&config, "prod", &[], &[]).unwrap();
-        assert_eq!(r[0].value, "base-prod");
+        let config = bundle_config(
+            BundleFormat::Json,
+            json,
+            vec![("MISSING", from_bundle("secrets", None))],
+        );
+        let err = resolve(&config, "local", &[], &[]).unwrap_err();
+        assert!(matches!(
+            &err[0].kind,
+            ResolveErrorKind::MissingBundleKey { key, .. } if key == "MISSING"
+        ));
 
-        // Without override active, base default
-        let r = resolve(&config, "staging", &[], &[]).unwrap();
-        assert_eq!(r[0].value, "base-default");
+        let config = bundle_config(
+            BundleFormat::Json,
+            literal("not json"),
+            vec![("A", from_bundle("secrets", None))],
+        );
+        let err = resolve(&config, "local", &[], &[]).unwrap_err();
+        assert!(matches!(err[0].kind, ResolveErrorKind::BundleParse { .. }));
+        assert_eq!(err[0].variable, "bundle:secrets");
+
+        let config = bundle_config(
+            BundleFormat::Json,
+            literal("{}"),
+            vec![("A", from_bundle("nope", None))],
+        );
+        let err = resolve(&config, "local", &[], &[]).unwrap_err();
+        assert!(matches!(
+            err[0].kind,
+            ResolveErrorKind::UnknownBundle { .. }
+        ));
     }
 
     #[test]
-    fn test_no_override_ignores_data() {
-        let config = Config {
-            variables: BTreeMap::from([(
-                "DB_HOST".to_owned(),
-                var_with_overrides(
-                    Some(literal("base")),
-                    BTreeMap::new(),
-                    BTreeMap::from([(
-                        "read-replica".to_owned(),
-                        Override {
-                            default: Some(literal("override-val")),
-                            envs: BTreeMap::new(),
-                        },
-                    )]),
-                ),
-            )]),
-        };
+    fn test_bundle_source_can_use_templates() {
+        let config = bundle_config(
+            BundleFormat::Dotenv,
+            template("HOST={{ REGION }}.db.internal"),
+            vec![
+                ("REGION", literal("eu")),
+                ("HOST", from_bundle("secrets", None)),
+            ],
+        );
         let resolved = resolve(&config, "local", &[], &[]).unwrap();
-        assert_eq!(resolved[0].value, "base");
+        let host = resolved.iter().find(|r| r.name == "HOST").unwrap();
+        assert_eq!(host.value, "eu.db.internal");
     }
 
     #[test]
-    fn test_override_with_skip() {
-        let config = Config {
-            variables: BTreeMap::from([(
-                "VAR".to_owned(),
-                var_with_overrides(
-                    Some(literal("present")),
-                    BTreeMap::new(),
-                    BTreeMap::from([(
-                        "disable".to_owned(),
-                        Override {
-                            default: Some(skip()),
-                            envs: BTreeMap::new(),
-                        },
-                    )]),
-                ),
-            )]),
-        };
-        let resolved = resolve(&config, "local", &[], &["disable".to_owned()]).unwrap();
-        assert!(resolved.is_empty());
+    fn test_unreferenced_bundle_is_not_run() {
+        let config = bundle_config(
+            BundleFormat::Json,
+            cmd(vec!["false"]),
+            vec![("A", literal("a"))],
+        );
+        assert_eq!(resolve(&config, "local", &[], &[]).unwrap().len(), 1);
+    }
+
+    fn per_item(for_each: crate::config::ForEach, default: Source) -> crate::config::Variable {
+        crate::config::Variable {
+            default: Some(default),
+            for_each: Some(for_each),
+            ..Default::default()
+        }
+    }
+
+    fn items(items: &[&str]) -> crate::config::ForEach {
+        crate::config::ForEach::Items(items.iter().map(|s| (*s).to_owned()).collect())
     }
 
     #[test]
-    fn test_override_with_template() {
+    fn test_for_each_expands_names_and_sources() {
         let config = Config {
             variables: BTreeMap::from([
                 (
-                    "HOST".to_owned(),
-                    var(BTreeMap::from([("local".to_owned(), literal("db.local"))])),
+                    "DOMAIN".to_owned(),
+                    var_with_default(literal("example.com"), BTreeMap::new()),
                 ),
                 (
-                    "CONN".to_owned(),
-                    var_with_overrides(
-                        Some(template("postgres://{{ HOST }}/main")),
-                        BTreeMap::new(),
-                        BTreeMap::from([(
-                            "alt".to_owned(),
-                            Override {
-                                default: Some(template("postgres://{{ HOST }}/replica")),
-                                envs: BTreeMap::new(),
-                            },
-                        )]),
+                    "TENANT_{{ item | upper }}_URL".to_owned(),
+                    per_item(
+                        items(&["acme", "globex"]),
+                        template("https://{{ item }}.{{ DOMAIN }}"),
                     ),
                 ),
+                (
+                    "TENANT_{{ item | upper }}_ID".to_owned(),
+                    per_item(items(&["acme"]), literal("id-{{ item }}")),
+                ),
             ]),
+            ..Default::default()
         };
-        let resolved = resolve(&config, "local", &[], &["alt".to_owned()]).unwrap();
-        let conn = resolved.iter().find(|r| r.name == "CONN").unwrap();
-        assert_eq!(conn.value, "postgres://db.local/replica");
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        let values: Vec<(&str, &str)> = resolved
+            .iter()
+            .map(|r| (r.name.as_str(), r.value.as_str()))
+            .collect();
+        assert_eq!(
+            values,
+            [
+                ("DOMAIN", "example.com"),
+                ("TENANT_ACME_ID", "id-acme"),
+                ("TENANT_ACME_URL", "https://acme.example.com"),
+                ("TENANT_GLOBEX_URL", "https://globex.example.com"),
+            ]
+        );
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_override_with_tags() {
-        // Tags and overrides are orthogonal.
+    fn test_for_each_items_from_source() {
+        let config = single_named(
+            "{{ item }}_HOME",
+            per_item(
+                crate::config::ForEach::Source(sh("printf 'A\\n\\nB\\n'")),
+                sh("echo /home/{{ item | lower }}"),
+            ),
+        );
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        let values: Vec<(&str, &str)> = resolved
+            .iter()
+            .map(|r| (r.name.as_str(), r.value.as_str()))
+            .collect();
+        assert_eq!(values, [("A_HOME", "/home/a"), ("B_HOME", "/home/b")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_for_each_items_cannot_inject_shell_or_template_syntax() {
+        let dir = std::env::temp_dir().join(format!("envoke-for-each-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pwned = dir.join("pwned");
+        let item = format!("a; touch {} $(touch {0}) '\"", pwned.display());
         let config = Config {
             variables: BTreeMap::from([
                 (
-                    "TAGGED".to_owned(),
-                    crate::config::Variable {
-                        description: None,
-                        tags: vec!["vault".to_owned()],
-                        default: Some(literal("base")),
-                        envs: BTreeMap::new(),
-                        overrides: BTreeMap::from([(
-                            "alt".to_owned(),
-                            Override {
-                                default: Some(literal("alt-val")),
-                                envs: BTreeMap::new(),
-                            },
-                        )]),
-                    },
+                    "SCRIPT_{{ item | length }}".to_owned(),
+                    per_item(items(&[&item]), sh("printf %s {{ item }}")),
                 ),
                 (
-                    "ALWAYS".to_owned(),
-                    var(BTreeMap::from([("local".to_owned(), literal("yes"))])),
+                    "SCRIPT_DOUBLE_{{ item | length }}".to_owned(),
+                    per_item(items(&[&item]), sh("printf %s \"<{{ item }}>\"")),
                 ),
-            ]),
-        };
-        // Tag not matched: TAGGED excluded, override irrelevant.
-        let resolved = resolve(&config, "local", &[], &["alt".to_owned()]).unwrap();
-        assert_eq!(resolved.len(), 1);
-        assert_eq!(resolved[0].name, "ALWAYS");
-
-        // Tag matched and override active: override value used.
-        let resolved =
-            resolve(&config, "local", &["vault".to_owned()], &["alt".to_owned()]).unwrap();
-        let tagged = resolved.iter().find(|r| r.name == "TAGGED").unwrap();
-        assert_eq!(tagged.value, "alt-val");
-    }
-
-    #[test]
-    fn test_override_no_config_error() {
-        // No source at any level → NoConfig.
-        let config = Config {
-            variables: BTreeMap::from([(
-                "VAR".to_owned(),
-                var_with_overrides(
-                    None,
-                    BTreeMap::new(),
-                    BTreeMap::from([(
-                        "alt".to_owned(),
-                        Override {
-                            default: None,
-                            envs: BTreeMap::new(),
-                        },
-                    )]),
+                (
+                    "SCRIPT_SINGLE_{{ item | length }}".to_owned(),
+                    per_item(items(&[&item]), sh("printf %s '<{{- item -}}>'")),
                 ),
-            )]),
+                (
+                    "TEMPLATE_{{ item | length }}".to_owned(),
+                    per_item(
+                        items(&["{{ x }}{# %}{% endwith %}\"", "b"]),
+                        template("<{{ item }}>"),
+                    ),
+                ),
+            ]),
+            ..Default::default()
         };
-        let err = resolve(&config, "local", &[], &["alt".to_owned()]).unwrap_err();
-        assert!(
-            err.iter()
-                .any(|e| matches!(e.kind, ResolveErrorKind::NoConfig))
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        let values: Vec<&str> = resolved.iter().map(|r| r.value.as_str()).collect();
+        let quoted = format!("<{item}>");
+        assert_eq!(
+            values,
+            [
+                item.as_str(),
+                &quoted,
+                &quoted,
+                "<b>",
+                "<{{ x }}{# %}{% endwith %}\">"
+            ]
         );
+        assert!(!pwned.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_override_undefined_for_variable() {
-        // Requested override not defined on this variable, falls to base.
-        let config = Config {
-            variables: BTreeMap::from([(
-                "VAR".to_owned(),
-                var_with_default(literal("base"), BTreeMap::new()),
-            )]),
-        };
-        let resolved = resolve(&config, "any", &[], &["nonexistent".to_owned()]).unwrap();
-        assert_eq!(resolved[0].value, "base");
-    }
-
-    #[test]
-    fn test_multiple_overrides_disjoint() {
+    fn test_for_each_templates_needs_and_cwd() {
+        let dir = std::env::temp_dir().join(format!("envoke-for-each-cwd-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::create_dir_all(dir.join("b")).unwrap();
+        let needs = vec!["TOKEN_{{ item | upper }}".to_owned()];
         let config = Config {
             variables: BTreeMap::from([
                 (
-                    "DB_HOST".to_owned(),
-                    var_with_overrides(
-                        Some(literal("main-db")),
-                        BTreeMap::new(),
-                        BTreeMap::from([(
-                            "read-replica".to_owned(),
-                            Override {
-                                default: Some(literal("replica-db")),
-                                envs: BTreeMap::new(),
-                            },
-                        )]),
+                    "TOKEN_A".to_owned(),
+                    var_with_default(literal("ta"), BTreeMap::new()),
+                ),
+                (
+                    "TOKEN_B".to_owned(),
+                    var_with_default(literal("tb"), BTreeMap::new()),
+                ),
+                (
+                    "SH_{{ item | upper }}".to_owned(),
+                    per_item(
+                        items(&["a", "b"]),
+                        Source::Sh(ShSource::Options(ShOptions {
+                            script: "printenv TOKEN_{{ item | upper }}".to_owned(),
+                            shell: None,
+                            strict: None,
+                            needs: needs.clone(),
+                            cache: None,
+                            retry: None,
+                            concurrency_group: None,
+                        })),
                     ),
                 ),
                 (
-                    "CACHE".to_owned(),
-                    var_with_overrides(
-                        Some(literal("lru")),
-                        BTreeMap::new(),
-                        BTreeMap::from([(
-                            "aggressive".to_owned(),
-                            Override {
-                                default: Some(literal("lfu")),
-                                envs: BTreeMap::new(),
-                            },
-                        )]),
+                    "CMD_{{ item | upper }}".to_owned(),
+                    per_item(
+                        items(&["a", "b"]),
+                        cmd_with(vec!["sh", "-c", "basename \"$PWD\""], |options| {
+                            options.cwd = Some(dir.join("{{ item }}"));
+                            options.needs = needs;
+                        }),
                     ),
                 ),
             ]),
-        };
-        let resolved = resolve(
-            &config,
-            "prod",
-            &[],
-            &["read-replica".to_owned(), "aggressive".to_owned()],
-        )
-        .unwrap();
-        let db = resolved.iter().find(|r| r.name == "DB_HOST").unwrap();
-        assert_eq!(db.value, "replica-db");
-        let cache = resolved.iter().find(|r| r.name == "CACHE").unwrap();
-        assert_eq!(cache.value, "lfu");
+            ..Default::default()
+        };
+        let resolved = resolve(&config, "local", &[], &[]).unwrap();
+        let values: Vec<(&str, &str)> = resolved
+            .iter()
+            .map(|r| (r.name.as_str(), r.value.as_str()))
+            .collect();
+        assert_eq!(
+            values,
+            [
+                ("CMD_A", "a"),
+                ("CMD_B", "b"),
+                ("SH_A", "ta"),
+                ("SH_B", "tb"),
+                ("TOKEN_A", "ta"),
+                ("TOKEN_B", "tb"),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_resolve_template_shell_escape() {
+    fn test_for_each_conflicts_and_errors() {
         let config = Config {
             variables: BTreeMap::from([
                 (
-                    "PASS".to_owned(),
-                    var(BTreeMap::from([(
-                        "local".to_owned(),
-                        literal("it's a secret"),
-                    )])),
+                    "A_URL".to_owned(),
+                    var_with_default(literal("x"), BTreeMap::new()),
                 ),
                 (
-                    "ESCAPED".to_owned(),
-                    var(BTreeMap::from([(
-                        "local".to_owned(),
-                        template("{{ PASS | shell_escape }}"),
-                    )])),
+                    "{{ item }}_URL".to_owned(),
+                    per_item(items(&["A", "B"]), literal("y")),
                 ),
             ]),
+            ..Default::default()
         };
-        let resolved = resolve(&config, "local", &[], &[]).unwrap();
-        let escaped = resolved.iter().find(|r| r.name == "ESCAPED").unwrap();
-        assert_eq!(escaped.value, "it'\\''s a secret");
+        let err = resolve(&config, "local", &[], &[]).unwrap_err();
+        assert!(matches!(
+            &err[0].kind,
+            ResolveErrorKind::ForEachConflict { name, other } if name == "A_URL" && other == "A_URL"
+        ));
+
+        let typo = single_named("{{ itme }}_X", per_item(items(&["a"]), literal("y")));
+        let err = resolve(&typo, "local", &[], &[]).unwrap_err();
+        assert!(matches!(err[0].kind, ResolveErrorKind::ForEach { .. }));
+
+        let needs_var = single_named(
+            "{{ item }}",
+            per_item(
+                crate::config::ForEach::Source(template("{{ OTHER }}")),
+                literal("y"),
+            ),
+        );
+        let err = resolve(&needs_var, "local", &[], &[]).unwrap_err();
+        assert!(matches!(
+            err[0].kind,
+            ResolveErrorKind::InvalidSource { .. }
+        ));
     }
 
     #[test]
-    fn test_resolve_template_builtin_filter() {
+    fn test_for_each_source_skipped_when_tag_filtered() {
+        let mut variable = per_item(
+            crate::config::ForEach::Source(cmd(vec!["false"])),
+            literal("y"),
+        );
+        variable.tags = vec!["tenants".to_owned()];
+        let config = single_named("{{ item }}", variable);
+        assert!(resolve(&config, "local", &[], &[]).unwrap().is_empty());
+    }
+
+    fn single_named(name: &str, variable: crate::config::Variable) -> Config {
+        Config {
+            variables: BTreeMap::from([(name.to_owned(), variable)]),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_slow_source_does_not_block_unrelated_chain() {
+        let dir = temp_dir("dag");
+        let marker = dir.join("marker");
+        // SLOW waits for a marker written by B, which sits behind a command
+        // and a template. A phase- or stage-based resolver would only start B
+        // after SLOW finished.
+        let slow = sh(&format!(
+            "for i in $(seq 50); do [ -f '{m}' ] && {{ echo saw; exit; }}; sleep 0.1; done; echo missing",
+            m = marker.display()
+        ));
+        let b = Source::Sh(ShSource::Options(ShOptions {
+            script: format!("touch '{}'; echo \"$T-b\"", marker.display()),
+            shell: None,
+            strict: None,
+            needs: vec!["T".to_owned()],
+            cache: None,
+            retry: None,
+            concurrency_group: None,
+        }));
         let config = Config {
             variables: BTreeMap::from([
+                ("SLOW".to_owned(), var_with_default(slow, BTreeMap::new())),
                 (
-                    "NAME".to_owned(),
-                    var(BTreeMap::from([("local".to_owned(), literal("hello"))])),
+                    "A".to_owned(),
+                    var_with_default(cmd(vec!["echo", "a"]), BTreeMap::new()),
                 ),
                 (
-                    "UPPER".to_owned(),
-                    var(BTreeMap::from([(
-                        "local".to_owned(),
-                        template("{{ NAME | upper }}"),
-                    )])),
+                    "T".to_owned(),
+                    var_with_default(template("{{ A }}-t"), BTreeMap::new()),
                 ),
+                ("B".to_owned(), var_with_default(b, BTreeMap::new())),
             ]),
+            ..Default::default()
         };
         let resolved = resolve(&config, "local", &[], &[]).unwrap();
-        let upper = resolved.iter().find(|r| r.name == "UPPER").unwrap();
-        assert_eq!(upper.value, "HELLO");
+        let values: Vec<(&str, &str)> = resolved
+            .iter()
+            .map(|r| (r.name.as_str(), r.value.as_str()))
+            .collect();
+        assert_eq!(
+            values,
+            [("A", "a"), ("B", "a-t-b"), ("SLOW", "saw"), ("T", "a-t")]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_multiple_overrides_conflict() {
+    fn test_resolver_builder() {
         let config = Config {
-            variables: BTreeMap::from([(
-                "VAR".to_owned(),
-                var_with_overrides(
-                    Some(literal("base")),
-                    BTreeMap::new(),
-                    BTreeMap::from([
-                        (
-                            "a".to_owned(),
-                            Override {
-                                default: Some(literal("a-val")),
-                                envs: BTreeMap::new(),
-                            },
-                        ),
-                        (
-                            "b".to_owned(),
-                            Override {
-                                default: Some(literal("b-val")),
-                                envs: BTreeMap::new(),
-                            },
-                        ),
-                    ]),
+            variables: BTreeMap::from([
+                (
+                    "PLAIN".to_owned(),
+                    var_with_default(literal("p"), BTreeMap::new()),
                 ),
-            )]),
+                (
+                    "TAGGED".to_owned(),
+                    crate::config::Variable {
+                        tags: vec!["vault".to_owned()],
+                        default: Some(template("{{ meta.environment }}@{{ meta.timestamp }}")),
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+        let names = |resolved: &[Resolved]| -> Vec<String> {
+            resolved.iter().map(|r| r.name.clone()).collect()
         };
-        let err = resolve(&config, "prod", &[], &["a".to_owned(), "b".to_owned()]).unwrap_err();
-        assert!(err.iter().any(|e| matches!(
-            &e.kind,
-            ResolveErrorKind::ConflictingOverrides { names }
-            if names.len() == 2
-        )));
-    }
 
-    // --- Meta object tests ---
+        let untagged = Resolver::new(&config, "prod").resolve().unwrap();
+        assert_eq!(names(&untagged), ["PLAIN"]);
 
-    #[test]
-    fn test_template_meta_environment() {
-        let config = Config {
-            variables: BTreeMap::from([(
-                "URL".to_owned(),
-                var(BTreeMap::from([(
-                    "staging".to_owned(),
-                    template("https://{{ meta.environment }}.example.com"),
-                )])),
-            )]),
-        };
-        let resolved = resolve(&config, "staging", &[], &[]).unwrap();
-        assert_eq!(resolved[0].value, "https://staging.example.com");
+        let tagged = Resolver::new(&config, "prod")
+            .tags(["vault"])
+            .timestamp(TS)
+            .parallel(false)
+            .resolve()
+            .unwrap();
+        assert_eq!(names(&tagged), ["PLAIN", "TAGGED"]);
+        assert_eq!(tagged[1].value, format!("prod@{TS}"));
+
+        let all = Resolver::new(&config, "prod").all_tags().resolve().unwrap();
+        assert_eq!(names(&all), ["PLAIN", "TAGGED"]);
+
+        let err = Resolver::new(&config, "prod")
+            .overrides(["a"])
+            .resolve()
+            .map(|_| ());
+        assert!(err.is_ok(), "unknown overrides only warn");
     }
 
     #[test]
-    fn test_template_meta_with_variable_refs() {
+    fn test_resolve_timed_reports_every_source() {
         let config = Config {
             variables: BTreeMap::from([
                 (
-                    "HOST".to_owned(),
-                    var(BTreeMap::from([("prod".to_owned(), literal("db.prod"))])),
+                    "SLOW".to_owned(),
+                    var_with_default(sh("sleep 0.1; echo slow"), BTreeMap::new()),
                 ),
                 (
-                    "CONN".to_owned(),
-                    var(BTreeMap::from([(
-                        "prod".to_owned(),
-                        template("postgres://{{ HOST }}/{{ meta.environment }}"),
-                    )])),
+                    "FAST".to_owned(),
+                    var_with_default(sh("echo fast"), BTreeMap::new()),
+                ),
+                (
+                    "BROKEN".to_owned(),
+                    var_with_default(sh("exit 3"), BTreeMap::new()),
+                ),
+                (
+                    "T".to_owned(),
+                    var_with_default(template("{{ FAST }}"), BTreeMap::new()),
                 ),
             ]),
+            ..Default::default()
         };
-        let resolved = resolve(&config, "prod", &[], &[]).unwrap();
-        let conn = resolved.iter().find(|r| r.name == "CONN").unwrap();
-        assert_eq!(conn.value, "postgres://db.prod/prod");
+        let (result, mut timings) = Resolver::new(&config, "local").resolve_timed();
+        assert!(result.is_err());
+        crate::timings::sort(&mut timings);
+        assert_eq!(timings[0].name, "SLOW");
+        assert!(timings[0].run >= Duration::from_millis(100));
+        let broken = timings.iter().find(|t| t.name == "BROKEN").unwrap();
+        assert!(!broken.ok);
+        assert_eq!(broken.source, "sh");
     }
 
     #[test]
-    fn test_template_meta_tags() {
+    fn test_resolve_partial_keeps_going() {
         let config = Config {
-            variables: BTreeMap::from([(
-                "TAGS".to_owned(),
-                var_with_default(template("{{ meta.tags | join(\",\") }}"), BTreeMap::new()),
-            )]),
+            variables: BTreeMap::from([
+                (
+                    "OK".to_owned(),
+                    var_with_default(literal("ok"), BTreeMap::new()),
+                ),
+                (
+                    "BROKEN".to_owned(),
+                    var_with_default(sh("exit 3"), BTreeMap::new()),
+                ),
+                (
+                    "SLOW".to_owned(),
+                    var_with_default(sh("sleep 0.1; echo slow"), BTreeMap::new()),
+                ),
+                (
+                    "USES_BROKEN".to_owned(),
+                    var_with_default(template("{{ BROKEN }}"), BTreeMap::new()),
+                ),
+                (
+                    "USES_USES".to_owned(),
+                    var_with_default(template("{{ USES_BROKEN }}"), BTreeMap::new()),
+                ),
+                ("MISSING".to_owned(), var(BTreeMap::new())),
+                (
+                    "USES_MISSING".to_owned(),
+                    var_with_default(template("{{ MISSING }}!"), BTreeMap::new()),
+                ),
+            ]),
+            ..Default::default()
         };
-        let resolved = resolve(
-            &config,
-            "local",
-            &["vault".to_owned(), "oauth".to_owned()],
-            &[],
-        )
-        .unwrap();
-        assert_eq!(resolved[0].value, "vault,oauth");
+        for parallel in [true, false] {
+            let partial = Resolver::new(&config, "local")
+                .parallel(parallel)
+                .resolve_partial();
+            let names: Vec<&str> = partial.resolved.iter().map(|r| r.name.as_str()).collect();
+            assert_eq!(names, ["OK", "SLOW"], "parallel: {parallel}");
+            let failed: Vec<(&str, String)> = partial
+                .errors
+                .iter()
+                .map(|e| (e.variable.as_str(), e.kind.to_string()))
+                .collect();
+            assert_eq!(
+                failed,
+                [
+                    ("BROKEN", failed[0].1.clone()),
+                    (
+                        "MISSING",
+                        "no configuration for this environment".to_owned()
+                    ),
+                    (
+                        "USES_BROKEN",
+                        "depends on 'BROKEN', which failed to resolve".to_owned()
+                    ),
+                    (
+                        "USES_MISSING",
+                        "depends on 'MISSING', which failed to resolve".to_owned()
+                    ),
+                    (
+                        "USES_USES",
+                        "depends on 'USES_BROKEN', which failed to resolve".to_owned()
+                    ),
+                ]
+            );
+        }
+
+        // Without keeping going, nothing is returned.
+        let err = Resolver::new(&config, "local").resolve().unwrap_err();
+        assert_eq!(err.errors[0].variable, "MISSING");
     }
 
-    #[test]
-    fn test_template_meta_overrides() {
-        let config = Config {
-            variables: BTreeMap::from([(
-                "OVERRIDES".to_owned(),
-                var_with_default(
-                    template("{{ meta.overrides | join(\",\") }}"),
-                    BTreeMap::new(),
+    fn explain_config() -> Config {
+        Config {
+            variables: BTreeMap::from([
+                (
+                    "HOST".to_owned(),
+                    var_with_default(literal("db"), BTreeMap::new()),
                 ),
-            )]),
-        };
-        let resolved = resolve(
-            &config,
-            "local",
-            &[],
-            &["alt".to_owned(), "fast".to_owned()],
-        )
-        .unwrap();
-        assert_eq!(resolved[0].value, "alt,fast");
+                (
+                    "URL".to_owned(),
+                    crate::config::Variable {
+                        tags: vec!["db".to_owned()],
+                        default: Some(template("pg://{{ HOST }}")),
+                        overrides: BTreeMap::from([(
+                            "local".to_owned(),
+                            Override {
+                                envs: BTreeMap::from([(
+                                    "dev".to_owned(),
+                                    literal("pg://localhost"),
+                                )]),
+                                default: None,
+                            },
+                        )]),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "BROKEN".to_owned(),
+                    var_with_default(sh("exit 1"), BTreeMap::new()),
+                ),
+            ]),
+            ..Default::default()
+        }
     }
 
     #[test]
-    fn test_template_meta_timestamp() {
-        let config = Config {
-            variables: BTreeMap::from([(
-                "TS".to_owned(),
-                var_with_default(template("{{ meta.timestamp }}"), BTreeMap::new()),
-            )]),
-        };
-        let resolved = resolve(&config, "local", &[], &[]).unwrap();
-        assert_eq!(resolved[0].value, TS);
-    }
+    fn test_explain_selection() {
+        let config = explain_config();
 
-    fn cmd_saturation_config(count: usize) -> (Config, Vec<(String, String)>) {
-        assert!(count > MAX_EXTERNAL_JOBS, "fixture must exceed pool size");
-        let mut variables = BTreeMap::new();
-        let mut expected = Vec::with_capacity(count);
-        for i in 0..count {
-            let name = format!("VAR_{i:02}");
-            let value = format!("value-{i}");
-            variables.insert(
-                name.clone(),
-                var(BTreeMap::from([(
-                    "local".to_owned(),
-                    cmd(vec!["echo", &value]),
-                )])),
-            );
-            expected.push((name, value));
-        }
-        (Config { variables }, expected)
+        let base = Resolver::new(&config, "dev").explain("URL", false).unwrap();
+        assert!(!base.tags.included);
+        assert!(matches!(
+            base.selection,
+            Selection::Selected {
+                origin: Origin::Default,
+                ..
+            }
+        ));
+        assert_eq!(base.dependencies, ["HOST"]);
+        assert_eq!(base.overrides.len(), 1);
+        assert!(!base.overrides[0].active);
+        assert!(base.value.is_none());
+
+        let overridden = Resolver::new(&config, "dev")
+            .tags(["db"])
+            .overrides(["local"])
+            .explain("URL", false)
+            .unwrap();
+        assert!(overridden.tags.included);
+        let Selection::Selected { origin, .. } = &overridden.selection else {
+            panic!("expected a selected source");
+        };
+        assert_eq!(origin.to_string(), "overrides.local.envs.dev");
+        assert!(overridden.dependencies.is_empty());
+        assert!(
+            overridden
+                .to_string()
+                .contains("override 'local' is active")
+        );
     }
 
     #[test]
-    fn test_parallel_queue_saturation() {
-        let (config, expected) = cmd_saturation_config(MAX_EXTERNAL_JOBS * 3);
-        let resolved = resolve(&config, "local", &[], &[]).unwrap();
-        let got: Vec<(String, String)> = resolved.into_iter().map(|r| (r.name, r.value)).collect();
-        assert_eq!(got, expected);
+    fn test_explain_dependencies_skip_template_globals() {
+        let mut config = explain_config();
+        config.variables.insert(
+            "DSN".to_owned(),
+            var_with_default(
+                template("{{ URL }}/{{ meta.environment }}?t={{ now() }}"),
+                BTreeMap::new(),
+            ),
+        );
+        let explanation = Resolver::new(&config, "dev").explain("DSN", false).unwrap();
+        assert_eq!(explanation.dependencies, ["URL"]);
     }
 
     #[test]
-    fn test_parallel_mixed_success_and_error() {
-        let mut variables = BTreeMap::new();
-        for name in ["OK_A", "OK_B", "OK_C"] {
-            variables.insert(
-                name.to_owned(),
-                var(BTreeMap::from([(
-                    "local".to_owned(),
-                    cmd(vec!["echo", "ok"]),
-                )])),
-            );
-        }
-        for name in ["FAIL_X", "FAIL_Y"] {
-            variables.insert(
-                name.to_owned(),
-                var(BTreeMap::from([("local".to_owned(), cmd(vec!["false"]))])),
-            );
-        }
-        let config = Config { variables };
-        let errors = resolve(&config, "local", &[], &[]).unwrap_err();
-        let mut failing: Vec<String> = errors.into_iter().map(|e| e.variable).collect();
-        failing.sort();
-        assert_eq!(failing, vec!["FAIL_X".to_owned(), "FAIL_Y".to_owned()]);
+    fn test_explain_show_value_ignores_broken_siblings() {
+        let mut config = explain_config();
+        config.variables.extend([
+            (
+                "PROD_ONLY".to_owned(),
+                var(BTreeMap::from([("prod".to_owned(), literal("p"))])),
+            ),
+            (
+                "TYPO".to_owned(),
+                var_with_default(template("{{ NOPE }}"), BTreeMap::new()),
+            ),
+            (
+                "USES_PROD_ONLY".to_owned(),
+                var_with_default(template("{{ PROD_ONLY }}"), BTreeMap::new()),
+            ),
+        ]);
+        let resolver = Resolver::new(&config, "dev").tags(["db"]);
+        let explanation = resolver.explain("URL", true).unwrap();
+        assert_eq!(explanation.value.as_deref(), Some("pg://db"));
+
+        let err = resolver.explain("USES_PROD_ONLY", true).unwrap_err();
+        let failures: Vec<(&str, &ResolveErrorKind)> = err
+            .errors
+            .iter()
+            .map(|e| (e.variable.as_str(), &e.kind))
+            .collect();
+        assert!(
+            matches!(
+                failures.as_slice(),
+                [
+                    ("USES_PROD_ONLY", ResolveErrorKind::DependencyFailed { .. }),
+                    ("PROD_ONLY", ResolveErrorKind::NoConfig),
+                ]
+            ),
+            "{failures:?}"
+        );
     }
 
     #[test]
-    fn test_sequential_resolves_same_values_as_parallel() {
-        let (config, expected) = cmd_saturation_config(MAX_EXTERNAL_JOBS * 3);
-        let resolved = resolve_all(&config, "local", &[], &[], TS, false).unwrap();
-        let got: Vec<(String, String)> = resolved.into_iter().map(|r| (r.name, r.value)).collect();
-        assert_eq!(got, expected);
+    fn test_explain_show_value_resolves_only_dependencies() {
+        let config = explain_config();
+        // BROKEN fails, but URL does not depend on it.
+        let explanation = Resolver::new(&config, "prod")
+            .tags(["db"])
+            .explain("URL", true)
+            .unwrap();
+        assert_eq!(explanation.value.as_deref(), Some("pg://db"));
+
+        let err = Resolver::new(&config, "prod")
+            .explain("MISSING", false)
+            .unwrap_err();
+        assert!(matches!(
+            err.errors[0].kind,
+            ResolveErrorKind::UnknownVariable
+        ));
     }
 }
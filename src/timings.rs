@@ -0,0 +1,131 @@
+//! Per-source timing of one resolution, as reported by `--timings`.
+
+use std::fmt::Write as _;
+use std::time::Duration;
+
+/// How long one source took to resolve.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SourceTiming {
+    /// Variable name, or `bundle:<name>` for a bundle.
+    pub name: String,
+    /// Kind of the source (see [`Source::kind`](crate::config::Source::kind)).
+    pub source: &'static str,
+    /// Time spent waiting for a worker after all dependencies resolved.
+    #[serde(rename = "queued_ms", serialize_with = "millis")]
+    pub queued: Duration,
+    /// Time spent resolving the source, including retries and cache lookups.
+    #[serde(rename = "run_ms", serialize_with = "millis")]
+    pub run: Duration,
+    pub ok: bool,
+}
+
+impl SourceTiming {
+    /// Queue wait plus run time.
+    #[must_use]
+    pub fn total(&self) -> Duration {
+        self.queued + self.run
+    }
+}
+
+fn millis<S: serde::Serializer>(d: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(d.as_secs_f64() * 1000.0)
+}
+
+/// Sort timings slowest first, ties by name.
+pub fn sort(timings: &mut [SourceTiming]) {
+    timings.sort_by(|a, b| b.total().cmp(&a.total()).then_with(|| a.name.cmp(&b.name)));
+}
+
+fn format_duration(d: Duration) -> String {
+    if d < Duration::from_secs(1) {
+        format!("{:.1}ms", d.as_secs_f64() * 1000.0)
+    } else {
+        format!("{:.2}s", d.as_secs_f64())
+    }
+}
+
+/// Render timings (already sorted) as an aligned table with a wall-clock
+/// total.
+#[must_use]
+pub fn table(timings: &[SourceTiming], wall: Duration) -> String {
+    let width = timings
+        .iter()
+        .map(|t| t.name.len())
+        .chain(["VARIABLE".len()])
+        .max()
+        .unwrap_or_default();
+    let mut out = format!(
+        "{:<width$}  {:<11}  {:>9}  {:>9}  STATUS\n",
+        "VARIABLE", "SOURCE", "QUEUED", "RUN"
+    );
+    for t in timings {
+        let _ = writeln!(
+            out,
+            "{:<width$}  {:<11}  {:>9}  {:>9}  {}",
+            t.name,
+            t.source,
+            format_duration(t.queued),
+            format_duration(t.run),
+            if t.ok { "ok" } else { "failed" },
+        );
+    }
+    let _ = writeln!(
+        out,
+        "{} source(s) in {} wall time",
+        timings.len(),
+        format_duration(wall)
+    );
+    out
+}
+
+/// JSON document written by `--timings-json`.
+#[derive(serde::Serialize)]
+pub struct Report<'a> {
+    pub environment: &'a str,
+    #[serde(rename = "wall_ms", serialize_with = "millis")]
+    pub wall: Duration,
+    pub sources: &'a [SourceTiming],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timing(name: &str, queued_ms: u64, run_ms: u64) -> SourceTiming {
+        SourceTiming {
+            name: name.to_owned(),
+            source: "cmd",
+            queued: Duration::from_millis(queued_ms),
+            run: Duration::from_millis(run_ms),
+            ok: true,
+        }
+    }
+
+    #[test]
+    fn test_sort_slowest_first() {
+        let mut timings = vec![timing("A", 0, 5), timing("B", 10, 20), timing("C", 0, 30)];
+        sort(&mut timings);
+        let names: Vec<&str> = timings.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["B", "C", "A"]);
+    }
+
+    #[test]
+    fn test_table_and_json() {
+        let timings = vec![timing("SLOW_TOKEN", 250, 1500)];
+        let out = table(&timings, Duration::from_millis(1800));
+        assert!(out.starts_with("VARIABLE    SOURCE"));
+        assert!(out.contains("SLOW_TOKEN  cmd            250.0ms      1.50s  ok\n"));
+        assert!(out.ends_with("1 source(s) in 1.80s wall time\n"));
+
+        let report = Report {
+            environment: "ci",
+            wall: Duration::from_millis(1800),
+            sources: &timings,
+        };
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["wall_ms"], 1800.0);
+        assert_eq!(json["sources"][0]["queued_ms"], 250.0);
+        assert_eq!(json["sources"][0]["run_ms"], 1500.0);
+        assert_eq!(json["sources"][0]["source"], "cmd");
+    }
+}